@@ -0,0 +1,13 @@
+-- Every relationship change, as recorded since before migrations were added. Existing
+-- databases already have this table.
+CREATE TABLE IF NOT EXISTS events (
+    -- Millisecond Unix timestamp.
+    timestamp BIGINT UNSIGNED NOT NULL,
+    guild BIGINT UNSIGNED NOT NULL,
+    channel BIGINT UNSIGNED NOT NULL,
+    source BIGINT UNSIGNED NOT NULL,
+    target BIGINT UNSIGNED NOT NULL,
+    -- A `RelationshipChangeReason`.
+    reason TINYINT UNSIGNED NOT NULL,
+    INDEX (guild, timestamp)
+);
@@ -0,0 +1,11 @@
+-- The in-memory graphs, so a restart doesn't have to start them from scratch.
+CREATE TABLE IF NOT EXISTS graph_edges (
+    guild BIGINT UNSIGNED NOT NULL,
+    channel BIGINT UNSIGNED NOT NULL,
+    source BIGINT UNSIGNED NOT NULL,
+    target BIGINT UNSIGNED NOT NULL,
+    weight FLOAT NOT NULL,
+    -- Millisecond Unix timestamp, like the events table.
+    last_updated BIGINT UNSIGNED NOT NULL,
+    PRIMARY KEY (guild, channel, source, target)
+);
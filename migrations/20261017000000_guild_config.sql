@@ -0,0 +1,7 @@
+-- Per-guild settings, missing rows and NULL columns use the defaults.
+CREATE TABLE IF NOT EXISTS guild_config (
+    guild BIGINT UNSIGNED NOT NULL,
+    dot_node_template TEXT NULL,
+    dot_edge_template TEXT NULL,
+    PRIMARY KEY (guild)
+);
@@ -0,0 +1,3 @@
+-- How much each change was worth when it was recorded, after any cooldown or penalty.
+-- Older rows are NULL, and fall back to their reason's default weight.
+ALTER TABLE events ADD COLUMN weight FLOAT NULL;
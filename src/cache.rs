@@ -1,30 +1,46 @@
 use anyhow::{Context, Result};
+use futures::future::join_all;
+use futures::stream::{self, StreamExt};
 use lru::LruCache;
-use parking_lot::Mutex;
-use tracing::{debug, info};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+use twilight_http::error::ErrorType;
 use twilight_http::Client;
-use twilight_model::channel::message::{Mention, MessageType};
+use twilight_model::channel::message::{Mention, MessageType, ReactionType};
+use twilight_model::channel::permission_overwrite::PermissionOverwriteType;
 use twilight_model::channel::{Channel, ChannelType, Message};
 use twilight_model::gateway::event::Event;
 use twilight_model::gateway::payload::incoming::{MemberUpdate, MessageUpdate};
+use twilight_model::gateway::GatewayReaction;
 use twilight_model::guild::{Guild, Member, PartialGuild, PartialMember, Permissions, Role};
 use twilight_model::id::marker::{
     ChannelMarker, GuildMarker, MessageMarker, RoleMarker, UserMarker,
 };
 use twilight_model::id::Id;
-use twilight_model::user::User;
+use twilight_model::user::{CurrentUser, User};
 use twilight_model::util::ImageHash;
 
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::num::NonZeroUsize;
 use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::RwLock as AsyncRwLock;
+use tokio::sync::{mpsc, oneshot};
 
-#[derive(Debug, Clone)]
+/// Only the ID and name are serialized, for graph exports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[allow(dead_code)]
 pub struct CachedUser {
     pub id: Id<UserMarker>,
     pub name: String,
     pub discriminator: u16,
+    #[serde(skip)]
     pub avatar: Option<ImageHash>,
+    #[serde(skip)]
     pub bot: bool,
 }
 
@@ -40,6 +56,18 @@ impl From<&User> for CachedUser {
     }
 }
 
+impl From<&CurrentUser> for CachedUser {
+    fn from(user: &CurrentUser) -> Self {
+        CachedUser {
+            id: user.id,
+            name: user.name.clone(),
+            discriminator: user.discriminator,
+            avatar: user.avatar,
+            bot: user.bot,
+        }
+    }
+}
+
 impl From<&Mention> for CachedUser {
     fn from(mention: &Mention) -> Self {
         CachedUser {
@@ -53,12 +81,15 @@ impl From<&Mention> for CachedUser {
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct CachedGuild {
     pub id: Id<GuildMarker>,
     pub name: String,
     pub icon: Option<ImageHash>,
     pub roles: Vec<Id<RoleMarker>>,
     pub owner_id: Id<UserMarker>,
+    /// Only sent with `GuildCreate`, and not kept up to date as members join or leave.
+    pub member_count: Option<u64>,
 }
 
 impl From<&PartialGuild> for CachedGuild {
@@ -69,6 +100,7 @@ impl From<&PartialGuild> for CachedGuild {
             icon: guild.icon,
             roles: guild.roles.iter().map(|role| role.id).collect(),
             owner_id: guild.owner_id,
+            member_count: None,
         }
     }
 }
@@ -81,11 +113,13 @@ impl From<&Guild> for CachedGuild {
             icon: guild.icon,
             roles: guild.roles.iter().map(|role| role.id).collect(),
             owner_id: guild.owner_id,
+            member_count: guild.member_count,
         }
     }
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct CachedRole {
     pub id: Id<RoleMarker>,
     pub name: String,
@@ -112,6 +146,18 @@ pub struct CachedMember {
     pub roles: Vec<Id<RoleMarker>>,
 }
 
+impl CachedMember {
+    /// The color of the member's highest positioned role that has one, or `0` if none do.
+    pub fn highest_role_color(&self, role_cache: &HashMap<Id<RoleMarker>, CachedRole>) -> u32 {
+        self.roles
+            .iter()
+            .filter_map(|role_id| role_cache.get(role_id))
+            .filter(|role| role.color != 0)
+            .max_by_key(|role| role.position)
+            .map_or(0, |role| role.color)
+    }
+}
+
 impl From<&PartialMember> for CachedMember {
     fn from(member: &PartialMember) -> Self {
         CachedMember {
@@ -140,29 +186,64 @@ impl From<&MemberUpdate> for CachedMember {
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct CachedChannel {
     pub id: Id<ChannelMarker>,
+    /// Missing for DMs, and for channels we've never seen outside of a `GuildCreate`.
+    pub guild_id: Option<Id<GuildMarker>>,
     pub name: String,
     pub kind: ChannelType,
+    /// For threads, the channel they were started in.
+    pub parent_id: Option<Id<ChannelMarker>>,
+    /// Members given thread management permissions on the channel itself, used as the
+    /// people responsible for a forum.
+    pub moderators: Vec<Id<UserMarker>>,
+    /// Roles given thread management permissions on the channel itself, see `moderators`.
+    pub moderator_roles: Vec<Id<RoleMarker>>,
 }
 
 impl From<&Channel> for CachedChannel {
     fn from(channel: &Channel) -> Self {
+        let moderator_overwrites = |kind| {
+            channel
+                .permission_overwrites
+                .iter()
+                .flatten()
+                .filter(move |overwrite| overwrite.kind == kind)
+                .filter(|overwrite| overwrite.allow.contains(Permissions::MANAGE_THREADS))
+                .map(|overwrite| overwrite.id)
+        };
+
+        let moderators = moderator_overwrites(PermissionOverwriteType::Member)
+            .map(|id| id.cast())
+            .collect();
+        let moderator_roles = moderator_overwrites(PermissionOverwriteType::Role)
+            .map(|id| id.cast())
+            .collect();
+
         CachedChannel {
             id: channel.id,
+            guild_id: channel.guild_id,
             name: channel.name.as_ref().map_or_else(
                 || format!("{:?}:{}", channel.kind, channel.id),
                 |name| name.clone(),
             ),
             kind: channel.kind,
+            parent_id: channel.parent_id,
+            moderators,
+            moderator_roles,
         }
     }
 }
 
 #[derive(Debug, Clone)]
+#[allow(dead_code)]
 pub struct CachedMessage {
     pub author_id: Id<UserMarker>,
     pub kind: MessageType,
+    /// Who has reacted with each emoji, keyed by `reaction_key`. Discord doesn't tell us this,
+    /// so it only has the reactions added while the message was cached.
+    pub reactors: HashMap<String, HashSet<Id<UserMarker>>>,
 }
 
 impl From<&Message> for CachedMessage {
@@ -170,37 +251,213 @@ impl From<&Message> for CachedMessage {
         CachedMessage {
             author_id: message.author.id,
             kind: message.kind,
+            reactors: HashMap::new(),
+        }
+    }
+}
+
+/// Identifies an emoji across reaction events, which don't always agree on a custom emoji's
+/// name or whether it's animated.
+pub fn reaction_key(emoji: &ReactionType) -> String {
+    match emoji {
+        ReactionType::Custom { id, .. } => id.to_string(),
+        ReactionType::Unicode { name } => name.clone(),
+    }
+}
+
+/// Hash a message's content, or `None` if it has none, like messages that are only attachments.
+/// The hashes are only comparable within the same run of the bot.
+pub fn hash_message_content(content: &str) -> Option<u64> {
+    if content.is_empty() {
+        return None;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+
+    Some(hasher.finish())
+}
+
+/// A difference between what's cached and what Discord says, found by `consistency_check`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CacheInconsistency {
+    StaleGuildName {
+        cached: String,
+        live: String,
+    },
+    /// The guild's list of roles has roles added or removed.
+    WrongGuildRoles {
+        missing: Vec<Id<RoleMarker>>,
+        removed: Vec<Id<RoleMarker>>,
+    },
+    /// A role's name, color, position or permissions changed.
+    StaleRole(Id<RoleMarker>),
+    StaleUserName {
+        user_id: Id<UserMarker>,
+        cached: String,
+        live: String,
+    },
+    StaleNick {
+        user_id: Id<UserMarker>,
+        cached: Option<String>,
+        live: Option<String>,
+    },
+    /// A cached member has since left the guild.
+    MissingMember(Id<UserMarker>),
+    WrongRoles(Id<UserMarker>),
+}
+
+impl fmt::Display for CacheInconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::StaleGuildName { cached, live } => {
+                write!(f, "guild name is {:?} but was cached as {:?}", live, cached)
+            }
+            Self::WrongGuildRoles { missing, removed } => write!(
+                f,
+                "guild roles are missing {:?} and still have removed {:?}",
+                missing, removed
+            ),
+            Self::StaleRole(role_id) => write!(f, "role {} has changed", role_id),
+            Self::StaleUserName {
+                user_id,
+                cached,
+                live,
+            } => write!(
+                f,
+                "user {} is named {:?} but was cached as {:?}",
+                user_id, live, cached
+            ),
+            Self::StaleNick {
+                user_id,
+                cached,
+                live,
+            } => write!(
+                f,
+                "member {} has nick {:?} but was cached with {:?}",
+                user_id, live, cached
+            ),
+            Self::MissingMember(user_id) => write!(f, "member {} has left the guild", user_id),
+            Self::WrongRoles(user_id) => write!(f, "member {} has different roles", user_id),
+        }
+    }
+}
+
+/// Everything cached about one guild, so leaving it drops the lot in one go.
+#[derive(Debug, Default)]
+struct GuildCache {
+    /// `None` until we've seen a `GuildCreate` or fetched it, even if other parts are cached.
+    guild: Option<CachedGuild>,
+    roles: HashMap<Id<RoleMarker>, CachedRole>,
+    members: HashMap<Id<UserMarker>, CachedMember>,
+    channels: HashMap<Id<ChannelMarker>, CachedChannel>,
+}
+
+impl GuildCache {
+    fn put_channel(&mut self, channel: CachedChannel) {
+        self.channels.insert(channel.id, channel);
+    }
+}
+
+/// Each guild's cache behind its own lock, so work on one guild never waits on another. The map
+/// itself is only locked long enough to find a guild's lock.
+#[derive(Default)]
+struct GuildCaches(Mutex<HashMap<Id<GuildMarker>, Arc<RwLock<GuildCache>>>>);
+
+impl GuildCaches {
+    fn get(&self, guild_id: Id<GuildMarker>) -> Option<Arc<RwLock<GuildCache>>> {
+        self.0.lock().get(&guild_id).cloned()
+    }
+
+    fn get_or_default(&self, guild_id: Id<GuildMarker>) -> Arc<RwLock<GuildCache>> {
+        self.0.lock().entry(guild_id).or_default().clone()
+    }
+
+    fn remove(&self, guild_id: Id<GuildMarker>) -> Option<Arc<RwLock<GuildCache>>> {
+        self.0.lock().remove(&guild_id)
+    }
+
+    fn all(&self) -> Vec<(Id<GuildMarker>, Arc<RwLock<GuildCache>>)> {
+        self.0
+            .lock()
+            .iter()
+            .map(|(&guild_id, guild_cache)| (guild_id, guild_cache.clone()))
+            .collect()
+    }
+}
+
+/// Doesn't wait for any of the locks, so guilds being written are printed as `<locked>`.
+impl fmt::Debug for GuildCaches {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut m = f.debug_map();
+        for (guild_id, guild_cache) in self.all() {
+            match guild_cache.try_read() {
+                Some(guild_cache) => m.entry(&guild_id, &format_args!("{:?}", *guild_cache)),
+                None => m.entry(&guild_id, &format_args!("<locked>")),
+            };
         }
+        m.finish()
     }
 }
 
-// TODO: I don't think the rest of these should be LRU other than messages, as we need them for
-//       all active objects. Investigate more once we have the GraphMap implemented.
-//       A bonus of non-LRU maps here would be the ability to use RwLock.
-// TODO: Rewrite this to be partitioned per-guild.
-#[allow(clippy::type_complexity)]
+/// Only messages are LRU, as they're the only thing that grows without bound. Everything else is
+/// limited to what Discord tells us about the guilds we're in.
 pub struct Cache {
     http: Arc<Client>,
-    users: Mutex<LruCache<Id<UserMarker>, CachedUser>>,
-    guilds: Mutex<LruCache<Id<GuildMarker>, CachedGuild>>,
-    roles: Mutex<LruCache<Id<RoleMarker>, CachedRole>>,
-    members: Mutex<LruCache<(Id<GuildMarker>, Id<UserMarker>), CachedMember>>,
-    channels: Mutex<LruCache<Id<ChannelMarker>, CachedChannel>>,
+    /// Not partitioned by guild, as users are shared between guilds and looked up without one.
+    users: RwLock<HashMap<Id<UserMarker>, CachedUser>>,
+    guilds: GuildCaches,
+    /// Which guild each cached channel is in, as channels are looked up by ID alone.
+    /// Always locked after a guild's cache when both are needed.
+    channel_guilds: RwLock<HashMap<Id<ChannelMarker>, Id<GuildMarker>>>,
     /// Used to lookup the author of messages being reacted to.
-    messages: Mutex<LruCache<Id<MessageMarker>, CachedMessage>>,
+    /// Read on every reaction, so this uses an async RwLock rather than blocking the runtime.
+    /// Only ever written by the task fed by `message_writes`.
+    messages: Arc<AsyncRwLock<MessageCache>>,
+    /// Queued writes to `messages`, applied in order by a single task so the event loop never
+    /// waits on the lock. This includes refreshing the recency of looked up messages.
+    message_writes: mpsc::Sender<MessageWrite>,
+}
+
+/// How many message cache writes can be waiting before events have to wait for the writer.
+const MESSAGE_WRITE_QUEUE_SIZE: usize = 1024;
+
+/// How many members `consistency_check` fetches at once, so big guilds don't flood the API.
+const CONSISTENCY_CHECK_CONCURRENCY: usize = 10;
+
+type MessageCache = LruCache<Id<MessageMarker>, CachedMessage>;
+type MessageWrite = Box<dyn FnOnce(&mut MessageCache) + Send>;
+
+/// A newtype to print a locked map without propagating the alternate rendering mode.
+struct PrintableMap<'a, K, V>(&'a RwLock<HashMap<K, V>>);
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for PrintableMap<'_, K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut m = f.debug_map();
+        for (k, v) in self.0.read().iter() {
+            // Manually use format_args! to not propagate the alternate rendering mode
+            // so we get a more compat representation due to the size of these maps.
+            m.entry(&format_args!("{:?}", k), &format_args!("{:?}", v));
+        }
+        m.finish()
+    }
 }
 
 /// A newtype to wrap LruCache, as LruCache's Debug impl doesn't print the container contents.
-struct PrintableLruCache<'a, K, V>(&'a Mutex<LruCache<K, V>>);
+/// Doesn't wait for the lock.
+struct PrintableRwLockLruCache<'a, K, V>(&'a AsyncRwLock<LruCache<K, V>>);
 
 impl<K: std::cmp::Eq + std::hash::Hash + fmt::Debug, V: fmt::Debug> fmt::Debug
-    for PrintableLruCache<'_, K, V>
+    for PrintableRwLockLruCache<'_, K, V>
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cache = match self.0.try_read() {
+            Ok(cache) => cache,
+            Err(_) => return f.write_str("<locked>"),
+        };
+
         let mut m = f.debug_map();
-        for (k, v) in self.0.lock().iter() {
-            // Manually use format_args! to not propagate the alternate rendering mode
-            // so we get a more compat representation due to the size of these maps.
+        for (k, v) in cache.iter() {
             m.entry(&format_args!("{:?}", k), &format_args!("{:?}", v));
         }
         m.finish()
@@ -210,60 +467,158 @@ impl<K: std::cmp::Eq + std::hash::Hash + fmt::Debug, V: fmt::Debug> fmt::Debug
 impl fmt::Debug for Cache {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("Cache")
-            .field("users", &PrintableLruCache(&self.users))
-            .field("guilds", &PrintableLruCache(&self.guilds))
-            .field("roles", &PrintableLruCache(&self.roles))
-            .field("members", &PrintableLruCache(&self.members))
-            .field("channels", &PrintableLruCache(&self.channels))
-            .field("messages", &PrintableLruCache(&self.messages))
+            .field("users", &PrintableMap(&self.users))
+            .field("guilds", &self.guilds)
+            .field("messages", &PrintableRwLockLruCache(&self.messages))
             .finish()
     }
 }
 
+/// How many of each LRU-cached type to keep. Only messages are LRU, everything else is limited
+/// to the guilds we're in and doesn't need one.
 #[derive(Debug, Copy, Clone)]
-#[allow(dead_code)]
+pub struct CacheConfig {
+    pub messages: NonZeroUsize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            messages: NonZeroUsize::new(5000).unwrap(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Serialize)]
+pub struct GuildCacheStats {
+    pub roles: usize,
+    pub members: usize,
+    pub channels: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct CacheStats {
-    users: usize,
-    guilds: usize,
-    roles: usize,
-    members: usize,
-    channels: usize,
-    messages: usize,
+    pub users: usize,
+    pub guilds: usize,
+    pub messages: usize,
+    pub messages_capacity: usize,
+    pub per_guild: HashMap<Id<GuildMarker>, GuildCacheStats>,
 }
 
 // The `get_*` functions in here release the lock while processing in order to support async in
-// the future, and a potential switch to RwLock if we move away from LruCache.
+// the future.
 impl Cache {
-    pub fn new(http: Arc<Client>) -> Self {
-        // TODO: Tune these cache sizes.
-        let cache_limit = NonZeroUsize::new(5000).unwrap();
+    /// The message cache's writer is spawned on `runtime`, and runs until the cache is dropped.
+    pub fn new(http: Arc<Client>, config: CacheConfig, runtime: &Handle) -> Self {
+        let messages = Arc::new(AsyncRwLock::new(LruCache::new(config.messages)));
+
+        let (message_writes, mut queued_writes) =
+            mpsc::channel::<MessageWrite>(MESSAGE_WRITE_QUEUE_SIZE);
+        let writer_messages = messages.clone();
+        runtime.spawn(async move {
+            while let Some(write) = queued_writes.recv().await {
+                write(&mut *writer_messages.write().await);
+            }
+        });
 
         Cache {
             http,
-            users: Mutex::new(LruCache::new(cache_limit)),
-            guilds: Mutex::new(LruCache::new(cache_limit)),
-            roles: Mutex::new(LruCache::new(cache_limit)),
-            members: Mutex::new(LruCache::new(cache_limit)),
-            channels: Mutex::new(LruCache::new(cache_limit)),
-            messages: Mutex::new(LruCache::new(cache_limit)),
+            users: RwLock::new(HashMap::new()),
+            guilds: GuildCaches::default(),
+            channel_guilds: RwLock::new(HashMap::new()),
+            messages,
+            message_writes,
+        }
+    }
+
+    /// Queue a write to the message cache, waiting for room if the queue is full.
+    /// It's applied after every write queued before it.
+    async fn write_messages(&self, write: impl FnOnce(&mut MessageCache) + Send + 'static) {
+        if self.message_writes.send(Box::new(write)).await.is_err() {
+            error!("message cache writer has stopped, dropping write");
         }
     }
 
-    pub fn get_stats(&self) -> CacheStats {
+    pub async fn get_stats(&self) -> CacheStats {
+        let messages = self.messages.read().await;
+        self.get_stats_with_messages(&messages)
+    }
+
+    fn get_stats_with_messages(&self, messages: &MessageCache) -> CacheStats {
+        let (messages, messages_capacity) = (messages.len(), messages.cap().get());
+
+        let mut guilds = 0;
+        let per_guild = self
+            .guilds
+            .all()
+            .into_iter()
+            .map(|(guild_id, guild_cache)| {
+                let guild_cache = guild_cache.read();
+                if guild_cache.guild.is_some() {
+                    guilds += 1;
+                }
+
+                let stats = GuildCacheStats {
+                    roles: guild_cache.roles.len(),
+                    members: guild_cache.members.len(),
+                    channels: guild_cache.channels.len(),
+                };
+
+                (guild_id, stats)
+            })
+            .collect();
+
         CacheStats {
-            users: self.users.lock().len(),
-            guilds: self.guilds.lock().len(),
-            roles: self.roles.lock().len(),
-            members: self.members.lock().len(),
-            channels: self.channels.lock().len(),
-            messages: self.messages.lock().len(),
+            users: self.users.read().len(),
+            guilds,
+            messages,
+            messages_capacity,
+            per_guild,
+        }
+    }
+
+    /// Forget everything cached for a guild, like when we're removed from it.
+    /// Users are kept, as they may share other guilds with us.
+    pub fn remove_guild(&self, guild_id: Id<GuildMarker>) {
+        let guild_cache = match self.guilds.remove(guild_id) {
+            Some(guild_cache) => guild_cache,
+            None => return,
+        };
+
+        let guild_cache = guild_cache.read();
+        let mut channel_guilds = self.channel_guilds.write();
+        for channel_id in guild_cache.channels.keys() {
+            channel_guilds.remove(channel_id);
         }
     }
 
-    pub fn update(&self, event: &Event) {
+    /// Run `f` on the guild's cache, or return `None` if nothing is cached for the guild.
+    fn with_guild<T>(
+        &self,
+        guild_id: Id<GuildMarker>,
+        f: impl FnOnce(&GuildCache) -> T,
+    ) -> Option<T> {
+        let guild_cache = self.guilds.get(guild_id)?;
+        let guild_cache = guild_cache.read();
+        Some(f(&guild_cache))
+    }
+
+    /// Run `f` on the guild's cache, creating an empty one if there isn't one yet.
+    fn with_guild_mut<T>(
+        &self,
+        guild_id: Id<GuildMarker>,
+        f: impl FnOnce(&mut GuildCache) -> T,
+    ) -> T {
+        let guild_cache = self.guilds.get_or_default(guild_id);
+        let mut guild_cache = guild_cache.write();
+        f(&mut guild_cache)
+    }
+
+    pub async fn update(&self, event: &Event) {
         match event {
             Event::ChannelCreate(channel) => self.put_channel(channel),
             Event::ChannelUpdate(channel) => self.put_channel(channel),
+            Event::ThreadCreate(thread) => self.put_channel(thread),
             Event::GuildCreate(guild) => self.put_full_guild(guild),
             Event::GuildUpdate(guild) => self.put_guild(guild),
             Event::MemberAdd(member) => self.put_full_member(member.guild_id, member),
@@ -273,34 +628,48 @@ impl Cache {
                     self.put_full_member(chunk.guild_id, member)
                 }
             }
-            Event::MessageCreate(message) => self.put_message(message),
-            Event::MessageUpdate(message) => self.put_message_update(message),
+            Event::MessageCreate(message) => self.put_message(message).await,
+            Event::MessageUpdate(message) => self.put_message_update(message).await,
             Event::ReactionAdd(reaction) => {
                 if let (Some(guild_id), Some(member)) = (reaction.guild_id, &reaction.member) {
                     self.put_full_member(guild_id, member);
                 }
+
+                self.put_reaction(reaction).await;
             }
-            Event::RoleCreate(role) => self.put_role(&role.role),
-            Event::RoleUpdate(role) => self.put_role(&role.role),
+            Event::ReactionRemove(reaction) => self.remove_reaction(reaction).await,
+            Event::RoleCreate(role) => self.put_role(role.guild_id, &role.role),
+            Event::RoleUpdate(role) => self.put_role(role.guild_id, &role.role),
+            // Discord only sends this for our own user, everyone else's changes arrive as
+            // `MemberUpdate`s, which replace the cached user too.
+            Event::UserUpdate(user) => self.put_current_user(user),
             _ => info!("event not used by cache: {:?}", event.kind()),
         }
 
-        debug!("cache stats: {:?}", self.get_stats());
+        // Skipped rather than waiting if the message writer has the lock.
+        if let Ok(messages) = self.messages.try_read() {
+            debug!("cache stats: {:?}", self.get_stats_with_messages(&messages));
+        }
     }
 
     fn put_user(&self, user: &User) {
-        let mut cache = self.users.lock();
-        cache.put(user.id, CachedUser::from(user));
+        let mut cache = self.users.write();
+        cache.insert(user.id, CachedUser::from(user));
+    }
+
+    fn put_current_user(&self, user: &CurrentUser) {
+        let mut cache = self.users.write();
+        cache.insert(user.id, CachedUser::from(user));
     }
 
     fn put_user_mention(&self, mention: &Mention) {
-        let mut cache = self.users.lock();
-        cache.put(mention.id, CachedUser::from(mention));
+        let mut cache = self.users.write();
+        cache.insert(mention.id, CachedUser::from(mention));
     }
 
     pub async fn get_user(&self, user_id: Id<UserMarker>) -> Result<CachedUser> {
         let cached_user = {
-            let mut cache = self.users.lock();
+            let cache = self.users.read();
             cache.get(&user_id).cloned()
         };
 
@@ -318,33 +687,75 @@ impl Cache {
         }
     }
 
-    fn put_guild(&self, guild: &PartialGuild) {
-        for role in &guild.roles {
-            self.put_role(role);
-        }
+    /// Look up many users at once, checking the cache under a single lock and then fetching
+    /// any that are missing concurrently. Users that can't be fetched are left out.
+    pub async fn get_or_fetch_users(&self, user_ids: &[Id<UserMarker>]) -> Vec<CachedUser> {
+        let (mut found, missing) = {
+            let cache = self.users.read();
+
+            let mut found = HashMap::new();
+            let mut missing = Vec::new();
+            for &user_id in user_ids {
+                match cache.get(&user_id) {
+                    Some(cached_user) => {
+                        found.insert(user_id, cached_user.clone());
+                    }
+                    None => missing.push(user_id),
+                }
+            }
 
-        let mut cache = self.guilds.lock();
-        cache.put(guild.id, CachedGuild::from(guild));
-    }
+            (found, missing)
+        };
 
-    fn put_full_guild(&self, guild: &Guild) {
-        for channel in &guild.channels {
-            self.put_channel(channel);
+        if !missing.is_empty() {
+            info!("{} users not in cache, fetching", missing.len());
         }
 
-        for role in &guild.roles {
-            self.put_role(role);
+        let fetched = join_all(missing.into_iter().map(|user_id| async move {
+            let user = async { Result::<_>::Ok(self.http.user(user_id).await?.model().await?) };
+
+            (user_id, user.await)
+        }))
+        .await;
+
+        for (user_id, user) in fetched {
+            match user {
+                Ok(user) => {
+                    self.put_user(&user);
+                    found.insert(user_id, CachedUser::from(&user));
+                }
+                Err(error) => warn!("failed to fetch user {}: {}", user_id, error),
+            }
         }
 
-        let mut cache = self.guilds.lock();
-        cache.put(guild.id, CachedGuild::from(guild));
+        user_ids
+            .iter()
+            .filter_map(|user_id| found.remove(user_id))
+            .collect()
+    }
+
+    fn put_guild(&self, guild: &PartialGuild) {
+        self.put_all_guild_roles(guild.id, &guild.roles);
+
+        self.with_guild_mut(guild.id, |guild_cache| {
+            guild_cache.guild = Some(CachedGuild::from(guild));
+        });
+    }
+
+    fn put_full_guild(&self, guild: &Guild) {
+        self.put_all_channels(guild.id, &guild.channels);
+        self.put_all_channels(guild.id, &guild.threads);
+        self.put_all_guild_roles(guild.id, &guild.roles);
+
+        self.with_guild_mut(guild.id, |guild_cache| {
+            guild_cache.guild = Some(CachedGuild::from(guild));
+        });
     }
 
     pub async fn get_guild(&self, guild_id: Id<GuildMarker>) -> Result<CachedGuild> {
-        let cached_guild = {
-            let mut cache = self.guilds.lock();
-            cache.get(&guild_id).cloned()
-        };
+        let cached_guild = self
+            .with_guild(guild_id, |guild_cache| guild_cache.guild.clone())
+            .flatten();
 
         match cached_guild {
             Some(cached_guild) => Ok(cached_guild),
@@ -360,9 +771,19 @@ impl Cache {
         }
     }
 
-    fn put_role(&self, role: &Role) {
-        let mut cache = self.roles.lock();
-        cache.put(role.id, CachedRole::from(role));
+    fn put_role(&self, guild_id: Id<GuildMarker>, role: &Role) {
+        self.with_guild_mut(guild_id, |guild_cache| {
+            guild_cache.roles.insert(role.id, CachedRole::from(role));
+        });
+    }
+
+    /// Like `put_role` for each role, but only taking the lock once.
+    fn put_all_guild_roles(&self, guild_id: Id<GuildMarker>, roles: &[Role]) {
+        self.with_guild_mut(guild_id, |guild_cache| {
+            for role in roles {
+                guild_cache.roles.insert(role.id, CachedRole::from(role));
+            }
+        });
     }
 
     pub async fn get_role(
@@ -370,10 +791,11 @@ impl Cache {
         guild_id: Id<GuildMarker>,
         role_id: Id<RoleMarker>,
     ) -> Result<CachedRole> {
-        let cached_role = {
-            let mut cache = self.roles.lock();
-            cache.get(&role_id).cloned()
-        };
+        let cached_role = self
+            .with_guild(guild_id, |guild_cache| {
+                guild_cache.roles.get(&role_id).cloned()
+            })
+            .flatten();
 
         match cached_role {
             Some(cached_role) => Ok(cached_role),
@@ -382,9 +804,7 @@ impl Cache {
 
                 let roles = self.http.roles(guild_id).await?.model().await?;
 
-                for role in &roles {
-                    self.put_role(role);
-                }
+                self.put_all_guild_roles(guild_id, &roles);
 
                 let role = roles
                     .iter()
@@ -402,25 +822,31 @@ impl Cache {
         user_id: Id<UserMarker>,
         member: &PartialMember,
     ) {
-        let mut cache = self.members.lock();
-        cache.put((guild_id, user_id), CachedMember::from(member));
+        self.with_guild_mut(guild_id, |guild_cache| {
+            guild_cache
+                .members
+                .insert(user_id, CachedMember::from(member));
+        });
     }
 
     fn put_full_member(&self, guild_id: Id<GuildMarker>, member: &Member) {
         self.put_user(&member.user);
 
-        let mut cache = self.members.lock();
-        cache.put((guild_id, member.user.id), CachedMember::from(member));
+        self.with_guild_mut(guild_id, |guild_cache| {
+            guild_cache
+                .members
+                .insert(member.user.id, CachedMember::from(member));
+        });
     }
 
     fn put_member_update(&self, member: &MemberUpdate) {
         self.put_user(&member.user);
 
-        let mut cache = self.members.lock();
-        cache.put(
-            (member.guild_id, member.user.id),
-            CachedMember::from(member),
-        );
+        self.with_guild_mut(member.guild_id, |guild_cache| {
+            guild_cache
+                .members
+                .insert(member.user.id, CachedMember::from(member));
+        });
     }
 
     pub async fn get_member(
@@ -428,10 +854,11 @@ impl Cache {
         guild_id: Id<GuildMarker>,
         user_id: Id<UserMarker>,
     ) -> Result<CachedMember> {
-        let cached_member = {
-            let mut cache = self.members.lock();
-            cache.get(&(guild_id, user_id)).cloned()
-        };
+        let cached_member = self
+            .with_guild(guild_id, |guild_cache| {
+                guild_cache.members.get(&user_id).cloned()
+            })
+            .flatten();
 
         match cached_member {
             Some(cached_member) => Ok(cached_member),
@@ -455,16 +882,305 @@ impl Cache {
         }
     }
 
+    /// Look up many of a guild's members at once, like `get_or_fetch_users`. Discord's HTTP API
+    /// can't fetch a list of specific members in one request, so missing members are fetched
+    /// concurrently instead. Members that can't be fetched are left out.
+    pub async fn get_or_fetch_members(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_ids: &[Id<UserMarker>],
+    ) -> HashMap<Id<UserMarker>, CachedMember> {
+        let (mut found, missing) = {
+            let guild_cache = self.guilds.get(guild_id);
+            let guild_cache = guild_cache.as_ref().map(|guild_cache| guild_cache.read());
+            let members = guild_cache.as_ref().map(|guild_cache| &guild_cache.members);
+
+            let mut found = HashMap::new();
+            let mut missing = Vec::new();
+            for &user_id in user_ids {
+                match members.and_then(|members| members.get(&user_id)) {
+                    Some(cached_member) => {
+                        found.insert(user_id, cached_member.clone());
+                    }
+                    None => missing.push(user_id),
+                }
+            }
+
+            (found, missing)
+        };
+
+        if !missing.is_empty() {
+            info!(
+                "{} members for guild {} not in cache, fetching",
+                missing.len(),
+                guild_id
+            );
+        }
+
+        let fetched = join_all(missing.into_iter().map(|user_id| async move {
+            let member = async {
+                Result::<_>::Ok(
+                    self.http
+                        .guild_member(guild_id, user_id)
+                        .await?
+                        .model()
+                        .await?,
+                )
+            };
+
+            (user_id, member.await)
+        }))
+        .await;
+
+        for (user_id, member) in fetched {
+            match member {
+                Ok(member) => {
+                    self.put_full_member(guild_id, &member);
+                    found.insert(user_id, CachedMember::from(&member));
+                }
+                Err(error) => warn!(
+                    "failed to fetch member {} for guild {}: {}",
+                    user_id, guild_id, error
+                ),
+            }
+        }
+
+        found
+    }
+
+    /// Fetch a member, or `None` if they've left the guild.
+    async fn fetch_member_if_present(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Result<Option<Member>> {
+        let response = match self.http.guild_member(guild_id, user_id).await {
+            Ok(response) => response,
+            Err(error) => match error.kind() {
+                ErrorType::Response { status, .. } if status.get() == 404 => return Ok(None),
+                _ => return Err(error.into()),
+            },
+        };
+
+        Ok(Some(response.model().await?))
+    }
+
+    /// Compare everything cached for a guild with what Discord currently says, without updating
+    /// the cache. This fetches every cached member, `CONSISTENCY_CHECK_CONCURRENCY` at a time,
+    /// so it's only run when asked for.
+    pub async fn consistency_check(
+        &self,
+        guild_id: Id<GuildMarker>,
+    ) -> Result<Vec<CacheInconsistency>> {
+        let mut inconsistencies = Vec::new();
+
+        let live_guild = self.http.guild(guild_id).await?.model().await?;
+        let cached_guild = self
+            .with_guild(guild_id, |guild_cache| guild_cache.guild.clone())
+            .flatten();
+
+        if let Some(cached_guild) = cached_guild {
+            if cached_guild.name != live_guild.name {
+                inconsistencies.push(CacheInconsistency::StaleGuildName {
+                    cached: cached_guild.name.clone(),
+                    live: live_guild.name.clone(),
+                });
+            }
+
+            let live_role_ids: Vec<_> = live_guild.roles.iter().map(|role| role.id).collect();
+            let missing: Vec<_> = live_role_ids
+                .iter()
+                .copied()
+                .filter(|role_id| !cached_guild.roles.contains(role_id))
+                .collect();
+            let removed: Vec<_> = cached_guild
+                .roles
+                .iter()
+                .copied()
+                .filter(|role_id| !live_role_ids.contains(role_id))
+                .collect();
+
+            if !missing.is_empty() || !removed.is_empty() {
+                inconsistencies.push(CacheInconsistency::WrongGuildRoles { missing, removed });
+            }
+        }
+
+        if let Some(guild_cache) = self.guilds.get(guild_id) {
+            let guild_cache = guild_cache.read();
+            let roles = &guild_cache.roles;
+
+            for role in &live_guild.roles {
+                let stale = match roles.get(&role.id) {
+                    Some(cached_role) => {
+                        cached_role.name != role.name
+                            || cached_role.color != role.color
+                            || cached_role.position != role.position
+                            || cached_role.permissions != role.permissions
+                    }
+                    None => false,
+                };
+
+                if stale {
+                    inconsistencies.push(CacheInconsistency::StaleRole(role.id));
+                }
+            }
+        }
+
+        let cached_members = self.get_all_members_for_guild(guild_id);
+
+        let user_ids: Vec<_> = cached_members.iter().map(|&(user_id, _)| user_id).collect();
+        let live_members: Vec<_> = stream::iter(user_ids)
+            .map(|user_id| self.fetch_member_if_present(guild_id, user_id))
+            .buffered(CONSISTENCY_CHECK_CONCURRENCY)
+            .collect()
+            .await;
+
+        for ((user_id, cached_member), live_member) in cached_members.iter().zip(live_members) {
+            let live_member = match live_member {
+                Ok(Some(live_member)) => live_member,
+                Ok(None) => {
+                    inconsistencies.push(CacheInconsistency::MissingMember(*user_id));
+                    continue;
+                }
+                Err(error) => {
+                    warn!(
+                        "failed to fetch member {} for guild {}: {}",
+                        user_id, guild_id, error
+                    );
+                    continue;
+                }
+            };
+
+            let cached_name = {
+                let cache = self.users.read();
+                cache.get(user_id).map(|user| user.name.clone())
+            };
+
+            match cached_name {
+                Some(cached_name) if cached_name != live_member.user.name => {
+                    inconsistencies.push(CacheInconsistency::StaleUserName {
+                        user_id: *user_id,
+                        cached: cached_name,
+                        live: live_member.user.name.clone(),
+                    })
+                }
+                _ => (),
+            }
+
+            if cached_member.nick != live_member.nick {
+                inconsistencies.push(CacheInconsistency::StaleNick {
+                    user_id: *user_id,
+                    cached: cached_member.nick.clone(),
+                    live: live_member.nick.clone(),
+                });
+            }
+
+            let mut cached_roles = cached_member.roles.clone();
+            let mut live_roles = live_member.roles.clone();
+            cached_roles.sort();
+            live_roles.sort();
+
+            if cached_roles != live_roles {
+                inconsistencies.push(CacheInconsistency::WrongRoles(*user_id));
+            }
+        }
+
+        Ok(inconsistencies)
+    }
+
+    /// Every member of the guild that's currently cached, without fetching any others.
+    pub fn get_all_members_for_guild(
+        &self,
+        guild_id: Id<GuildMarker>,
+    ) -> Vec<(Id<UserMarker>, CachedMember)> {
+        self.with_guild(guild_id, |guild_cache| {
+            guild_cache
+                .members
+                .iter()
+                .map(|(&user_id, member)| (user_id, member.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Everyone given thread management permissions on the channel, either directly or through
+    /// a role. Role holders are only found among the cached members, without fetching any others.
+    pub fn get_channel_moderators(
+        &self,
+        guild_id: Id<GuildMarker>,
+        channel: &CachedChannel,
+    ) -> Vec<Id<UserMarker>> {
+        let mut moderators: HashSet<_> = channel.moderators.iter().copied().collect();
+
+        if !channel.moderator_roles.is_empty() {
+            if let Some(guild_cache) = self.guilds.get(guild_id) {
+                let guild_cache = guild_cache.read();
+                let role_holders = guild_cache
+                    .members
+                    .iter()
+                    .filter(|(_, member)| {
+                        member
+                            .roles
+                            .iter()
+                            .any(|role_id| channel.moderator_roles.contains(role_id))
+                    })
+                    .map(|(&user_id, _)| user_id);
+
+                moderators.extend(role_holders);
+            }
+        }
+
+        let mut moderators: Vec<_> = moderators.into_iter().collect();
+        moderators.sort();
+        moderators
+    }
+
+    /// A cached channel in the guild with the name, ignoring case. Never fetches.
+    pub fn find_channel_by_name(
+        &self,
+        guild_id: Id<GuildMarker>,
+        name: &str,
+    ) -> Option<CachedChannel> {
+        self.with_guild(guild_id, |guild_cache| {
+            guild_cache
+                .channels
+                .values()
+                .find(|channel| channel.name.eq_ignore_ascii_case(name))
+                .cloned()
+        })
+        .flatten()
+    }
+
+    /// Channels outside of guilds, like DMs, aren't cached.
     fn put_channel(&self, channel: &Channel) {
-        let mut cache = self.channels.lock();
-        cache.put(channel.id, CachedChannel::from(channel));
+        if let Some(guild_id) = channel.guild_id {
+            self.put_all_channels(guild_id, std::slice::from_ref(channel));
+        }
+    }
+
+    /// Like `put_channel` for each of a guild's channels, but only taking the locks once.
+    /// Channels sent as part of a guild don't have their own guild ID, so it's filled in.
+    fn put_all_channels(&self, guild_id: Id<GuildMarker>, channels: &[Channel]) {
+        let guild_cache = self.guilds.get_or_default(guild_id);
+        let mut guild_cache = guild_cache.write();
+        let mut channel_guilds = self.channel_guilds.write();
+
+        for channel in channels {
+            let mut cached_channel = CachedChannel::from(channel);
+            cached_channel.guild_id.get_or_insert(guild_id);
+
+            guild_cache.put_channel(cached_channel);
+            channel_guilds.insert(channel.id, guild_id);
+        }
     }
 
     pub async fn get_channel(&self, channel_id: Id<ChannelMarker>) -> Result<CachedChannel> {
-        let cached_channel = {
-            let mut cache = self.channels.lock();
-            cache.get(&channel_id).cloned()
-        };
+        let cached_channel = self.get_channel_guild_id(channel_id).and_then(|guild_id| {
+            self.with_guild(guild_id, |guild_cache| {
+                guild_cache.channels.get(&channel_id).cloned()
+            })
+            .flatten()
+        });
 
         match cached_channel {
             Some(cached_channel) => Ok(cached_channel),
@@ -480,7 +1196,13 @@ impl Cache {
         }
     }
 
-    fn put_message(&self, message: &Message) {
+    /// The guild a channel is in, if it's cached. Never fetches, so it's cheap to call anywhere.
+    pub fn get_channel_guild_id(&self, channel_id: Id<ChannelMarker>) -> Option<Id<GuildMarker>> {
+        let channel_guilds = self.channel_guilds.read();
+        channel_guilds.get(&channel_id).copied()
+    }
+
+    async fn put_message(&self, message: &Message) {
         self.put_user(&message.author);
 
         if let (Some(guild_id), Some(member)) = (message.guild_id, &message.member) {
@@ -496,11 +1218,14 @@ impl Cache {
             }
         }
 
-        let mut cache = self.messages.lock();
-        cache.put(message.id, CachedMessage::from(message));
+        let (message_id, cached_message) = (message.id, CachedMessage::from(message));
+        self.write_messages(move |cache| {
+            cache.put(message_id, cached_message);
+        })
+        .await;
     }
 
-    fn put_message_update(&self, message: &MessageUpdate) {
+    async fn put_message_update(&self, message: &MessageUpdate) {
         if let Some(author) = &message.author {
             self.put_user(author);
         }
@@ -517,43 +1242,465 @@ impl Cache {
         }
 
         if let (Some(author), Some(kind)) = (&message.author, message.kind) {
-            let mut cache = self.messages.lock();
-            cache.put(
-                message.id,
-                CachedMessage {
-                    author_id: author.id,
-                    kind,
-                },
-            );
+            let (message_id, author_id) = (message.id, author.id);
+
+            self.write_messages(move |cache| {
+                // Edits don't touch reactions, so keep the ones we've seen.
+                let reactors = cache
+                    .pop(&message_id)
+                    .map(|cached| cached.reactors)
+                    .unwrap_or_default();
+
+                cache.put(
+                    message_id,
+                    CachedMessage {
+                        author_id,
+                        kind,
+                        reactors,
+                    },
+                );
+            })
+            .await;
         }
     }
 
+    async fn put_reaction(&self, reaction: &GatewayReaction) {
+        let (message_id, user_id) = (reaction.message_id, reaction.user_id);
+        let key = reaction_key(&reaction.emoji);
+
+        self.write_messages(move |cache| {
+            if let Some(message) = cache.peek_mut(&message_id) {
+                message.reactors.entry(key).or_default().insert(user_id);
+            }
+        })
+        .await;
+    }
+
+    async fn remove_reaction(&self, reaction: &GatewayReaction) {
+        let (message_id, user_id) = (reaction.message_id, reaction.user_id);
+        let key = reaction_key(&reaction.emoji);
+
+        self.write_messages(move |cache| {
+            if let Some(message) = cache.peek_mut(&message_id) {
+                if let Some(reactors) = message.reactors.get_mut(&key) {
+                    reactors.remove(&user_id);
+
+                    if reactors.is_empty() {
+                        message.reactors.remove(&key);
+                    }
+                }
+            }
+        })
+        .await;
+    }
+
+    /// Everyone known to have reacted to a message with an emoji, forgetting them, for when
+    /// all of that emoji's reactions are removed at once.
+    pub async fn take_reactors(
+        &self,
+        message_id: Id<MessageMarker>,
+        emoji: &ReactionType,
+    ) -> Vec<Id<UserMarker>> {
+        // Queued like the other writes, so it sees every reaction that arrived before it.
+        let key = reaction_key(emoji);
+        let (sender, receiver) = oneshot::channel();
+        self.write_messages(move |cache| {
+            let reactors = cache
+                .peek_mut(&message_id)
+                .and_then(|message| message.reactors.remove(&key))
+                .map(|reactors| reactors.into_iter().collect())
+                .unwrap_or_default();
+
+            let _ = sender.send(reactors);
+        })
+        .await;
+
+        receiver.await.unwrap_or_default()
+    }
+
     pub async fn get_message(
         &self,
         channel_id: Id<ChannelMarker>,
         message_id: Id<MessageMarker>,
     ) -> Result<CachedMessage> {
         let cached_message = {
-            let mut cache = self.messages.lock();
-            cache.get(&message_id).cloned()
+            let cache = self.messages.read().await;
+            cache.peek(&message_id).cloned()
         };
 
-        match cached_message {
-            Some(cached_message) => Ok(cached_message),
-            None => {
-                info!("message {} not in cache, fetching", message_id);
+        if let Some(cached_message) = cached_message {
+            // Peeked under the read lock, so refresh its recency separately.
+            self.write_messages(move |cache| {
+                cache.promote(&message_id);
+            })
+            .await;
 
-                let message = self
-                    .http
-                    .message(channel_id, message_id)
-                    .await?
-                    .model()
-                    .await?;
+            return Ok(cached_message);
+        }
 
-                self.put_message(&message);
+        // A message that was only just sent may still be queued, like one being reacted to
+        // straight away, so look again once every write queued before now has been applied.
+        let (sender, receiver) = oneshot::channel();
+        self.write_messages(move |cache| {
+            let _ = sender.send(cache.get(&message_id).cloned());
+        })
+        .await;
 
-                Ok(CachedMessage::from(&message))
-            }
+        if let Ok(Some(cached_message)) = receiver.await {
+            return Ok(cached_message);
+        }
+
+        info!("message {} not in cache, fetching", message_id);
+
+        let message = self
+            .http
+            .message(channel_id, message_id)
+            .await?
+            .model()
+            .await?;
+
+        self.put_message(&message).await;
+
+        Ok(CachedMessage::from(&message))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheConfig, CacheInconsistency, CachedMember, CachedMessage, CachedRole};
+    use serde_json::{json, Value};
+    use tokio::runtime::Handle;
+    use twilight_http::Client;
+    use twilight_model::channel::message::{MessageType, ReactionType};
+    use twilight_model::channel::Channel;
+    use twilight_model::gateway::event::Event;
+    use twilight_model::gateway::payload::incoming::{MessageCreate, ReactionAdd};
+    use twilight_model::gateway::GatewayReaction;
+    use twilight_model::guild::{Member, Permissions};
+    use twilight_model::id::Id;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    fn role(id: u64, color: u32, position: i64) -> CachedRole {
+        CachedRole {
+            id: Id::new(id),
+            name: format!("role {}", id),
+            color,
+            position,
+            permissions: Permissions::empty(),
         }
     }
+
+    fn message_create(id: u64, channel_id: u64, author_id: u64) -> Event {
+        let message: Value = json!({
+            "id": id.to_string(),
+            "channel_id": channel_id.to_string(),
+            "author": {
+                "id": author_id.to_string(),
+                "username": format!("user-{}", author_id),
+                "discriminator": "0001",
+                "avatar": null,
+            },
+            "content": "hello",
+            "timestamp": "2026-01-01T00:00:00.000000+00:00",
+            "edited_timestamp": null,
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [],
+            "mention_roles": [],
+            "attachments": [],
+            "embeds": [],
+            "pinned": false,
+            "type": 0,
+        });
+
+        Event::MessageCreate(Box::new(MessageCreate(
+            serde_json::from_value(message).unwrap(),
+        )))
+    }
+
+    #[test]
+    fn test_highest_role_color() {
+        let roles: HashMap<_, _> = [role(1, 0xFF0000, 1), role(2, 0x00FF00, 3), role(3, 0, 5)]
+            .iter()
+            .map(|role| (role.id, role.clone()))
+            .collect();
+
+        let member = CachedMember {
+            nick: None,
+            roles: vec![Id::new(1), Id::new(2), Id::new(3), Id::new(4)],
+        };
+
+        assert_eq!(member.highest_role_color(&roles), 0x00FF00);
+
+        let member = CachedMember {
+            nick: None,
+            roles: vec![Id::new(3)],
+        };
+
+        assert_eq!(member.highest_role_color(&roles), 0);
+    }
+
+    #[tokio::test]
+    async fn test_remove_guild() {
+        let cache = Cache::new(
+            Arc::new(Client::new(String::new())),
+            CacheConfig::default(),
+            &Handle::current(),
+        );
+        let (guild_id, other_guild_id) = (Id::new(1), Id::new(2));
+
+        let channel = |id: u64| -> Channel {
+            serde_json::from_value(json!({ "id": id.to_string(), "type": 0, "name": "general" }))
+                .unwrap()
+        };
+
+        cache.put_all_channels(guild_id, &[channel(10), channel(11)]);
+        cache.put_all_channels(other_guild_id, &[channel(20)]);
+        assert_eq!(cache.get_channel_guild_id(Id::new(11)), Some(guild_id));
+        assert_eq!(cache.get_stats().await.per_guild[&guild_id].channels, 2);
+
+        cache.remove_guild(guild_id);
+        assert_eq!(cache.get_channel_guild_id(Id::new(11)), None);
+        assert_eq!(
+            cache.get_channel_guild_id(Id::new(20)),
+            Some(other_guild_id)
+        );
+        assert!(!cache.get_stats().await.per_guild.contains_key(&guild_id));
+    }
+
+    #[tokio::test]
+    async fn test_guilds_lock_separately() {
+        let cache = Cache::new(
+            Arc::new(Client::new(String::new())),
+            CacheConfig::default(),
+            &Handle::current(),
+        );
+        let (guild_id, other_guild_id) = (Id::new(1), Id::new(2));
+
+        let channel: Channel =
+            serde_json::from_value(json!({ "id": "20", "type": 0, "name": "general" })).unwrap();
+        cache.put_all_channels(other_guild_id, &[channel]);
+
+        let guild_cache = cache.guilds.get_or_default(guild_id);
+        let _writing = guild_cache.write();
+
+        let found = cache.find_channel_by_name(other_guild_id, "GENERAL");
+        assert_eq!(found.map(|channel| channel.id), Some(Id::new(20)));
+        assert!(format!("{:?}", cache).contains("<locked>"));
+    }
+
+    #[tokio::test]
+    async fn test_get_channel_moderators() {
+        let cache = Cache::new(
+            Arc::new(Client::new(String::new())),
+            CacheConfig::default(),
+            &Handle::current(),
+        );
+        let guild_id = Id::new(1);
+
+        // One moderator by their own overwrite, the rest by role, and a role that can't.
+        let overwrite = |id: &str, kind: u8, allow: &str| json!({ "id": id, "type": kind, "allow": allow, "deny": "0" });
+        let forum: Channel = serde_json::from_value(json!({
+            "id": "10",
+            "type": 15,
+            "name": "help",
+            "permission_overwrites": [
+                overwrite("20", 1, "17179869184"),
+                overwrite("30", 0, "17179869184"),
+                overwrite("31", 0, "1024"),
+            ],
+        }))
+        .unwrap();
+        cache.put_all_channels(guild_id, &[forum]);
+
+        for (user_id, roles) in [(21, vec!["30"]), (22, vec!["31"]), (23, vec!["31", "30"])] {
+            let member: Member = serde_json::from_value(json!({
+                "deaf": false,
+                "mute": false,
+                "flags": 0,
+                "joined_at": "2026-01-01T00:00:00.000000+00:00",
+                "roles": roles,
+                "user": {
+                    "id": user_id.to_string(),
+                    "username": format!("user-{}", user_id),
+                    "discriminator": "0001",
+                    "avatar": null,
+                },
+            }))
+            .unwrap();
+            cache.put_full_member(guild_id, &member);
+        }
+
+        let forum = cache.get_channel(Id::new(10)).await.unwrap();
+        assert_eq!(
+            cache.get_channel_moderators(guild_id, &forum),
+            vec![Id::new(20), Id::new(21), Id::new(23)],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_take_reactors() {
+        let cache = Cache::new(
+            Arc::new(Client::new(String::new())),
+            CacheConfig::default(),
+            &Handle::current(),
+        );
+        let message_id = Id::new(1);
+
+        cache.messages.write().await.put(
+            message_id,
+            CachedMessage {
+                author_id: Id::new(2),
+                kind: MessageType::Regular,
+                reactors: HashMap::new(),
+            },
+        );
+
+        let reaction = |user_id: u64, emoji: ReactionType| GatewayReaction {
+            channel_id: Id::new(3),
+            emoji,
+            guild_id: Some(Id::new(4)),
+            member: None,
+            message_id,
+            user_id: Id::new(user_id),
+        };
+        let thumbs_up = || ReactionType::Unicode {
+            name: "\u{1f44d}".to_string(),
+        };
+        let custom = |name: &str| ReactionType::Custom {
+            animated: false,
+            id: Id::new(5),
+            name: Some(name.to_string()),
+        };
+
+        cache.put_reaction(&reaction(10, thumbs_up())).await;
+        cache.put_reaction(&reaction(11, thumbs_up())).await;
+        cache.put_reaction(&reaction(12, thumbs_up())).await;
+        cache.remove_reaction(&reaction(11, thumbs_up())).await;
+        cache.put_reaction(&reaction(13, custom("old"))).await;
+
+        let mut reactors = cache.take_reactors(message_id, &thumbs_up()).await;
+        reactors.sort();
+        assert_eq!(reactors, vec![Id::new(10), Id::new(12)]);
+        assert!(cache
+            .take_reactors(message_id, &thumbs_up())
+            .await
+            .is_empty());
+
+        // Custom emoji are matched by ID, even if they've been renamed since.
+        assert_eq!(
+            cache.take_reactors(message_id, &custom("new")).await,
+            vec![Id::new(13)],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_message_just_sent() {
+        let cache = Cache::new(
+            Arc::new(Client::new(String::new())),
+            CacheConfig::default(),
+            &Handle::current(),
+        );
+
+        // The writer hasn't run yet, and the client has no token to fetch it with.
+        cache.update(&message_create(1, 2, 3)).await;
+
+        let message = cache.get_message(Id::new(2), Id::new(1)).await.unwrap();
+        assert_eq!(message.author_id, Id::new(3));
+    }
+
+    /// Reactions to a channel's recent messages at 1000 a second, each looking up the message
+    /// like `handle_event` does. Run with `cargo test --release -- --ignored --nocapture`.
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn bench_reactions() {
+        const REACTIONS_PER_SECOND: u64 = 1000;
+        const MESSAGES: u64 = 100;
+
+        let cache = Arc::new(Cache::new(
+            Arc::new(Client::new(String::new())),
+            CacheConfig::default(),
+            &Handle::current(),
+        ));
+
+        for message_id in 1..=MESSAGES {
+            cache.update(&message_create(message_id, 1, 2)).await;
+        }
+
+        let mut interval =
+            tokio::time::interval(Duration::from_secs(1) / REACTIONS_PER_SECOND as u32);
+        let start = Instant::now();
+
+        let mut lookups = Vec::new();
+        for i in 0..REACTIONS_PER_SECOND {
+            interval.tick().await;
+
+            let reaction = GatewayReaction {
+                channel_id: Id::new(1),
+                emoji: ReactionType::Unicode {
+                    name: "\u{1f44d}".to_string(),
+                },
+                guild_id: None,
+                member: None,
+                message_id: Id::new(1 + i % MESSAGES),
+                user_id: Id::new(10 + i),
+            };
+
+            // Like the event loop, the cache is updated before the handler is spawned.
+            let received = Instant::now();
+            cache
+                .update(&Event::ReactionAdd(Box::new(ReactionAdd(reaction.clone()))))
+                .await;
+
+            let cache = cache.clone();
+            lookups.push(tokio::spawn(async move {
+                cache
+                    .get_message(reaction.channel_id, reaction.message_id)
+                    .await
+                    .unwrap();
+
+                received.elapsed()
+            }));
+        }
+
+        let mut latencies = Vec::new();
+        for lookup in lookups {
+            latencies.push(lookup.await.unwrap());
+        }
+
+        let elapsed = start.elapsed();
+        latencies.sort();
+
+        println!(
+            "{} reactions in {:?}, lookup latency median {:?}, p99 {:?}, max {:?}",
+            latencies.len(),
+            elapsed,
+            latencies[latencies.len() / 2],
+            latencies[latencies.len() * 99 / 100],
+            latencies[latencies.len() - 1],
+        );
+
+        assert_eq!(cache.get_stats().await.messages, MESSAGES as usize);
+    }
+
+    #[test]
+    fn test_cache_inconsistency_display() {
+        let inconsistency = CacheInconsistency::StaleNick {
+            user_id: Id::new(1),
+            cached: None,
+            live: Some("alice".to_string()),
+        };
+        assert_eq!(
+            inconsistency.to_string(),
+            "member 1 has nick Some(\"alice\") but was cached with None",
+        );
+
+        assert_eq!(
+            CacheInconsistency::MissingMember(Id::new(2)).to_string(),
+            "member 2 has left the guild",
+        );
+    }
 }
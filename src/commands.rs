@@ -1,21 +1,75 @@
 use anyhow::{Context as AnyhowContext, Result};
 use futures::future::join_all;
+use serde::Serialize;
+use sqlx::MySqlPool;
 use tokio::io::AsyncWriteExt;
 use tokio::process;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 use twilight_command_parser::{Arguments, CommandParserConfig, Parser};
-use twilight_model::channel::message::embed::{Embed, EmbedField, EmbedFooter};
-use twilight_model::channel::Message;
+use twilight_http::Client;
+use twilight_model::application::command::{
+    CommandOption, CommandOptionChoice, CommandOptionChoiceValue, CommandOptionType,
+    CommandOptionValue as CommandOptionLimit,
+};
+use twilight_model::application::interaction::application_command::{
+    CommandDataOption, CommandOptionValue,
+};
+use twilight_model::application::interaction::InteractionData;
+use twilight_model::channel::message::embed::{
+    Embed, EmbedAuthor, EmbedField, EmbedFooter, EmbedImage,
+};
+use twilight_model::channel::message::MessageFlags;
+use twilight_model::channel::{ChannelType, Message};
 use twilight_model::gateway::event::Event;
-use twilight_model::gateway::event::Event::MessageCreate;
+use twilight_model::gateway::event::Event::{InteractionCreate, MessageCreate};
+use twilight_model::guild::Permissions;
+use twilight_model::http::interaction::{
+    InteractionResponse, InteractionResponseData, InteractionResponseType,
+};
+use twilight_model::id::marker::{
+    ApplicationMarker, ChannelMarker, GuildMarker, RoleMarker, UserMarker,
+};
 use twilight_model::id::Id;
+use twilight_model::user::User;
+use twilight_model::util::Timestamp;
 
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::env;
 use std::process::Stdio;
+use std::time::{Duration, Instant};
 use twilight_model::http::attachment::Attachment;
 
+use crate::cache::{CacheStats, CachedChannel};
 use crate::context::Context;
-use crate::social::graph::ColorScheme;
+use crate::guild_config::{get_guild_config, set_dot_template, DotTemplate};
+use crate::social::analysis::{
+    get_interaction_velocity, get_previous_interaction_velocity, network_growth_rate,
+    simulate_pair_weight, user_activity_score, user_activity_scores,
+};
+use crate::social::dot::DotAttributes;
+use crate::social::graph::{
+    format_channel_dot, ColorScheme, DotOptions, GraphSummary, LabelMode, LayoutEngine, Palette,
+    PathWeightAggregation, SocialGraph,
+};
+use crate::social::history::{
+    get_active_users_since, get_channel_interaction_counts, get_interaction_history,
+    get_interaction_leaderboard, get_most_active_channel, get_new_connections_since,
+    get_reason_distribution, get_top_channels, get_weekly_activity,
+};
+use crate::social::inference::{Interaction, InteractionType, RelationshipChangeReason};
+use crate::social::{decay_graphs, delete_channel_events};
+use crate::util::{
+    blend_colors, format_duration, format_long_duration, format_relative_time,
+    get_current_timestamp_ms,
+};
 
+/// Handle prefix commands, returning whether the event was a command.
+#[deprecated(
+    since = "0.2.0",
+    note = "prefix commands are being replaced by slash commands, use those instead"
+)]
 pub async fn handle_event(context: &Context, event: &Event) -> Result<bool> {
     match event {
         MessageCreate(message) => handle_message(context, message).await,
@@ -24,6 +78,8 @@ pub async fn handle_event(context: &Context, event: &Event) -> Result<bool> {
 }
 
 async fn handle_message(context: &Context, message: &Message) -> Result<bool> {
+    let received = Instant::now();
+
     // Ignore messages from bots (including ourself)
     if message.author.bot {
         return Ok(false);
@@ -37,9 +93,30 @@ async fn handle_message(context: &Context, message: &Message) -> Result<bool> {
     config.add_prefix(format!("<@!{}> ", context.user.id));
     config.add_command("help", false);
     config.add_command("invite", false);
+    config.add_command("ping", false);
     config.add_command("graph", false);
     config.add_command("stats", false);
     config.add_command("dump", false);
+    config.add_command("list", false);
+    config.add_command("check-cache", false);
+    config.add_command("debug-last", false);
+    config.add_command("history", false);
+    config.add_command("strength", false);
+    config.add_command("compare", false);
+    config.add_command("simulate", false);
+    config.add_command("report", false);
+    config.add_command("community", false);
+    config.add_command("leaderboard", false);
+    config.add_command("top-channels", false);
+    config.add_command("activity", false);
+    config.add_command("active", false);
+    config.add_command("network", false);
+    config.add_command("template", false);
+    config.add_command("rules", false);
+    config.add_command("forget-channel", false);
+    config.add_command("decay", false);
+    config.add_command("export", false);
+    config.add_command("opt-out", false);
 
     let parser = Parser::new(config);
     let command = match parser.parse(&message.content) {
@@ -49,11 +126,41 @@ async fn handle_message(context: &Context, message: &Message) -> Result<bool> {
 
     info!("received command: {:?} in message {:?}", command, message);
 
+    if !is_allowed_to_run(context, command.name, message.guild_id, message.author.id).await? {
+        info!(
+            "{} tried to run {} command without Manage Server",
+            message.author.id, command.name,
+        );
+        return Ok(true);
+    }
+
     let result = match command.name {
-        "help" | "invite" => command_help(context, message).await,
-        "graph" => command_graph(context, message, command.arguments).await,
-        "stats" => command_stats(context, message).await,
-        "dump" => command_dump(context, message, command.arguments).await,
+        "help" => command_help(context, &message.into()).await,
+        "invite" => command_invite(context, message).await,
+        "ping" => command_ping(context, message, received).await,
+        "graph" => command_graph(context, &message.into(), command.arguments).await,
+        "stats" => command_stats(context, &message.into(), command.arguments).await,
+        "dump" => command_dump(context, &message.into(), command.arguments).await,
+        "check-cache" => command_check_cache(context, message, command.arguments).await,
+        "list" => command_list(context, message).await,
+        "debug-last" => command_debug_last(context, message).await,
+        "history" => command_history(context, message, command.arguments).await,
+        "strength" => command_strength(context, message, command.arguments).await,
+        "compare" => command_compare(context, message, command.arguments).await,
+        "simulate" => command_simulate(context, message, command.arguments).await,
+        "report" => command_report(context, message).await,
+        "community" => command_community(context, message).await,
+        "leaderboard" => command_leaderboard(context, message, command.arguments).await,
+        "top-channels" => command_top_channels(context, message, command.arguments).await,
+        "activity" => command_activity(context, message, command.arguments).await,
+        "active" => command_active(context, message).await,
+        "network" => command_network(context, message, command.arguments).await,
+        "template" => command_template(context, message, command.arguments).await,
+        "rules" => command_rules(context, message, command.arguments).await,
+        "forget-channel" => command_forget_channel(context, message, command.arguments).await,
+        "decay" => command_decay(context, message).await,
+        "export" => command_export(context, message, command.arguments).await,
+        "opt-out" => command_opt_out(context, message, command.arguments).await,
         _ => Ok(()),
     };
 
@@ -73,43 +180,600 @@ async fn handle_message(context: &Context, message: &Message) -> Result<bool> {
     Ok(true)
 }
 
-async fn command_help(context: &Context, message: &Message) -> Result<()> {
+/// A slash command, with its options in the order they're written out as arguments.
+struct SlashCommand {
+    name: &'static str,
+    description: &'static str,
+    options: Vec<CommandOption>,
+    /// Who Discord shows the command to by default. It's only a default that servers can
+    /// change, so we still check when it's run.
+    default_member_permissions: Option<Permissions>,
+}
+
+fn slash_option(kind: CommandOptionType, name: &str, description: &str) -> CommandOption {
+    CommandOption {
+        autocomplete: None,
+        channel_types: None,
+        choices: None,
+        description: description.to_string(),
+        description_localizations: None,
+        kind,
+        max_length: None,
+        max_value: None,
+        min_length: None,
+        min_value: None,
+        name: name.to_string(),
+        name_localizations: None,
+        options: None,
+        required: None,
+    }
+}
+
+fn slash_choices<'a>(
+    choices: impl IntoIterator<Item = (&'a str, String)>,
+) -> Vec<CommandOptionChoice> {
+    choices
+        .into_iter()
+        .map(|(name, value)| CommandOptionChoice {
+            name: name.to_string(),
+            name_localizations: None,
+            value: CommandOptionChoiceValue::String(value),
+        })
+        .collect()
+}
+
+/// The commands we register with Discord. Each one is handled by the same function as its
+/// prefix command, so any option without a proper type falls back to `options`, which takes
+/// the prefix command's arguments as typed.
+fn get_slash_commands() -> Vec<SlashCommand> {
+    let mut graph_channel = slash_option(
+        CommandOptionType::Channel,
+        "channel",
+        "Only draw this channel and its threads.",
+    );
+    graph_channel.channel_types = Some(vec![ChannelType::GuildText, ChannelType::GuildForum]);
+
+    let mut graph_depth = slash_option(
+        CommandOptionType::Integer,
+        "depth",
+        "How many hops out from the user to draw.",
+    );
+    graph_depth.min_value = Some(CommandOptionLimit::Integer(1));
+    graph_depth.max_value = Some(CommandOptionLimit::Integer(MAX_EGO_DEPTH.into()));
+
+    let mut graph_format = slash_option(
+        CommandOptionType::String,
+        "format",
+        "What to draw the graph as.",
+    );
+    graph_format.choices = Some(slash_choices(
+        GraphFormat::ALL
+            .iter()
+            .map(|format| (format.name(), format!("--format={}", format.name())))
+            .chain([("mermaid", "--format=mermaid".to_string())]),
+    ));
+
+    let mut graph_layout = slash_option(
+        CommandOptionType::String,
+        "layout",
+        "How to lay out the graph, picked from its size by default.",
+    );
+    graph_layout.choices = Some(slash_choices(
+        LayoutEngine::ALL
+            .iter()
+            .map(|engine| (engine.name(), engine.name().to_string())),
+    ));
+
+    let mut stats_format = slash_option(
+        CommandOptionType::String,
+        "format",
+        "Send the stats as text or as a JSON file.",
+    );
+    stats_format.choices = Some(slash_choices([
+        ("text", "--format=text".to_string()),
+        ("json", "--format=json".to_string()),
+    ]));
+
+    let mut dump_guild = slash_option(
+        CommandOptionType::String,
+        "guild",
+        "The ID of the guild to dump, or leave it out to list them.",
+    );
+    dump_guild.required = Some(false);
+
+    vec![
+        SlashCommand {
+            name: "help",
+            description: "What the bot does and the commands it has.",
+            options: Vec::new(),
+            default_member_permissions: None,
+        },
+        SlashCommand {
+            name: "graph",
+            description: "Draw who interacts with who in this server.",
+            options: vec![
+                graph_channel,
+                slash_option(
+                    CommandOptionType::User,
+                    "user",
+                    "Only draw this person's connections.",
+                ),
+                graph_depth,
+                graph_format,
+                graph_layout,
+                slash_option(
+                    CommandOptionType::String,
+                    "options",
+                    "Anything else from help, like `dark --undirected`.",
+                ),
+            ],
+            default_member_permissions: Some(Permissions::MANAGE_GUILD),
+        },
+        SlashCommand {
+            name: "stats",
+            description: "How much the bot has seen in this server.",
+            options: vec![
+                stats_format,
+                slash_option(
+                    CommandOptionType::Boolean,
+                    "clustering",
+                    "Include how tightly knit the server is.",
+                ),
+                slash_option(
+                    CommandOptionType::Boolean,
+                    "breakdown",
+                    "Include what kinds of interactions have been seen.",
+                ),
+            ],
+            default_member_permissions: Some(Permissions::MANAGE_GUILD),
+        },
+        SlashCommand {
+            name: "dump",
+            description: "Export a guild's graph.",
+            options: vec![
+                dump_guild,
+                slash_option(
+                    CommandOptionType::String,
+                    "options",
+                    "Like `ids`, `svg`, `sfdp` or `d3`.",
+                ),
+            ],
+            // Only admins see it, it's for bot owners anyway.
+            default_member_permissions: Some(Permissions::empty()),
+        },
+    ]
+}
+
+/// Register our slash commands globally, updating any that are already registered.
+pub async fn register_slash_commands(
+    http: &Client,
+    application_id: Id<ApplicationMarker>,
+) -> Result<()> {
+    let client = http.interaction(application_id);
+
+    for command in get_slash_commands() {
+        let mut request = client
+            .create_global_command()
+            .chat_input(command.name, command.description)?
+            .command_options(&command.options)?;
+
+        if let Some(permissions) = command.default_member_permissions {
+            request = request.default_member_permissions(permissions);
+        }
+
+        request.await?;
+    }
+
+    Ok(())
+}
+
+/// A slash command's options written out as the prefix command's arguments, in the order the
+/// command declares them, so both go through the same parsing.
+fn format_slash_command_options(command: &SlashCommand, options: &[CommandDataOption]) -> String {
+    let mut arguments = Vec::new();
+
+    for declared in &command.options {
+        let option = match options.iter().find(|option| option.name == declared.name) {
+            Some(option) => option,
+            None => continue,
+        };
+
+        match &option.value {
+            CommandOptionValue::Channel(channel_id) => arguments.push(format!("<#{}>", channel_id)),
+            CommandOptionValue::User(user_id) => arguments.push(format!("<@{}>", user_id)),
+            CommandOptionValue::Integer(value) => arguments.push(value.to_string()),
+            CommandOptionValue::String(value) => arguments.push(value.clone()),
+            CommandOptionValue::Boolean(true) => arguments.push(format!("--{}", option.name)),
+            _ => (),
+        }
+    }
+
+    arguments.join(" ")
+}
+
+/// Handle slash commands, returning whether the event was one.
+pub async fn handle_interaction_event(context: &Context, event: &Event) -> Result<bool> {
+    let interaction = match event {
+        InteractionCreate(interaction) => interaction,
+        _ => return Ok(false),
+    };
+
+    let data = match &interaction.data {
+        Some(InteractionData::ApplicationCommand(data)) => data,
+        _ => return Ok(false),
+    };
+
+    info!(
+        "received slash command: {:?} in interaction {}",
+        data, interaction.id
+    );
+
+    let invocation = Invocation {
+        guild_id: interaction.guild_id,
+        channel_id: interaction
+            .channel_id
+            .context("interaction not in a channel")?,
+        author: interaction.author().context("interaction has no author")?,
+        interaction: Some((interaction.application_id, &interaction.token)),
+    };
+
+    if !is_allowed_to_run(
+        context,
+        &data.name,
+        invocation.guild_id,
+        invocation.author.id,
+    )
+    .await?
+    {
+        // Only the person who ran it sees this, so it doesn't need to be quiet like for messages.
+        context
+            .http
+            .interaction(interaction.application_id)
+            .create_response(
+                interaction.id,
+                &interaction.token,
+                &InteractionResponse {
+                    kind: InteractionResponseType::ChannelMessageWithSource,
+                    data: Some(InteractionResponseData {
+                        content: Some(format!(
+                            "You need the Manage Server permission to use `/{}`.",
+                            data.name,
+                        )),
+                        flags: Some(MessageFlags::EPHEMERAL),
+                        ..InteractionResponseData::default()
+                    }),
+                },
+            )
+            .await?;
+
+        return Ok(true);
+    }
+
+    // Drawing a graph can take longer than the 3 seconds Discord waits for a response, so
+    // acknowledge it straight away and send the reply as a followup.
+    context
+        .http
+        .interaction(interaction.application_id)
+        .create_response(
+            interaction.id,
+            &interaction.token,
+            &InteractionResponse {
+                kind: InteractionResponseType::DeferredChannelMessageWithSource,
+                data: None,
+            },
+        )
+        .await?;
+
+    let arguments = get_slash_commands()
+        .iter()
+        .find(|command| command.name == data.name)
+        .map(|command| format_slash_command_options(command, &data.options))
+        .unwrap_or_default();
+    let arguments = Arguments::new(&arguments);
+
+    let result = match data.name.as_str() {
+        "help" => command_help(context, &invocation).await,
+        "graph" => command_graph(context, &invocation, arguments).await,
+        "stats" => command_stats(context, &invocation, arguments).await,
+        "dump" => command_dump(context, &invocation, arguments).await,
+        name => Err(anyhow::anyhow!("{} is not a recognized command", name)),
+    };
+
+    if let Err(error) = result {
+        error!("slash command failed: {:?}", error);
+
+        invocation
+            .reply_content(
+                context,
+                &format!(
+                    "Sorry, there was an error handling that command :warning:\n```\n{}\n```",
+                    error
+                ),
+            )
+            .await?;
+    }
+
+    Ok(true)
+}
+
+/// The permissions requested by the invite link, and why we need each of them.
+const INVITE_PERMISSIONS: [(Permissions, &str); 5] = [
+    (
+        Permissions::VIEW_CHANNEL,
+        "**View Channels** to see the conversations we infer relationships from.",
+    ),
+    (
+        Permissions::SEND_MESSAGES,
+        "**Send Messages** to reply to commands.",
+    ),
+    (
+        Permissions::EMBED_LINKS,
+        "**Embed Links** to send help and invite messages like this one.",
+    ),
+    (
+        Permissions::ATTACH_FILES,
+        "**Attach Files** to upload rendered graphs.",
+    ),
+    (
+        Permissions::READ_MESSAGE_HISTORY,
+        "**Read Message History** to find the authors of replied-to and reacted-to messages.",
+    ),
+];
+
+fn get_invite_url(context: &Context) -> String {
+    let permissions = INVITE_PERMISSIONS
+        .iter()
+        .fold(Permissions::empty(), |acc, (permission, _)| {
+            acc | *permission
+        });
+
+    format!(
+        "https://discord.com/api/oauth2/authorize?client_id={}&permissions={}&scope=bot%20applications.commands",
+        context.user.id,
+        permissions.bits(),
+    )
+}
+
+fn get_command_footer(author: &User) -> EmbedFooter {
+    EmbedFooter {
+        icon_url: None,
+        proxy_icon_url: None,
+        text: format!(
+            "Sent in response to a command from {}#{:04}",
+            author.name, author.discriminator,
+        ),
+    }
+}
+
+/// Who ran a command and where, so the same command can answer a message or a slash command.
+struct Invocation<'a> {
+    guild_id: Option<Id<GuildMarker>>,
+    channel_id: Id<ChannelMarker>,
+    author: &'a User,
+    /// The application and token to follow up with for slash commands, which are answered
+    /// through the interaction rather than with a new message.
+    interaction: Option<(Id<ApplicationMarker>, &'a str)>,
+}
+
+impl<'a> From<&'a Message> for Invocation<'a> {
+    fn from(message: &'a Message) -> Self {
+        Invocation {
+            guild_id: message.guild_id,
+            channel_id: message.channel_id,
+            author: &message.author,
+            interaction: None,
+        }
+    }
+}
+
+impl Invocation<'_> {
+    async fn reply(
+        &self,
+        context: &Context,
+        content: Option<&str>,
+        embeds: &[Embed],
+        attachments: &[Attachment],
+    ) -> Result<()> {
+        match self.interaction {
+            Some((application_id, token)) => {
+                let client = context.http.interaction(application_id);
+                let mut followup = client
+                    .create_followup(token)
+                    .embeds(embeds)?
+                    .attachments(attachments)?;
+
+                if let Some(content) = content {
+                    followup = followup.content(content)?;
+                }
+
+                followup.await?;
+            }
+            None => {
+                let mut message = context
+                    .http
+                    .create_message(self.channel_id)
+                    .embeds(embeds)?
+                    .attachments(attachments)?;
+
+                if let Some(content) = content {
+                    message = message.content(content)?;
+                }
+
+                message.await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn reply_content(&self, context: &Context, content: &str) -> Result<()> {
+        self.reply(context, Some(content), &[], &[]).await
+    }
+}
+
+/// Render an interaction as an embed, for any command that wants to show one.
+async fn interaction_to_embed(context: &Context, interaction: &Interaction) -> Result<Embed> {
+    let source = context.cache.get_user(interaction.source).await?;
+
+    let source_name = match context
+        .cache
+        .get_member(interaction.guild, interaction.source)
+        .await
+    {
+        Ok(member) => member.nick,
+        Err(_) => None,
+    }
+    .unwrap_or_else(|| source.name.clone());
+
+    let source_avatar = source.avatar.map(|avatar| {
+        format!(
+            "https://cdn.discordapp.com/avatars/{}/{}.png",
+            source.id, avatar,
+        )
+    });
+
+    let mention_list = |user_ids: &[Id<UserMarker>]| {
+        user_ids
+            .iter()
+            .map(|user_id| format!("<@{}>", user_id))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let target = interaction.target.map(|target| format!("<@{}>", target));
+
+    let mut actions = Vec::new();
+
+    match interaction.what {
+        InteractionType::Message => {
+            if let Some(target) = target {
+                actions.push(format!("replied to {}", target));
+            }
+
+            if !interaction.other_targets.is_empty() {
+                actions.push(format!(
+                    "mentioned {}",
+                    mention_list(&interaction.other_targets),
+                ));
+            }
+        }
+        InteractionType::Reaction => {
+            actions.extend(target.map(|target| format!("reacted to a message by {}", target)));
+        }
+        InteractionType::ReactionRemoval => {
+            actions.extend(
+                target.map(|target| format!("removed a reaction to a message by {}", target)),
+            );
+        }
+        InteractionType::EventRSVP => {
+            actions.extend(target.map(|target| format!("subscribed to an event by {}", target)));
+        }
+        InteractionType::EventAttendance => {
+            actions.push(format!(
+                "attended an event with {}",
+                mention_list(&interaction.other_targets),
+            ));
+        }
+        InteractionType::ForumPost => {
+            actions.push(format!(
+                "posted in a forum moderated by {}",
+                mention_list(&interaction.other_targets),
+            ));
+        }
+    }
+
+    if actions.is_empty() {
+        actions.push("sent a message".to_string());
+    }
+
+    let description = format!("{} in <#{}>", actions.join(" and "), interaction.channel);
+
+    let timestamp = Timestamp::from_micros(interaction.timestamp as i64 * 1000)?;
+
+    let guild_name = context.cache.get_guild(interaction.guild).await?.name;
+
+    Ok(Embed {
+        author: Some(EmbedAuthor {
+            icon_url: source_avatar,
+            name: source_name,
+            proxy_icon_url: None,
+            url: None,
+        }),
+        color: None,
+        description: Some(description),
+        fields: Vec::new(),
+        footer: Some(EmbedFooter {
+            icon_url: None,
+            proxy_icon_url: None,
+            text: guild_name,
+        }),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: Some(timestamp),
+        title: None,
+        url: None,
+        video: None,
+    })
+}
+
+async fn command_help(context: &Context, invocation: &Invocation<'_>) -> Result<()> {
     let description = format!(
         "I'm a Discord Bot that infers relationships between users and draws pretty graphs.\n\
-        I'll only respond to messages that directly mention me, like `@{} help`.",
+        I'll only respond to messages that directly mention me, like `@{} help`, \
+        or to slash commands like `/graph`. \
+        Everything but `help`, `invite`, `ping` and `opt-out` needs the Manage Server permission.",
         context.user.name,
     );
 
     let commands_field = EmbedField {
         inline: false,
         name: "Commands".to_string(),
-        value: vec![
+        value: [
             "` help               `\u{2000}This message.",
-            "` graph [light|dark] `\u{2000}Get a preview-quality graph image.",
+            "` invite             `\u{2000}Get a link to add the bot to your server.",
+            "` ping               `\u{2000}Check how quickly the bot is responding.",
+            "` opt-out [on|off]   `\u{2000}Stop or restart tracking your interactions in this server, \
+            until the bot restarts.",
+            "` history @a @b      `\u{2000}Recent interactions between two users.",
+            "` strength @a @b     `\u{2000}How closely two users are connected.",
+            "` compare @a @b      `\u{2000}Two users' connections side by side.",
+            "` simulate @a @b     `\u{2000}How strong two users' connection could be with `--interactions=10` more over `--period=30d`.",
+            "` report             `\u{2000}What happened in the last week.",
+            "` community          `\u{2000}The largest groups of connected users.",
+            "` leaderboard        `\u{2000}Who receives the most interactions, or sends with `--sent`, or is most active with `--score`.",
+            "` top-channels       `\u{2000}The busiest channels this week, or over `--since=30d`.",
+            "` network @role      `\u{2000}How the members of a role are connected.",
+            "` activity @user     `\u{2000}How active a user has been over the last year.",
+            "` active             `\u{2000}How many people have been active in the last week.",
+            "` template node|edge `\u{2000}Show or set extra GraphViz attributes for graphs.",
+            "` rules [on|off rule]`\u{2000}Show or switch the ways interactions are recognized, \
+            until the bot restarts.",
+            "` forget-channel #c  `\u{2000}Delete every interaction recorded in a channel.",
+            "` graph [light|dark] `\u{2000}Get a preview-quality graph image, \
+            or add a channel like `#general` for just that channel and its threads. \
+            Mention someone like `@alice` to see their connections, \
+            and add a number up to 3 like `@alice 2` to reach further out. \
+            Add `--weight-threshold=5` (5 to 10 works well) to hide weak connections, \
+            `--undirected` for one line per pair, or `--colour-scheme=pastel` for other colors. \
+            In servers with more than 50 people, `--min-members=2` hides loosely connected users \
+            and `--no-isolated` hides users with no connections left. \
+            Bots are left out unless you add `--include-bots`. \
+            `--mode=channels` shows which channels share users instead, \
+            and `--format=mermaid` also posts Mermaid source to paste into GitHub or Notion. \
+            Add `svg` or `pdf` for a file that stays sharp when zoomed into. \
+            The layout suits the graph's size, or pick one like `sfdp`, `neato` or `circo`.",
         ]
         .join("\n"),
     };
 
-    let invite_url = format!(
-        "https://discord.com/api/oauth2/authorize?client_id={}&permissions=117824&scope=bot",
-        context.user.id,
-    );
-
     let invite_field = EmbedField {
         inline: false,
         name: "Want graphs for your guild?".to_string(),
         value: format!(
             "[Click here]({}) to invite the bot to join your server.",
-            invite_url,
-        ),
-    };
-
-    let footer = EmbedFooter {
-        icon_url: None,
-        proxy_icon_url: None,
-        text: format!(
-            "Sent in response to a command from {}#{:04}",
-            message.author.name, message.author.discriminator,
+            get_invite_url(context),
         ),
     };
 
@@ -118,7 +782,7 @@ async fn command_help(context: &Context, message: &Message) -> Result<()> {
         color: None,
         description: Some(description),
         fields: vec![commands_field, invite_field],
-        footer: Some(footer),
+        footer: Some(get_command_footer(invocation.author)),
         image: None,
         kind: "rich".to_string(),
         provider: None,
@@ -129,161 +793,2939 @@ async fn command_help(context: &Context, message: &Message) -> Result<()> {
         video: None,
     };
 
+    invocation.reply(context, None, &[embed], &[]).await?;
+
+    Ok(())
+}
+
+async fn command_invite(context: &Context, message: &Message) -> Result<()> {
+    let description = format!(
+        "[Click here]({}) to invite the bot to join your server.",
+        get_invite_url(context),
+    );
+
+    let permissions_field = EmbedField {
+        inline: false,
+        name: "Requested permissions".to_string(),
+        value: INVITE_PERMISSIONS
+            .iter()
+            .map(|(_, reason)| *reason)
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(description),
+        fields: vec![permissions_field],
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some("Want graphs for your guild?".to_string()),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+/// What the nodes of a drawn graph are.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum GraphMode {
+    Users,
+    /// Channels, linked by the users they share.
+    Channels,
+}
+
+/// A channel given to the graph command, either mentioned or typed out by name.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum ChannelArgument {
+    Id(Id<ChannelMarker>),
+    Name(String),
+}
+
+/// What GraphViz renders a graph to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum GraphFormat {
+    Png,
+    /// Scales to any size, for large graphs that turn to mush as a PNG.
+    Svg,
+    Pdf,
+}
+
+impl GraphFormat {
+    const ALL: [GraphFormat; 3] = [GraphFormat::Png, GraphFormat::Svg, GraphFormat::Pdf];
+
+    /// The name GraphViz uses for the format, which is also its file extension.
+    fn name(self) -> &'static str {
+        match self {
+            GraphFormat::Png => "png",
+            GraphFormat::Svg => "svg",
+            GraphFormat::Pdf => "pdf",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == name)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct GraphArguments {
+    mode: GraphMode,
+    format: GraphFormat,
+    /// Also post Mermaid source in a code block, for pasting into GitHub or Notion.
+    mermaid: bool,
+    color_scheme: ColorScheme,
+    transparent: bool,
+    undirected: bool,
+    weight_threshold: Option<f32>,
+    palette: Option<Palette>,
+    min_members: usize,
+    no_isolated: bool,
+    include_bots: bool,
+    /// `None` for `--layout=auto`, see `select_layout_engine`.
+    layout: Option<LayoutEngine>,
+    /// Only draw this channel, rather than the whole guild.
+    channel: Option<ChannelArgument>,
+    /// Only draw the connections around this user, out to `depth` hops.
+    user: Option<Id<UserMarker>>,
+    depth: u8,
+}
+
+/// How far out a user's connections can be drawn. Past this it's most of the guild anyway.
+const MAX_EGO_DEPTH: u8 = 3;
+
+/// The fill for the user at the center of their connections.
+const EGO_COLOR: u32 = 0xF1C40F;
+
+/// Parse `[light|dark] [transparent]`, with `--name=value` options, a `#channel`, an
+/// `@user [depth]`, a format like `svg` and a layout like `sfdp` allowed anywhere.
+fn parse_graph_arguments(arguments: Arguments<'_>) -> Result<GraphArguments> {
+    let mut parsed = GraphArguments {
+        mode: GraphMode::Users,
+        format: GraphFormat::Png,
+        mermaid: false,
+        color_scheme: ColorScheme::Dark,
+        transparent: false,
+        undirected: false,
+        weight_threshold: None,
+        palette: None,
+        min_members: 0,
+        no_isolated: false,
+        include_bots: false,
+        layout: None,
+        channel: None,
+        user: None,
+        depth: 1,
+    };
+
+    let mut positional = 0;
+    let mut after_user = false;
+    for argument in arguments {
+        // A number straight after a user mention is how far out to draw their connections.
+        if std::mem::take(&mut after_user) {
+            if let Ok(depth) = argument.parse::<u8>() {
+                if !(1..=MAX_EGO_DEPTH).contains(&depth) {
+                    anyhow::bail!("the depth has to be between 1 and {}", MAX_EGO_DEPTH);
+                }
+
+                parsed.depth = depth;
+                continue;
+            }
+        }
+
+        if argument.starts_with("<@") && !argument.starts_with("<@&") {
+            let user_id = parse_user_argument(argument)
+                .with_context(|| format!("{} is not a valid user", argument))?;
+
+            parsed.user = Some(user_id);
+            after_user = true;
+            continue;
+        }
+
+        if let Some(format) = GraphFormat::from_name(argument) {
+            parsed.format = format;
+            continue;
+        }
+
+        if let Some(engine) = LayoutEngine::from_name(argument) {
+            parsed.layout = Some(engine);
+            continue;
+        }
+
+        if argument.starts_with("<#") {
+            let channel_id = parse_channel_argument(argument)
+                .with_context(|| format!("{} is not a valid channel", argument))?;
+
+            parsed.channel = Some(ChannelArgument::Id(channel_id));
+            continue;
+        }
+
+        if let Some(name) = argument.strip_prefix('#') {
+            parsed.channel = Some(ChannelArgument::Name(name.to_string()));
+            continue;
+        }
+
+        if let Some(option) = argument.strip_prefix("--") {
+            let (name, value) = option.split_once('=').unwrap_or((option, ""));
+
+            match name {
+                "undirected" => parsed.undirected = true,
+                "no-isolated" => parsed.no_isolated = true,
+                "include-bots" => parsed.include_bots = true,
+                "mode" => {
+                    parsed.mode = match value {
+                        "users" => GraphMode::Users,
+                        "channels" => GraphMode::Channels,
+                        _ => anyhow::bail!(
+                            "{} is not a recognized mode, expected \"users\" or \"channels\"",
+                            value,
+                        ),
+                    }
+                }
+                "layout" => {
+                    parsed.layout = match value {
+                        "auto" => None,
+                        _ => {
+                            let engine = LayoutEngine::from_name(value).with_context(|| {
+                                let names: Vec<_> =
+                                    LayoutEngine::ALL.iter().map(|engine| engine.name()).collect();
+
+                                format!(
+                                    "{} is not a recognized layout, expected \"auto\" or one of {}",
+                                    value,
+                                    names.join(", "),
+                                )
+                            })?;
+
+                            Some(engine)
+                        }
+                    }
+                }
+                "format" => match GraphFormat::from_name(value) {
+                    Some(format) => parsed.format = format,
+                    None if value == "mermaid" => parsed.mermaid = true,
+                    None => anyhow::bail!(
+                        "{} is not a recognized format, expected \"png\", \"svg\", \"pdf\" or \"mermaid\"",
+                        value,
+                    ),
+                },
+                "weight-threshold" => {
+                    let weight_threshold: f32 = value
+                        .parse()
+                        .with_context(|| format!("{} is not a valid weight threshold", value))?;
+
+                    if !weight_threshold.is_finite() || weight_threshold < 0.0 {
+                        anyhow::bail!("the weight threshold can't be negative");
+                    }
+
+                    parsed.weight_threshold = Some(weight_threshold);
+                }
+                "min-members" => {
+                    parsed.min_members = value
+                        .parse()
+                        .with_context(|| format!("{} is not a valid minimum", value))?;
+                }
+                "colour-scheme" | "color-scheme" => {
+                    let palette = Palette::from_name(value).with_context(|| {
+                        let names: Vec<_> = Palette::ALL
+                            .iter()
+                            .map(|palette| format!("\"{}\"", palette.name()))
+                            .collect();
+
+                        format!(
+                            "{} is not a recognized colour scheme, expected one of {}",
+                            value,
+                            names.join(", "),
+                        )
+                    })?;
+
+                    parsed.palette = Some(palette);
+                }
+                _ => anyhow::bail!("{} is not a recognized option", argument),
+            }
+
+            continue;
+        }
+
+        match positional {
+            0 => {
+                parsed.color_scheme = match argument {
+                    "light" => ColorScheme::Light,
+                    "dark" => ColorScheme::Dark,
+                    value => anyhow::bail!(
+                        "{} is not a recognized color scheme, expected \"light\" or \"dark\"",
+                        value,
+                    ),
+                }
+            }
+            1 => {
+                parsed.transparent = match argument {
+                    "transparent" => true,
+                    value => anyhow::bail!(
+                        "{} is not a recognized background, expected \"transparent\"",
+                        value,
+                    ),
+                }
+            }
+            _ => anyhow::bail!("{} is not a recognized argument", argument),
+        }
+
+        positional += 1;
+    }
+
+    if parsed.mode == GraphMode::Channels && parsed.channel.is_some() {
+        anyhow::bail!("a channel can't be drawn with --mode=channels");
+    }
+
+    if parsed.user.is_some() {
+        if parsed.mode == GraphMode::Channels {
+            anyhow::bail!("a user's connections can't be drawn with --mode=channels");
+        }
+
+        if parsed.channel.is_some() {
+            anyhow::bail!("a user's connections can't be drawn for a single channel");
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Find the channel a user meant in the guild, by ID or by name, ignoring case.
+async fn resolve_channel_argument(
+    context: &Context,
+    guild_id: Id<GuildMarker>,
+    argument: &ChannelArgument,
+) -> Result<Option<CachedChannel>> {
+    match argument {
+        ChannelArgument::Id(channel_id) => {
+            if context.cache.get_channel_guild_id(*channel_id) != Some(guild_id) {
+                return Ok(None);
+            }
+
+            Ok(Some(context.cache.get_channel(*channel_id).await?))
+        }
+        ChannelArgument::Name(name) => Ok(context.cache.find_channel_by_name(guild_id, name)),
+    }
+}
+
+async fn command_ping(context: &Context, message: &Message, received: Instant) -> Result<()> {
+    let reply = context
+        .http
+        .create_message(message.channel_id)
+        .content("Pong! \u{1F3D3}")?
+        .await?
+        .model()
+        .await?;
+
+    let mut content = format!(
+        "Pong! \u{1F3D3}\nLatency: {}ms",
+        received.elapsed().as_millis(),
+    );
+
+    if let Some(gateway_latency) = context.gateway_latency {
+        content.push_str(&format!(
+            "\nGateway heartbeat: {}ms",
+            gateway_latency.as_millis(),
+        ));
+    }
+
+    context
+        .http
+        .update_message(reply.channel_id, reply.id)
+        .content(Some(&content))?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_graph(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    arguments: Arguments<'_>,
+) -> Result<()> {
+    // TODO: Respond to the command on errors.
+
+    let guild_id = invocation.guild_id.context("message not to guild")?;
+    let guild_name = context.cache.get_guild(guild_id).await?.name;
+
+    let arguments = parse_graph_arguments(arguments)?;
+
+    let channel = match &arguments.channel {
+        Some(argument) => match resolve_channel_argument(context, guild_id, argument).await? {
+            Some(channel) => Some(channel),
+            None => {
+                let described = match argument {
+                    ChannelArgument::Id(channel_id) => format!("<#{}>", channel_id),
+                    ChannelArgument::Name(name) => format!("`#{}`", name),
+                };
+
+                invocation
+                    .reply_content(
+                        context,
+                        &format!(
+                            "I couldn't find {} in this server, \
+                            try mentioning the channel or checking its name.",
+                            described,
+                        ),
+                    )
+                    .await?;
+
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+
+    if !start_render(context, invocation, guild_id).await? {
+        return Ok(());
+    }
+
+    let attachment_base_name = match &channel {
+        Some(channel) => sanitize_name_for_attachment(&format!("{}-{}", guild_name, channel.name)),
+        None => sanitize_name_for_attachment(&guild_name),
+    };
+
+    let color_scheme = arguments.color_scheme;
+    let transparent = arguments.transparent;
+
+    let weight_threshold = match arguments.weight_threshold {
+        Some(weight_threshold) => {
+            context
+                .session
+                .update_graph_preferences(guild_id, |preferences| {
+                    preferences.weight_threshold = Some(weight_threshold);
+                });
+
+            weight_threshold
+        }
+        None => context
+            .session
+            .get_graph_preferences(guild_id)
+            .weight_threshold
+            .unwrap_or(0.0),
+    };
+
+    let palette = match arguments.palette {
+        Some(palette) => {
+            context
+                .session
+                .update_graph_preferences(guild_id, |preferences| {
+                    preferences.palette = Some(palette);
+                });
+
+            palette
+        }
+        None => context
+            .session
+            .get_graph_preferences(guild_id)
+            .palette
+            .unwrap_or_default(),
+    };
+
+    if arguments.mode == GraphMode::Channels {
+        let options = DotOptions {
+            color_scheme,
+            palette,
+            transparent,
+            layout: arguments.layout,
+            ..DotOptions::default()
+        };
+
+        return send_channel_graph(
+            context,
+            invocation,
+            guild_id,
+            attachment_base_name,
+            arguments.format,
+            &options,
+        )
+        .await;
+    }
+
+    let (selected_graph, author_metadata, graph_age) = {
+        let social = context.social.lock();
+        (
+            // A single channel or user's connections are quick enough to build while holding
+            // the lock.
+            match (&channel, arguments.user) {
+                (Some(channel), _) => Some(Some(social.build_channel_graph(guild_id, channel.id))),
+                (None, Some(user_id)) => {
+                    Some(social.build_ego_graph(guild_id, user_id, arguments.depth))
+                }
+                (None, None) => None,
+            },
+            social.get_node_metadata(guild_id, invocation.author.id),
+            social.get_graph_age(guild_id),
+        )
+    };
+
+    let mut graph = match selected_graph {
+        Some(Some(graph)) => graph,
+        Some(None) => {
+            invocation
+                .reply_content(
+                    context,
+                    &format!(
+                        "I haven't seen <@{}> interact with anyone yet.",
+                        arguments.user.unwrap(),
+                    ),
+                )
+                .await?;
+
+            return Ok(());
+        }
+        None => {
+            let snapshot = context.social.lock().get_graph_snapshot(guild_id);
+            snapshot.build_guild_graph()
+        }
+    };
+
+    if graph.is_empty() {
+        if let Some(channel) = &channel {
+            invocation
+                .reply_content(
+                    context,
+                    &format!("I haven't seen any interactions in <#{}> yet.", channel.id),
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        return send_no_interactions_reply(context, invocation).await;
+    }
+
+    // This is our own copy, so pruning it doesn't touch the live graph.
+    if arguments.no_isolated {
+        graph.prune_isolated_nodes();
+    }
+
+    if context.config.track_presence {
+        let social = context.social.lock();
+        social.presence.normalize(guild_id, &mut graph);
+    }
+
+    let guild_config = get_guild_config(context.pool.as_ref(), guild_id).await?;
+
+    // Gray out anyone who's been quiet this week.
+    let active_users = match &context.pool {
+        Some(pool) => Some(get_active_users_since(pool, guild_id, get_week_ago_ms()).await?),
+        None => None,
+    };
+
+    let options = DotOptions {
+        color_scheme,
+        palette,
+        transparent,
+        label_mode: LabelMode::DisplayName,
+        undirected: arguments.undirected,
+        weight_threshold,
+        node_attributes: guild_config.dot_node_template,
+        edge_attributes: guild_config.dot_edge_template,
+        min_degree: arguments.min_members,
+        layout: arguments.layout,
+        active_users,
+        include_bots: arguments.include_bots,
+        node_colors: arguments
+            .user
+            .map(|user_id| HashMap::from([(user_id, EGO_COLOR)]))
+            .unwrap_or_default(),
+    };
+
+    let dot = graph
+        .to_dot(context, guild_id, Some(invocation.author), &options)
+        .await?;
+
+    if arguments.mermaid {
+        let mermaid = graph.to_mermaid(context, guild_id, &options).await?;
+
+        send_mermaid_source(
+            context,
+            invocation,
+            attachment_base_name.clone() + ".mmd",
+            mermaid,
+        )
+        .await?;
+    }
+
+    let rendered = render_dot(context, &dot, arguments.format, arguments.layout).await?;
+
+    let description = author_metadata.map(|metadata| {
+        format!(
+            "Your last interaction was {}.",
+            format_relative_time(metadata.last_seen_ms)
+        )
+    });
+
+    if arguments.format != GraphFormat::Png {
+        return send_graph_file(
+            context,
+            invocation,
+            attachment_base_name + "." + arguments.format.name(),
+            rendered,
+            description,
+        )
+        .await;
+    }
+
+    let png = if transparent {
+        add_png_shadow(&rendered, color_scheme).await?
+    } else {
+        rendered
+    };
+
+    send_graph_image(
+        context,
+        invocation,
+        attachment_base_name + ".png",
+        png,
+        Some(get_density_color(graph.density())),
+        description,
+        graph_age.map(|age| {
+            format!(
+                "Based on {} of interaction data.",
+                format_long_duration(age)
+            )
+        }),
+    )
+    .await
+}
+
+/// Draw the guild's channels, linked by how many users they have in common.
+async fn send_channel_graph(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    guild_id: Id<GuildMarker>,
+    attachment_base_name: String,
+    format: GraphFormat,
+    options: &DotOptions,
+) -> Result<()> {
+    let edges = {
+        let social = context.social.lock();
+        social.channel_interaction_graph(guild_id)
+    };
+
+    let edges = match edges {
+        Some(edges) if !edges.is_empty() => edges,
+        _ => return send_no_interactions_reply(context, invocation).await,
+    };
+
+    let mut channel_ids: Vec<_> = edges.keys().flat_map(|&(a, b)| [a, b]).collect();
+    channel_ids.sort();
+    channel_ids.dedup();
+
+    let names = join_all(
+        channel_ids
+            .into_iter()
+            .map(|channel_id| context.cache.get_channel(channel_id)),
+    )
+    .await
+    .into_iter()
+    .filter_map(|channel| channel.ok())
+    .map(|channel| (channel.id, channel.name))
+    .collect();
+
+    let guild_config = get_guild_config(context.pool.as_ref(), guild_id).await?;
+
+    let options = DotOptions {
+        node_attributes: guild_config.dot_node_template,
+        edge_attributes: guild_config.dot_edge_template,
+        ..options.clone()
+    };
+
+    let dot = format_channel_dot(&edges, &names, &options)?;
+
+    let rendered = render_dot(context, &dot, format, options.layout).await?;
+
+    if format != GraphFormat::Png {
+        return send_graph_file(
+            context,
+            invocation,
+            attachment_base_name + "-channels." + format.name(),
+            rendered,
+            None,
+        )
+        .await;
+    }
+
+    let png = if options.transparent {
+        add_png_shadow(&rendered, options.color_scheme).await?
+    } else {
+        rendered
+    };
+
+    send_graph_image(
+        context,
+        invocation,
+        attachment_base_name + "-channels.png",
+        png,
+        None,
+        None,
+        None,
+    )
+    .await
+}
+
+/// Reply for commands that need a graph in a guild we haven't seen any interactions in yet.
+async fn send_no_interactions_reply(context: &Context, invocation: &Invocation<'_>) -> Result<()> {
+    invocation
+        .reply_content(
+            context,
+            "I haven't seen any interactions in this server yet, \
+            try again once people have been chatting for a while.",
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Start the guild's render cooldown, or tell them how long is left on it and return `false`.
+async fn start_render(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    guild_id: Id<GuildMarker>,
+) -> Result<bool> {
+    let remaining = match context.rate_limiter.try_start_render(guild_id) {
+        Ok(()) => return Ok(true),
+        Err(remaining) => remaining,
+    };
+
+    // Round up, so we never say to wait 0 seconds.
+    let remaining = Duration::from_secs(remaining.as_secs() + 1);
+
+    invocation
+        .reply_content(
+            context,
+            &format!(
+                "A graph was recently generated in this server, please wait {}.",
+                format_duration(remaining),
+            ),
+        )
+        .await?;
+
+    Ok(false)
+}
+
+/// Tell them if any of `user_ids` have opted out of tracking and return `false`, so commands
+/// about particular users don't show anything about them.
+async fn check_not_opted_out(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    guild_id: Id<GuildMarker>,
+    user_ids: &[Id<UserMarker>],
+) -> Result<bool> {
+    let opted_out = {
+        let social = context.social.lock();
+        user_ids
+            .iter()
+            .copied()
+            .find(|&user_id| social.is_opted_out(guild_id, user_id))
+    };
+
+    let user_id = match opted_out {
+        Some(user_id) => user_id,
+        None => return Ok(true),
+    };
+
+    invocation
+        .reply_content(
+            context,
+            &format!(
+                "<@{}> has opted out of having their interactions tracked.",
+                user_id
+            ),
+        )
+        .await?;
+
+    Ok(false)
+}
+
+/// Post Mermaid source as a code block, or as a file if it's too long for a message.
+async fn send_mermaid_source(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    attachment_name: String,
+    source: String,
+) -> Result<()> {
+    const MESSAGE_LENGTH_LIMIT: usize = 2000;
+
+    let content = format!("```mermaid\n{}\n```", source);
+
+    if content.chars().count() <= MESSAGE_LENGTH_LIMIT {
+        invocation.reply_content(context, &content).await?;
+    } else {
+        invocation
+            .reply(
+                context,
+                Some("The Mermaid source is too long for a message, so it's attached instead."),
+                &[],
+                &[Attachment::from_bytes(
+                    attachment_name,
+                    source.into_bytes(),
+                    0,
+                )],
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Reply with a rendered graph as a plain file, for formats an embed can't show.
+///
+/// twilight doesn't let us set an attachment's content type, Discord goes by the file
+/// extension, so `attachment_name` needs the right one.
+async fn send_graph_file(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    attachment_name: String,
+    file: Vec<u8>,
+    description: Option<String>,
+) -> Result<()> {
+    invocation
+        .reply(
+            context,
+            description.as_deref(),
+            &[],
+            &[Attachment::from_bytes(attachment_name, file, 0)],
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Reply with a rendered graph in an embed, with an optional note after the usual footer.
+async fn send_graph_image(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    attachment_name: String,
+    png: Vec<u8>,
+    color: Option<u32>,
+    description: Option<String>,
+    footer_note: Option<String>,
+) -> Result<()> {
+    let mut footer = get_command_footer(invocation.author);
+    if let Some(footer_note) = footer_note {
+        footer.text = format!("{}\n{}", footer_note, footer.text);
+    }
+
+    let embed = Embed {
+        author: None,
+        color,
+        description,
+        fields: Vec::new(),
+        footer: Some(footer),
+        image: Some(EmbedImage {
+            height: None,
+            proxy_url: None,
+            url: format!("attachment://{}", attachment_name),
+            width: None,
+        }),
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: None,
+        url: None,
+        video: None,
+    };
+
+    invocation
+        .reply(
+            context,
+            None,
+            &[embed],
+            &[Attachment::from_bytes(attachment_name, png, 0)],
+        )
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Serialize)]
+struct ClusteringStats {
+    /// The mean cluster coefficient of the guild's users.
+    average: f64,
+    /// The cluster coefficient of the user who ran the command.
+    user: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct StatsReport {
+    cache: CacheStats,
+    graph: Option<GraphSummary>,
+    member_count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clustering: Option<ClusteringStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasons: Option<HashMap<RelationshipChangeReason, u64>>,
+}
+
+/// Each reason's share of the interactions, most common first, like `45% reply, 32% mention`.
+fn format_reason_distribution(distribution: &HashMap<RelationshipChangeReason, u64>) -> String {
+    let total: u64 = distribution.values().sum();
+    if total == 0 {
+        return "None yet".to_string();
+    }
+
+    // Several reasons share a name, like the two kinds of mention.
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for (reason, count) in distribution {
+        *counts.entry(reason.to_string()).or_default() += count;
+    }
+
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(a_name, a_count), (b_name, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_name.cmp(b_name))
+    });
+
+    counts
+        .iter()
+        .map(|(name, count)| format!("{:.0}% {}", *count as f64 * 100.0 / total as f64, name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+async fn command_stats(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    arguments: Arguments<'_>,
+) -> Result<()> {
+    let mut json = false;
+    let mut clustering = false;
+    let mut breakdown = false;
+
+    for argument in arguments {
+        match argument {
+            "--format=json" => json = true,
+            "--format=text" => json = false,
+            "--clustering" => clustering = true,
+            "--breakdown" => breakdown = true,
+            value => anyhow::bail!(
+                "{} is not a recognized option, expected \"--format=json\", \"--format=text\", \"--clustering\" or \"--breakdown\"",
+                value,
+            ),
+        }
+    }
+
+    let member_count = match invocation.guild_id {
+        Some(guild_id) => context.cache.get_guild(guild_id).await?.member_count,
+        None => None,
+    };
+
+    let graph = match invocation.guild_id {
+        Some(guild_id) => {
+            let social = context.social.lock();
+            social.summarize(guild_id)
+        }
+        None => None,
+    };
+
+    let clustering = match invocation.guild_id {
+        Some(guild_id) if clustering => {
+            let social = context.social.lock();
+            Some(ClusteringStats {
+                average: social.average_cluster_coefficient(guild_id),
+                user: social.cluster_coefficient(guild_id, invocation.author.id),
+            })
+        }
+        _ => None,
+    };
+
+    let reasons = match (invocation.guild_id, &context.pool) {
+        (Some(guild_id), Some(pool)) if breakdown => {
+            Some(get_reason_distribution(pool, guild_id).await?)
+        }
+        _ => None,
+    };
+
+    // Only show this guild's share of the cache, other guilds' IDs aren't for sharing.
+    let mut cache_stats = context.cache.get_stats().await;
+    cache_stats
+        .per_guild
+        .retain(|&guild_id, _| Some(guild_id) == invocation.guild_id);
+
+    if !json {
+        let mut content = format!("{:?}", cache_stats);
+
+        content.push_str(&format!(
+            "\nShard: {} of {}",
+            context.shard.number(),
+            context.shard.total(),
+        ));
+
+        if let Some(member_count) = member_count {
+            content.push_str(&format!("\nMembers: {}", member_count));
+        }
+
+        if let (Some(guild_id), Some(graph)) = (invocation.guild_id, &graph) {
+            content.push_str(&format!(
+                "\nGraph: {} users, {} connections, {:.1}% density",
+                graph.node_count,
+                graph.edge_count,
+                graph.density * 100.0,
+            ));
+
+            if graph.last_interaction_ms > 0 {
+                content.push_str(&format!(
+                    "\nLast interaction: {}",
+                    format_relative_time(graph.last_interaction_ms),
+                ));
+            }
+
+            if !graph.top_users.is_empty() {
+                let names = join_all(
+                    graph
+                        .top_users
+                        .iter()
+                        .map(|&(user_id, _)| get_display_name(context, guild_id, user_id)),
+                )
+                .await;
+
+                let names: Vec<_> = names
+                    .into_iter()
+                    .zip(&graph.top_users)
+                    .map(|(name, &(user_id, _))| name.unwrap_or_else(|_| user_id.to_string()))
+                    .collect();
+
+                content.push_str(&format!("\nMost connected: {}", names.join(", ")));
+            }
+
+            if graph.node_count > 0 {
+                let diameter = {
+                    let social = context.social.lock();
+                    social.graph_diameter(guild_id)
+                };
+
+                let distance = match diameter {
+                    Some(1) => "1 hop".to_string(),
+                    Some(hops) => format!("{} hops", hops),
+                    None => "unlimited, not everyone is connected".to_string(),
+                };
+
+                content.push_str(&format!("\nMaximum social distance: {}", distance));
+            }
+        }
+
+        if let Some(clustering) = &clustering {
+            content.push_str(&format!(
+                "\nClustering: {:.3} on average, {:.3} for you",
+                clustering.average, clustering.user,
+            ));
+        }
+
+        if let Some(reasons) = &reasons {
+            content.push_str(&format!(
+                "\nInteractions: {}",
+                format_reason_distribution(reasons),
+            ));
+        }
+
+        if let (Some(guild_id), Some(pool)) = (invocation.guild_id, &context.pool) {
+            let most_active_channel =
+                describe_most_active_channel(context, pool, guild_id, get_week_ago_ms()).await?;
+
+            if let Some(most_active_channel) = most_active_channel {
+                content.push_str(&format!(
+                    "\nMost active channel this week: {}",
+                    most_active_channel,
+                ));
+            }
+        }
+
+        invocation.reply_content(context, &content).await?;
+
+        return Ok(());
+    }
+
+    let report = StatsReport {
+        cache: cache_stats,
+        graph,
+        member_count,
+        clustering,
+        reasons,
+    };
+
+    let contents = serde_json::to_vec_pretty(&report)?;
+
+    invocation
+        .reply(
+            context,
+            None,
+            &[],
+            &[Attachment::from_bytes("stats.json".to_owned(), contents, 0)],
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn command_history(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    const HISTORY_LIMIT: usize = 20;
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("interaction history requires a database")?;
+
+    let mut next_user = || {
+        arguments
+            .next()
+            .and_then(parse_user_argument)
+            .context("expected two users, like `history @alice @bob`")
+    };
+
+    let user_a = next_user()?;
+    let user_b = next_user()?;
+
+    if !check_not_opted_out(context, &message.into(), guild_id, &[user_a, user_b]).await? {
+        return Ok(());
+    }
+
+    let history = get_interaction_history(pool, guild_id, user_a, user_b).await?;
+
+    let description = match (history.first(), history.last()) {
+        (Some(&(first, _)), Some(&(last, _))) => {
+            let mut lines = vec![format!(
+                "{} interactions between <@{}> and <@{}>, first <t:{}:D>, most recently {}.\n",
+                history.len(),
+                user_a,
+                user_b,
+                first / 1000,
+                format_relative_time(last),
+            )];
+
+            let skip = history.len().saturating_sub(HISTORY_LIMIT);
+            for &(timestamp, reason) in &history[skip..] {
+                let reason = match RelationshipChangeReason::try_from(reason) {
+                    Ok(reason) => reason.to_string(),
+                    Err(_) => format!("unknown ({})", reason),
+                };
+
+                lines.push(format!("<t:{}:f>\u{2000}{}", timestamp / 1000, reason));
+            }
+
+            lines.join("\n")
+        }
+        _ => format!(
+            "There are no recorded interactions between <@{}> and <@{}>.",
+            user_a, user_b,
+        ),
+    };
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(description),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some("Interaction history".to_string()),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+/// Join lines until the next one would overflow an embed field, noting how many were left out.
+fn join_field_lines(lines: &[String], separator: &str) -> String {
+    const FIELD_LIMIT: usize = 1024;
+
+    let mut value = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        let remaining = lines.len() - i;
+        let more = format!("{}and {} more", separator, remaining);
+
+        // Leave room to say how many were left out, unless this is the last line.
+        let reserved = if remaining > 1 { more.len() } else { 0 };
+        if value.len() + separator.len() + line.len() + reserved > FIELD_LIMIT {
+            value.push_str(&more);
+            break;
+        }
+
+        if !value.is_empty() {
+            value.push_str(separator);
+        }
+        value.push_str(line);
+    }
+
+    value
+}
+
+/// Milliseconds since the epoch, a week ago.
+fn get_week_ago_ms() -> u64 {
+    const WEEK_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+    get_current_timestamp_ms().saturating_sub(WEEK_MS)
+}
+
+async fn describe_most_active_channel(
+    context: &Context,
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+) -> Result<Option<String>> {
+    let (channel_id, count) = match get_most_active_channel(pool, guild_id, since_ms).await? {
+        Some(channel) => channel,
+        None => return Ok(None),
+    };
+
+    let channel_name = match context.cache.get_channel(channel_id).await {
+        Ok(channel) => format!("#{}", channel.name),
+        Err(_) => format!("<#{}>", channel_id),
+    };
+
+    Ok(Some(format!("{} ({} interactions)", channel_name, count)))
+}
+
+async fn command_report(context: &Context, message: &Message) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("reports require a database")?;
+
+    let since_ms = get_week_ago_ms();
+
+    let new_connections = get_new_connections_since(pool, guild_id, since_ms).await?;
+    let growth_rate = network_growth_rate(pool, guild_id, 7).await?;
+    let velocity = get_interaction_velocity(pool, guild_id, 7).await?;
+    let previous_velocity = get_previous_interaction_velocity(pool, guild_id, 7).await?;
+
+    let new_connections_field = EmbedField {
+        inline: false,
+        name: "New connections this week".to_string(),
+        value: if new_connections.is_empty() {
+            "None yet, go say hello to someone!".to_string()
+        } else {
+            let pairs: Vec<_> = new_connections
+                .iter()
+                .map(|(a, b)| format!("<@{}> \u{2194} <@{}>", a, b))
+                .collect();
+
+            join_field_lines(&pairs, ", ")
+        },
+    };
+
+    let most_active_channel_field = EmbedField {
+        inline: false,
+        name: "Most active channel this week".to_string(),
+        value: describe_most_active_channel(context, pool, guild_id, since_ms)
+            .await?
+            .unwrap_or_else(|| "No interactions yet.".to_string()),
+    };
+
+    let guild_name = context.cache.get_guild(guild_id).await?.name;
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(format!(
+            "Network grew by {:.1}% this week.\n{}",
+            growth_rate * 100.0,
+            format_velocity(velocity, previous_velocity),
+        )),
+        fields: vec![new_connections_field, most_active_channel_field],
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(format!("Weekly report for {}", guild_name)),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+/// The member's nickname, falling back to their username.
+async fn get_display_name(
+    context: &Context,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<String> {
+    let user = context.cache.get_user(user_id).await?;
+
+    let nick = match context.cache.get_member(guild_id, user_id).await {
+        Ok(member) => member.nick,
+        Err(_) => None,
+    };
+
+    Ok(nick.unwrap_or(user.name))
+}
+
+async fn command_community(context: &Context, message: &Message) -> Result<()> {
+    const COMMUNITY_COUNT: usize = 5;
+    const NAMED_MEMBER_COUNT: usize = 2;
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+
+    let communities = {
+        let social = context.social.lock();
+        social.get_communities_by_size(guild_id)
+    };
+
+    let mut lines = Vec::new();
+    for (i, community) in communities.iter().take(COMMUNITY_COUNT).enumerate() {
+        let name_futures = community
+            .iter()
+            .take(NAMED_MEMBER_COUNT)
+            .map(|&user_id| get_display_name(context, guild_id, user_id));
+
+        let mut names: Vec<_> = join_all(name_futures)
+            .await
+            .into_iter()
+            .zip(community)
+            .map(|(name, user_id)| name.unwrap_or_else(|_| format!("<@{}>", user_id)))
+            .collect();
+
+        if community.len() > NAMED_MEMBER_COUNT {
+            names.push(format!("+{} more", community.len() - NAMED_MEMBER_COUNT));
+        }
+
+        lines.push(format!(
+            "Community {}: {} members ({})",
+            i + 1,
+            community.len(),
+            names.join(", "),
+        ));
+    }
+
+    if lines.is_empty() {
+        lines.push("No communities yet, go say hello to someone!".to_string());
+    }
+
+    let guild_name = context.cache.get_guild(guild_id).await?.name;
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(lines.join("\n")),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(format!("Largest communities in {}", guild_name)),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_network(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+
+    let role_id = arguments
+        .next()
+        .and_then(parse_role_argument)
+        .context("expected a role, like `network @Moderators`")?;
+
+    let role = context.cache.get_role(guild_id, role_id).await?;
+
+    if !start_render(context, &message.into(), guild_id).await? {
+        return Ok(());
+    }
+
+    // Without the members intent the member cache only has people we've seen recently, so look
+    // up everyone in the graph instead.
+    let graph_user_ids: Vec<_> = {
+        let social = context.social.lock();
+        social.to_adjacency_list(guild_id).into_keys().collect()
+    };
+
+    let user_ids: HashSet<_> = context
+        .cache
+        .get_or_fetch_members(guild_id, &graph_user_ids)
+        .await
+        .into_iter()
+        .filter(|(_, member)| member.roles.contains(&role_id))
+        .map(|(user_id, _)| user_id)
+        .collect();
+
+    let graph = {
+        let social = context.social.lock();
+        social.get_subgraph(guild_id, &user_ids)
+    };
+
+    let graph = match graph {
+        graph if !graph.is_empty() => graph,
+        _ => {
+            context
+                .http
+                .create_message(message.channel_id)
+                .content(&format!(
+                    "No interaction data for members of @{}.",
+                    role.name
+                ))?
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let guild_config = get_guild_config(context.pool.as_ref(), guild_id).await?;
+
+    let options = DotOptions {
+        weight_threshold: context
+            .session
+            .get_graph_preferences(guild_id)
+            .weight_threshold
+            .unwrap_or(0.0),
+        node_attributes: guild_config.dot_node_template,
+        edge_attributes: guild_config.dot_edge_template,
+        ..DotOptions::default()
+    };
+
+    let dot = graph
+        .to_dot(context, guild_id, Some(&message.author), &options)
+        .await?;
+
+    let png = render_dot(context, &dot, GraphFormat::Png, None).await?;
+
+    let attachment_name = sanitize_name_for_attachment(&role.name) + ".png";
+
+    let embed = Embed {
+        author: None,
+        color: Some(role.color).filter(|&color| color != 0),
+        description: None,
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: Some(EmbedImage {
+            height: None,
+            proxy_url: None,
+            url: format!("attachment://{}", attachment_name),
+            width: None,
+        }),
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(format!("Network of @{}", role.name)),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .attachments(&[Attachment::from_bytes(attachment_name, png, 0)])?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_compare(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    const FIRST_COLOR: u32 = 0x3498DB;
+    const SECOND_COLOR: u32 = 0xE74C3C;
+    const SHARED_COLOR: u32 = 0x9B59B6;
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+
+    let mut next_user = || {
+        arguments
+            .next()
+            .and_then(parse_user_argument)
+            .context("expected two users, like `compare @alice @bob`")
+    };
+
+    let user_a = next_user()?;
+    let user_b = next_user()?;
+
+    if user_a == user_b {
+        anyhow::bail!("expected two different users");
+    }
+
+    if !check_not_opted_out(context, &message.into(), guild_id, &[user_a, user_b]).await? {
+        return Ok(());
+    }
+
+    if !start_render(context, &message.into(), guild_id).await? {
+        return Ok(());
+    }
+
+    let snapshot = {
+        let social = context.social.lock();
+        social.get_graph_snapshot(guild_id)
+    };
+
+    let graph = snapshot.build_guild_graph();
+    if graph.is_empty() {
+        return send_no_interactions_reply(context, &message.into()).await;
+    }
+
+    let first = graph.ego_graph(user_a, 1);
+    let second = graph.ego_graph(user_b, 1);
+    let (merged, shared) = SocialGraph::merge_subgraphs(&first, &second);
+
+    let mut node_colors = HashMap::new();
+    for (ego, color) in [(&first, FIRST_COLOR), (&second, SECOND_COLOR)] {
+        for &(source, target) in ego.keys() {
+            node_colors.insert(source, color);
+            node_colors.insert(target, color);
+        }
+    }
+
+    for node in &shared {
+        node_colors.insert(node.user_id, SHARED_COLOR);
+    }
+
+    // The users being compared always keep their own color, even when they're connected.
+    node_colors.insert(user_a, FIRST_COLOR);
+    node_colors.insert(user_b, SECOND_COLOR);
+
+    let guild_config = get_guild_config(context.pool.as_ref(), guild_id).await?;
+
+    let options = DotOptions {
+        weight_threshold: context
+            .session
+            .get_graph_preferences(guild_id)
+            .weight_threshold
+            .unwrap_or(0.0),
+        node_attributes: guild_config.dot_node_template,
+        edge_attributes: guild_config.dot_edge_template,
+        node_colors,
+        ..DotOptions::default()
+    };
+
+    let dot = merged.to_dot(context, guild_id, None, &options).await?;
+
+    let png = render_dot(context, &dot, GraphFormat::Png, None).await?;
+
+    let shared_count = shared
+        .iter()
+        .filter(|node| node.user_id != user_a && node.user_id != user_b)
+        .count();
+
+    let attachment_name = "compare.png".to_string();
+
+    let embed = Embed {
+        author: None,
+        color: Some(SHARED_COLOR),
+        description: Some(format!(
+            "<@{}>'s connections in blue, <@{}>'s in red, and the {} they share in purple.",
+            user_a, user_b, shared_count,
+        )),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: Some(EmbedImage {
+            height: None,
+            proxy_url: None,
+            url: format!("attachment://{}", attachment_name),
+            width: None,
+        }),
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: None,
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .attachments(&[Attachment::from_bytes(attachment_name, png, 0)])?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_simulate(
+    context: &Context,
+    message: &Message,
+    arguments: Arguments<'_>,
+) -> Result<()> {
+    const DEFAULT_INTERACTIONS: u64 = 10;
+    const MAX_INTERACTIONS: u64 = 10_000;
+    const DEFAULT_PERIOD: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("simulations require a database")?;
+
+    let mut users = Vec::new();
+    let mut interactions = DEFAULT_INTERACTIONS;
+    let mut period = DEFAULT_PERIOD;
+
+    for argument in arguments {
+        if let Some(value) = argument.strip_prefix("--interactions=") {
+            interactions = value
+                .parse()
+                .with_context(|| format!("{} is not a valid number of interactions", value))?;
+            interactions = interactions.min(MAX_INTERACTIONS);
+        } else if let Some(value) = argument.strip_prefix("--period=") {
+            period = parse_duration_argument(value)?;
+        } else {
+            users.push(
+                parse_user_argument(argument)
+                    .with_context(|| format!("{} is not a recognized user or option", argument))?,
+            );
+        }
+    }
+
+    let (user_a, user_b) = match users[..] {
+        [user_a, user_b] => (user_a, user_b),
+        _ => anyhow::bail!("expected two users, like `simulate @alice @bob --interactions=10`"),
+    };
+
+    if !check_not_opted_out(context, &message.into(), guild_id, &[user_a, user_b]).await? {
+        return Ok(());
+    }
+
+    let (current, projected) = simulate_pair_weight(
+        pool,
+        &context.social,
+        guild_id,
+        (user_a, user_b),
+        period,
+        interactions,
+    )
+    .await?;
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(format!(
+            "Current: {:.1} \u{2192} Projected ({}, +{} interactions): {:.1}",
+            current,
+            format_duration(period),
+            interactions,
+            projected,
+        )),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some("Connection forecast".to_string()),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_strength(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+
+    let mut next_user = || {
+        arguments
+            .next()
+            .and_then(parse_user_argument)
+            .context("expected two users, like `strength @alice @bob`")
+    };
+
+    let user_a = next_user()?;
+    let user_b = next_user()?;
+
+    if !check_not_opted_out(context, &message.into(), guild_id, &[user_a, user_b]).await? {
+        return Ok(());
+    }
+
+    let (aggregation, aggregation_name) = match arguments.next() {
+        Some("minimum") | None => (PathWeightAggregation::Minimum, "weakest link"),
+        Some("product") => (PathWeightAggregation::Product, "product"),
+        Some("sum") => (PathWeightAggregation::Sum, "total"),
+        Some(value) => anyhow::bail!(
+            "{} is not a recognized aggregation, expected \"minimum\", \"product\" or \"sum\"",
+            value,
+        ),
+    };
+
+    let (snapshot, path_weight) = {
+        let social = context.social.lock();
+        (
+            social.get_graph_snapshot(guild_id),
+            social.get_path_weight(guild_id, user_a, user_b, aggregation),
+        )
+    };
+
+    let graph = snapshot.build_guild_graph();
+    if graph.is_empty() {
+        return send_no_interactions_reply(context, &message.into()).await;
+    }
+
+    let mut lines = vec![match graph.get_pair_weight(user_a, user_b) {
+        Some(weight) => format!("Direct connection: {:.2}", weight),
+        None => "No direct connection.".to_string(),
+    }];
+
+    match graph.get_path(user_a, user_b) {
+        Some(path) if path.len() > 2 => {
+            let hops = path
+                .iter()
+                .map(|user_id| format!("<@{}>", user_id))
+                .collect::<Vec<_>>()
+                .join(" \u{2192} ");
+
+            lines.push(format!(
+                "Shortest path: {} ({} hops, {} {:.2})",
+                hops,
+                path.len() - 1,
+                aggregation_name,
+                path_weight.unwrap_or_default(),
+            ));
+        }
+        Some(_) => (),
+        None => lines.push("Not connected, even indirectly.".to_string()),
+    }
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(lines.join("\n")),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some("Connection strength".to_string()),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum LeaderboardRanking {
+    Received,
+    Sent,
+    /// By `user_activity_scores`, which combines both with recency and variety.
+    Score,
+}
+
+async fn command_leaderboard(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    const LEADERBOARD_SIZE: u32 = 10;
+    const MEDALS: [&str; 3] = ["\u{1F947}", "\u{1F948}", "\u{1F949}"];
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("leaderboards require a database")?;
+
+    let ranking = match arguments.next() {
+        Some("--sent") => LeaderboardRanking::Sent,
+        Some("--score") => LeaderboardRanking::Score,
+        Some(value) => anyhow::bail!(
+            "{} is not a recognized option, expected \"--sent\" or \"--score\"",
+            value,
+        ),
+        None => LeaderboardRanking::Received,
+    };
+
+    let leaderboard: Vec<_> = match ranking {
+        LeaderboardRanking::Received | LeaderboardRanking::Sent => {
+            let sent = ranking == LeaderboardRanking::Sent;
+
+            let counts =
+                get_interaction_leaderboard(pool, guild_id, sent, LEADERBOARD_SIZE).await?;
+
+            // The graph's weights decay, so they show who's still connected, not just who was.
+            let social = context.social.lock();
+            counts
+                .into_iter()
+                .map(|(user_id, count)| {
+                    let weight = if sent {
+                        social.out_degree(guild_id, user_id)
+                    } else {
+                        social.in_degree(guild_id, user_id)
+                    };
+
+                    (
+                        user_id,
+                        format!("{} interactions, {:.1} weight now", count, weight),
+                    )
+                })
+                .collect()
+        }
+        LeaderboardRanking::Score => user_activity_scores(pool, &context.social, guild_id)
+            .await?
+            .into_iter()
+            .take(LEADERBOARD_SIZE as usize)
+            .map(|(user_id, score)| (user_id, format!("{:.0} activity score", score)))
+            .collect(),
+    };
+
+    let name_futures = leaderboard
+        .iter()
+        .map(|&(user_id, _)| get_display_name(context, guild_id, user_id));
+
+    let lines: Vec<_> = join_all(name_futures)
+        .await
+        .into_iter()
+        .zip(&leaderboard)
+        .enumerate()
+        .map(|(i, (name, (user_id, value)))| {
+            let name = name.unwrap_or_else(|_| format!("<@{}>", user_id));
+            let rank = match MEDALS.get(i) {
+                Some(medal) => medal.to_string(),
+                None => format!("{}.", i + 1),
+            };
+
+            format!("{} {} \u{2014} {}", rank, name, value)
+        })
+        .collect();
+
+    let description = if lines.is_empty() {
+        "No interactions yet.".to_string()
+    } else {
+        lines.join("\n")
+    };
+
+    let guild_name = context.cache.get_guild(guild_id).await?.name;
+    let title = match ranking {
+        LeaderboardRanking::Received => format!("Most interactions received in {}", guild_name),
+        LeaderboardRanking::Sent => format!("Most interactions sent in {}", guild_name),
+        LeaderboardRanking::Score => format!("Most active in {}", guild_name),
+    };
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(description),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(title),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_top_channels(
+    context: &Context,
+    message: &Message,
+    arguments: Arguments<'_>,
+) -> Result<()> {
+    const DEFAULT_LIMIT: u32 = 10;
+    const MAX_LIMIT: u32 = 25;
+    const DEFAULT_WINDOW: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("channel rankings require a database")?;
+
+    let mut limit = DEFAULT_LIMIT;
+    let mut window = DEFAULT_WINDOW;
+
+    for argument in arguments {
+        match argument.strip_prefix("--since=") {
+            Some(value) => window = parse_duration_argument(value)?,
+            None => {
+                limit = argument
+                    .parse()
+                    .with_context(|| format!("{} is not a valid number of channels", argument))?;
+                limit = limit.clamp(1, MAX_LIMIT);
+            }
+        }
+    }
+
+    let window_ms = window.as_millis() as u64;
+    let now_ms = get_current_timestamp_ms();
+    let since_ms = now_ms.saturating_sub(window_ms);
+
+    let channels = get_top_channels(pool, guild_id, since_ms, now_ms, limit).await?;
+
+    // Compare against the same length of time just before, to show which way each is going.
+    let channel_ids: Vec<_> = channels.iter().map(|&(channel_id, _)| channel_id).collect();
+    let previous = get_channel_interaction_counts(
+        pool,
+        guild_id,
+        &channel_ids,
+        since_ms.saturating_sub(window_ms),
+        since_ms,
+    )
+    .await?;
+
+    let names = join_all(
+        channel_ids
+            .iter()
+            .map(|&channel_id| context.cache.get_channel(channel_id)),
+    )
+    .await;
+
+    let lines: Vec<_> = channels
+        .iter()
+        .zip(names)
+        .enumerate()
+        .map(|(i, (&(channel_id, count), channel))| {
+            let name = match channel {
+                Ok(channel) => format!("#{}", channel.name),
+                Err(_) => format!("<#{}>", channel_id),
+            };
+
+            let previous = previous.get(&channel_id).copied().unwrap_or_default();
+
+            format!(
+                "{}. {} \u{2014} {} interactions {}",
+                i + 1,
+                name,
+                count,
+                get_trend_arrow(count, previous),
+            )
+        })
+        .collect();
+
+    let description = if lines.is_empty() {
+        "No interactions yet.".to_string()
+    } else {
+        lines.join("\n")
+    };
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(description),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(format!(
+            "Busiest channels in the last {}",
+            format_duration(window),
+        )),
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+/// An arrow showing whether a count went up, down, or stayed the same.
+fn get_trend_arrow(current: u64, previous: u64) -> char {
+    match current.cmp(&previous) {
+        Ordering::Greater => '\u{2191}',
+        Ordering::Less => '\u{2193}',
+        Ordering::Equal => '\u{2192}',
+    }
+}
+
+/// Describe a week's interactions per day, and how it changed from the week before.
+fn format_velocity(velocity: f64, previous_velocity: f64) -> String {
+    let average = format!("Average: {:.0} interactions/day this week", velocity);
+
+    if previous_velocity <= 0.0 {
+        return average;
+    }
+
+    let change = (velocity - previous_velocity) / previous_velocity * 100.0;
+    let arrow = match velocity.partial_cmp(&previous_velocity) {
+        Some(Ordering::Greater) => '\u{2191}',
+        Some(Ordering::Less) => '\u{2193}',
+        _ => '\u{2192}',
+    };
+
+    format!("{} ({} {:.0}% vs last week)", average, arrow, change.abs())
+}
+
+/// Parse a length of time like `12h`, `30d`, or `2w`.
+fn parse_duration_argument(value: &str) -> Result<Duration> {
+    const HOUR: u64 = 60 * 60;
+
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .with_context(|| format!("{} is missing a unit, like 30d", value))?;
+    let (count, unit) = value.split_at(split);
+
+    let count: u64 = count
+        .parse()
+        .with_context(|| format!("{} is not a valid length of time", value))?;
+
+    let unit_secs = match unit {
+        "h" => HOUR,
+        "d" => 24 * HOUR,
+        "w" => 7 * 24 * HOUR,
+        _ => anyhow::bail!(
+            "{} is not a recognized unit, expected \"h\", \"d\" or \"w\"",
+            unit,
+        ),
+    };
+
+    if count == 0 {
+        anyhow::bail!("the length of time can't be zero");
+    }
+
+    Ok(Duration::from_secs(count.saturating_mul(unit_secs)))
+}
+
+/// Draw counts as a bar chart one character wide per count, scaled to the largest.
+/// Zero counts are left blank so quiet periods stand out.
+fn format_sparkline(counts: &[u64]) -> String {
+    const BLOCKS: [char; 8] = [
+        '\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}', '\u{2585}', '\u{2586}', '\u{2587}',
+        '\u{2588}',
+    ];
+
+    let max = counts.iter().copied().max().unwrap_or_default();
+
+    counts
+        .iter()
+        .map(|&count| {
+            if count == 0 {
+                return ' ';
+            }
+
+            let level = (count * BLOCKS.len() as u64).div_ceil(max);
+            BLOCKS[level as usize - 1]
+        })
+        .collect()
+}
+
+async fn command_active(context: &Context, message: &Message) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("activity counts require a database")?;
+
+    let active_users = get_active_users_since(pool, guild_id, get_week_ago_ms()).await?;
+
+    let snapshot = {
+        let social = context.social.lock();
+        social.get_graph_snapshot(guild_id)
+    };
+
+    let tracked_users = snapshot.build_guild_graph().get_users();
+    if tracked_users.is_empty() {
+        return send_no_interactions_reply(context, &message.into()).await;
+    }
+
+    let active_count = tracked_users.intersection(&active_users).count();
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .content(&format!(
+            "{} out of {} tracked users were active in the last 7 days.",
+            active_count,
+            tracked_users.len(),
+        ))?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_activity(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    const WEEK_COUNT: u64 = 52;
+    const WEEK_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("activity charts require a database")?;
+
+    let user_id = arguments
+        .next()
+        .and_then(parse_user_argument)
+        .context("expected a user, like `activity @alice`")?;
+
+    if !check_not_opted_out(context, &message.into(), guild_id, &[user_id]).await? {
+        return Ok(());
+    }
+
+    let current_week = get_current_timestamp_ms() / WEEK_MS;
+    let first_week = current_week + 1 - WEEK_COUNT;
+
+    let activity = get_weekly_activity(pool, guild_id, user_id, first_week * WEEK_MS).await?;
+
+    let mut counts = vec![0; WEEK_COUNT as usize];
+    for (week, count) in activity {
+        if let Some(slot) = week
+            .checked_sub(first_week)
+            .and_then(|index| counts.get_mut(index as usize))
+        {
+            *slot = count;
+        }
+    }
+
+    let total: u64 = counts.iter().sum();
+
+    let score = user_activity_score(pool, &context.social, guild_id, user_id).await?;
+
+    let description = if total == 0 {
+        format!(
+            "<@{}> hasn't had any interactions in the last year.",
+            user_id
+        )
+    } else {
+        format!(
+            "Interactions involving <@{}> each week over the last year, oldest first.\n```\n{}\n```\n{} in total, and at most {} in a week. Activity score: {:.0}/100.",
+            user_id,
+            format_sparkline(&counts),
+            total,
+            counts.iter().max().unwrap_or(&0),
+            score,
+        )
+    };
+
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: Some(description),
+        fields: Vec::new(),
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: None,
+        url: None,
+        video: None,
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
+
+    Ok(())
+}
+
+/// Commands that don't show anything about who talks to who, so anyone can run them.
+const PUBLIC_COMMANDS: [&str; 4] = ["help", "invite", "ping", "opt-out"];
+
+/// Whether the user may run the command. Those not in `PUBLIC_COMMANDS` need Manage Server,
+/// unless it's a bot owner or they're not in a server.
+async fn is_allowed_to_run(
+    context: &Context,
+    command: &str,
+    guild_id: Option<Id<GuildMarker>>,
+    user_id: Id<UserMarker>,
+) -> Result<bool> {
+    if PUBLIC_COMMANDS.contains(&command) || context.owners.contains(&user_id) {
+        return Ok(true);
+    }
+
+    match guild_id {
+        Some(guild_id) => {
+            has_guild_permission(context, guild_id, user_id, Permissions::MANAGE_GUILD).await
+        }
+        None => Ok(true),
+    }
+}
+
+/// Whether the user has `permission` in the guild, through ownership or their roles.
+async fn has_guild_permission(
+    context: &Context,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    permission: Permissions,
+) -> Result<bool> {
+    let guild = context.cache.get_guild(guild_id).await?;
+    if guild.owner_id == user_id {
+        return Ok(true);
+    }
+
+    let member = context.cache.get_member(guild_id, user_id).await?;
+
+    // The @everyone role shares the guild's ID.
+    let role_futures = member
+        .roles
+        .iter()
+        .copied()
+        .chain(std::iter::once(guild_id.cast()))
+        .map(|role_id| context.cache.get_role(guild_id, role_id));
+
+    let permissions = join_all(role_futures)
+        .await
+        .into_iter()
+        .filter_map(|role| role.ok())
+        .fold(Permissions::empty(), |acc, role| acc | role.permissions);
+
+    Ok(permissions.contains(Permissions::ADMINISTRATOR) || permissions.contains(permission))
+}
+
+async fn command_template(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("templates require a database")?;
+
+    let template = match arguments.next() {
+        Some("node") => DotTemplate::Node,
+        Some("edge") => DotTemplate::Edge,
+        Some(value) => anyhow::bail!(
+            "{} is not a recognized template, expected \"node\" or \"edge\"",
+            value,
+        ),
+        None => anyhow::bail!("expected \"node\" or \"edge\""),
+    };
+
+    let content = match arguments.into_remainder().map(str::trim) {
+        None | Some("") => {
+            let guild_config = get_guild_config(Some(pool), guild_id).await?;
+
+            let current = match template {
+                DotTemplate::Node => guild_config.dot_node_template,
+                DotTemplate::Edge => guild_config.dot_edge_template,
+            };
+
+            match current {
+                Some(attributes) => format!("Current template: `{}`", attributes),
+                None => "No template set.".to_string(),
+            }
+        }
+        Some(value) => {
+            if value == "clear" {
+                set_dot_template(pool, guild_id, template, None).await?;
+
+                "Template cleared.".to_string()
+            } else {
+                let attributes: DotAttributes = value.parse()?;
+                set_dot_template(pool, guild_id, template, Some(&attributes)).await?;
+
+                format!("Template set to `{}`", attributes)
+            }
+        }
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .content(&content)?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_rules(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+
+    let enabled = match arguments.next() {
+        Some("on") => true,
+        Some("off") => false,
+        Some(value) => anyhow::bail!("{} is not recognized, expected \"on\" or \"off\"", value),
+        None => {
+            let rules = {
+                let social = context.social.lock();
+                social.get_rules(guild_id)
+            };
+
+            let lines: Vec<_> = rules
+                .into_iter()
+                .map(|(name, enabled)| {
+                    format!("`{}`: {}", name, if enabled { "on" } else { "off" })
+                })
+                .collect();
+
+            context
+                .http
+                .create_message(message.channel_id)
+                .content(&lines.join("\n"))?
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let name = arguments.next().context("expected a rule name")?;
+
+    {
+        let mut social = context.social.lock();
+        social.set_rule_enabled(guild_id, name, enabled)?;
+    }
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .content(&format!(
+            "Turned `{}` {}.",
+            name,
+            if enabled { "on" } else { "off" }
+        ))?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_forget_channel(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    let guild_id = message.guild_id.context("message not to guild")?;
+    let pool = context
+        .pool
+        .as_ref()
+        .context("forgetting channels requires a database")?;
+
+    if !has_guild_permission(
+        context,
+        guild_id,
+        message.author.id,
+        Permissions::ADMINISTRATOR,
+    )
+    .await?
+    {
+        info!(
+            "{} tried to forget a channel but isn't an administrator",
+            message.author.id,
+        );
+        return Ok(());
+    }
+
+    let channel_id = arguments
+        .next()
+        .and_then(parse_channel_argument)
+        .context("expected a channel")?;
+
+    // Events are deleted by guild too, but catch the mistake before asking for confirmation.
+    match context.cache.get_channel_guild_id(channel_id) {
+        Some(channel_guild_id) if channel_guild_id != guild_id => {
+            anyhow::bail!("<#{}> isn't in this server", channel_id)
+        }
+        _ => (),
+    }
+
+    let confirmed = match arguments.next() {
+        Some("confirm") => true,
+        Some(value) => anyhow::bail!("{} is not a recognized option, expected \"confirm\"", value),
+        None => false,
+    };
+
+    let content = if !confirmed {
+        context
+            .session
+            .request_forget_channel(guild_id, message.author.id, channel_id);
+
+        format!(
+            "This will permanently delete every interaction recorded in <#{}>. \
+            Run the command again with `confirm` at the end within a minute to continue.",
+            channel_id,
+        )
+    } else if !context
+        .session
+        .confirm_forget_channel(guild_id, message.author.id, channel_id)
+    {
+        "Nothing to confirm, run the command without `confirm` first.".to_string()
+    } else {
+        warn!(
+            "{} is forgetting channel {} in guild {}",
+            message.author.id, channel_id, guild_id,
+        );
+
+        // Hold the guild's write lock, so an interaction in the channel can't write its edges
+        // back between deleting them and forgetting the graph.
+        let write_lock = context.edge_writes.get(guild_id);
+        let write_guard = write_lock.lock().await;
+
+        let deleted = delete_channel_events(pool, guild_id, channel_id).await?;
+
+        {
+            let mut social = context.social.lock();
+            social.forget_channel(guild_id, channel_id);
+        }
+
+        drop(write_guard);
+
+        warn!(
+            "forgot {} events from channel {} in guild {}",
+            deleted, channel_id, guild_id,
+        );
+
+        format!("Forgot {} interactions from <#{}>.", deleted, channel_id)
+    };
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .content(&content)?
+        .await?;
+
+    Ok(())
+}
+
+async fn command_debug_last(context: &Context, message: &Message) -> Result<()> {
+    if !context.owners.contains(&message.author.id) {
+        info!(
+            "{} tried to run debug-last command but isn't an owner",
+            message.author.id,
+        );
+        return Ok(());
+    }
+
+    let guild_id = message.guild_id.context("message not to guild")?;
+
+    let processed = match context.session.get_last_interaction(guild_id) {
+        Some(processed) => processed,
+        None => {
+            context
+                .http
+                .create_message(message.channel_id)
+                .content("No interactions have been processed in this server since starting.")?
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let embed = interaction_to_embed(context, &processed.interaction).await?;
+
+    let contents = serde_json::to_vec_pretty(&processed)?;
+
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .attachments(&[Attachment::from_bytes(
+            "interaction.json".to_owned(),
+            contents,
+            0,
+        )])?
+        .await?;
+
+    Ok(())
+}
+
+/// `dump <guild_id> [ids|anonymous] [png|svg|pdf] [engine] [d3]` attaches a guild's graph as
+/// DOT and rendered in the format, PNG by default, or with `d3` as JSON for d3-force. That
+/// drops straight into Observable's force-directed graph template,
+/// `https://observablehq.com/@d3/force-directed-graph`, in place of its `miserables.json`.
+/// With no guild ID, lists the guilds instead.
+async fn command_dump(
+    context: &Context,
+    invocation: &Invocation<'_>,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    if !context.owners.contains(&invocation.author.id) {
+        info!(
+            "{} tried to run dump command but isn't an owner",
+            invocation.author.id,
+        );
+        return Ok(());
+    }
+
+    if let Some(guild_id) = arguments.next() {
+        let guild_id: u64 = guild_id.parse()?;
+        let guild_id = Id::new(guild_id);
+
+        let mut label_mode = LabelMode::DisplayName;
+        let mut d3 = false;
+        let mut format = GraphFormat::Png;
+        let mut layout = None;
+
+        for argument in arguments {
+            if let Some(parsed) = GraphFormat::from_name(argument) {
+                format = parsed;
+                continue;
+            }
+
+            if let Some(engine) = LayoutEngine::from_name(argument) {
+                layout = Some(engine);
+                continue;
+            }
+
+            match argument {
+                "ids" => label_mode = LabelMode::UserId,
+                "anonymous" => label_mode = LabelMode::Anonymous,
+                "d3" => d3 = true,
+                value => anyhow::bail!(
+                    "{} is not a recognized option, expected \"ids\", \"anonymous\", \"d3\", \
+                    a format like \"svg\" or a layout like \"sfdp\"",
+                    value,
+                ),
+            }
+        }
+
+        let guild_name = context.cache.get_guild(guild_id).await?.name;
+        let attachment_base_name = sanitize_name_for_attachment(&guild_name);
+
+        let snapshot = {
+            let social = context.social.lock();
+            social.get_graph_snapshot(guild_id)
+        };
+
+        let graph = snapshot.build_guild_graph();
+        if graph.is_empty() {
+            return send_no_interactions_reply(context, invocation).await;
+        }
+
+        let guild_config = get_guild_config(context.pool.as_ref(), guild_id).await?;
+
+        let options = DotOptions {
+            color_scheme: ColorScheme::Light,
+            transparent: false,
+            label_mode,
+            undirected: false,
+            weight_threshold: 0.0,
+            node_attributes: guild_config.dot_node_template,
+            edge_attributes: guild_config.dot_edge_template,
+            layout,
+            ..DotOptions::default()
+        };
+
+        if d3 {
+            let json = graph.to_json_d3force(context, guild_id, &options).await?;
+
+            invocation
+                .reply(
+                    context,
+                    None,
+                    &[],
+                    &[Attachment::from_bytes(
+                        attachment_base_name + ".json",
+                        json.into_bytes(),
+                        0,
+                    )],
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        let dot = graph.to_dot(context, guild_id, None, &options).await?;
+
+        let rendered = render_dot(context, &dot, format, options.layout).await?;
+
+        invocation
+            .reply(
+                context,
+                None,
+                &[],
+                &[
+                    Attachment::from_bytes(
+                        attachment_base_name.clone() + ".dot",
+                        dot.into_bytes(),
+                        0,
+                    ),
+                    Attachment::from_bytes(attachment_base_name + "." + format.name(), rendered, 1),
+                ],
+            )
+            .await?;
+
+        return Ok(());
+    }
+
+    send_guild_list(context, invocation).await
+}
+
+/// `export [guild_id]` attaches a guild's graph, this one by default, as JSON for other programs.
+async fn command_export(
+    context: &Context,
+    message: &Message,
+    mut arguments: Arguments<'_>,
+) -> Result<()> {
+    if !context.owners.contains(&message.author.id) {
+        info!(
+            "{} tried to run export command but isn't an owner",
+            message.author.id,
+        );
+        return Ok(());
+    }
+
+    let guild_id = match arguments.next() {
+        Some(guild_id) => Id::new_checked(guild_id.parse()?)
+            .with_context(|| format!("{} is not a valid guild ID", guild_id))?,
+        None => message.guild_id.context("message not to guild")?,
+    };
+
+    let guild_name = context.cache.get_guild(guild_id).await?.name;
+
+    let snapshot = {
+        let social = context.social.lock();
+        social.get_graph_snapshot(guild_id)
+    };
+
+    let graph = snapshot.build_guild_graph();
+    if graph.is_empty() {
+        return send_no_interactions_reply(context, &message.into()).await;
+    }
+
+    let json = graph.to_json(context, guild_id).await?;
+
     context
         .http
         .create_message(message.channel_id)
-        .embeds(&[embed])?
+        .attachments(&[Attachment::from_bytes(
+            sanitize_name_for_attachment(&guild_name) + ".json",
+            json.into_bytes(),
+            0,
+        )])?
         .await?;
 
     Ok(())
 }
 
-async fn command_graph(
+/// `opt-out [on|off]` stops inferring anything about the author in this server, or starts again
+/// with `off`. Like `rules`, this only lasts until the bot restarts.
+async fn command_opt_out(
     context: &Context,
     message: &Message,
     mut arguments: Arguments<'_>,
 ) -> Result<()> {
-    // TODO: Respond to the command on errors.
-
     let guild_id = message.guild_id.context("message not to guild")?;
-    let guild_name = context.cache.get_guild(guild_id).await?.name;
-    let attachment_base_name = sanitize_name_for_attachment(&guild_name);
-
-    let color_scheme = match arguments.next() {
-        Some("light") => ColorScheme::Light,
-        Some("dark") => ColorScheme::Dark,
-        Some(value) => anyhow::bail!(
-            "{} is not a recognized color scheme, expected \"light\" or \"dark\"",
-            value,
-        ),
-        None => ColorScheme::Dark,
-    };
-
-    let transparent = matches!(arguments.next(), Some("transparent"));
 
-    let graph = {
-        let social = context.social.lock();
-
-        social
-            .build_guild_graph(guild_id)
-            .context("no graph for guild")?
+    let opted_out = match arguments.next() {
+        Some("on") | None => true,
+        Some("off") => false,
+        Some(value) => anyhow::bail!("{} is not recognized, expected \"on\" or \"off\"", value),
     };
 
-    let dot = graph
-        .to_dot(
-            context,
-            guild_id,
-            Some(&message.author),
-            color_scheme,
-            transparent,
-        )
-        .await?;
-
-    let png = render_dot(&dot).await?;
+    {
+        let mut social = context.social.lock();
+        social.set_opted_out(guild_id, message.author.id, opted_out);
+    }
 
-    let png = if transparent {
-        add_png_shadow(&png, color_scheme).await?
+    let content = if opted_out {
+        "I'll stop tracking your interactions in this server. \
+        Your existing connections will fade away over time."
     } else {
-        png
+        "I'll track your interactions in this server again."
     };
 
     context
         .http
         .create_message(message.channel_id)
-        .attachments(&[Attachment::from_bytes(
-            attachment_base_name + ".png",
-            png,
-            0,
-        )])?
+        .content(content)?
         .await?;
 
     Ok(())
 }
 
-async fn command_stats(context: &Context, message: &Message) -> Result<()> {
+async fn command_list(context: &Context, message: &Message) -> Result<()> {
+    if !context.owners.contains(&message.author.id) {
+        info!(
+            "{} tried to run list command but isn't an owner",
+            message.author.id,
+        );
+        return Ok(());
+    }
+
+    send_guild_list(context, &message.into()).await
+}
+
+/// Run a decay pass now rather than waiting for the next scheduled one.
+async fn command_decay(context: &Context, message: &Message) -> Result<()> {
+    if !context.owners.contains(&message.author.id) {
+        info!(
+            "{} tried to run decay command but isn't an owner",
+            message.author.id,
+        );
+        return Ok(());
+    }
+
+    let removed = decay_graphs(
+        &context.social,
+        context.pool.as_ref(),
+        &context.edge_writes,
+        &context.config.decay,
+    )
+    .await?;
+
     context
         .http
         .create_message(message.channel_id)
-        .content(&format!("{:?}", context.cache.get_stats()))?
+        .content(&format!(
+            "Decayed edges that haven't changed in {}, removing {}.",
+            format_long_duration(context.config.decay.cutoff),
+            removed,
+        ))?
         .await?;
 
     Ok(())
 }
 
-async fn command_dump(
+/// Reply with every guild that has a graph and its stats, most recently active first.
+async fn command_check_cache(
     context: &Context,
     message: &Message,
     mut arguments: Arguments<'_>,
 ) -> Result<()> {
     if !context.owners.contains(&message.author.id) {
         info!(
-            "{} tried to run dump command but isn't an owner",
+            "{} tried to run check-cache command but isn't an owner",
             message.author.id,
         );
         return Ok(());
     }
 
-    if let Some(guild_id) = arguments.next() {
-        let guild_id: u64 = guild_id.parse()?;
-        let guild_id = Id::new(guild_id);
-
-        let guild_name = context.cache.get_guild(guild_id).await?.name;
-        let attachment_base_name = sanitize_name_for_attachment(&guild_name);
+    let guild_id: u64 = arguments
+        .next()
+        .context("expected a guild ID, like `check-cache 123`")?
+        .parse()?;
+    let guild_id = Id::new_checked(guild_id).context("guild IDs can't be zero")?;
 
-        let graph = {
-            let social = context.social.lock();
+    let inconsistencies = context.cache.consistency_check(guild_id).await?;
 
-            social
-                .build_guild_graph(guild_id)
-                .context("no graph for guild")?
-        };
+    let lines: Vec<_> = inconsistencies
+        .iter()
+        .map(|inconsistency| inconsistency.to_string())
+        .collect();
 
-        let dot = graph
-            .to_dot(context, guild_id, None, ColorScheme::Light, false)
-            .await?;
+    let inconsistencies_field = EmbedField {
+        inline: false,
+        name: format!("Inconsistencies ({})", inconsistencies.len()),
+        value: if lines.is_empty() {
+            "None found.".to_string()
+        } else {
+            join_field_lines(&lines, "\n")
+        },
+    };
 
-        let png = render_dot(&dot).await?;
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: None,
+        fields: vec![inconsistencies_field],
+        footer: Some(get_command_footer(&message.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: Some(format!("Cache check for {}", guild_id)),
+        url: None,
+        video: None,
+    };
 
-        context
-            .http
-            .create_message(message.channel_id)
-            .attachments(&[
-                Attachment::from_bytes(attachment_base_name.clone() + ".dot", dot.into_bytes(), 0),
-                Attachment::from_bytes(attachment_base_name + ".png", png, 1),
-            ])?
-            .await?;
+    context
+        .http
+        .create_message(message.channel_id)
+        .embeds(&[embed])?
+        .await?;
 
-        return Ok(());
-    }
+    Ok(())
+}
 
-    let guild_ids = {
+async fn send_guild_list(context: &Context, invocation: &Invocation<'_>) -> Result<()> {
+    let guilds = {
         let social = context.social.lock();
-        social.get_all_guild_ids()
+        social.list_guilds_with_stats()
     };
 
-    let guild_futures = guild_ids
-        .into_iter()
-        .map(|guild_id| context.cache.get_guild(guild_id));
+    let names = join_all(
+        guilds
+            .iter()
+            .map(|guild| context.cache.get_guild(guild.guild_id)),
+    )
+    .await;
 
-    let guilds: Vec<_> = join_all(guild_futures)
-        .await
-        .into_iter()
-        .filter_map(|guild| guild.ok())
-        .map(|guild| format!("{} - {}", guild.id, guild.name))
+    let lines: Vec<_> = guilds
+        .iter()
+        .zip(names)
+        .map(|(guild, name)| {
+            let name = name.map_or_else(|_| "unknown".to_string(), |cached| cached.name);
+
+            let last_interaction = if guild.last_interaction_ms > 0 {
+                format_relative_time(guild.last_interaction_ms)
+            } else {
+                "not since starting".to_string()
+            };
+
+            format!(
+                "{} - {}: {} users, {} connections, last interaction {}",
+                guild.guild_id, name, guild.node_count, guild.edge_count, last_interaction,
+            )
+        })
         .collect();
 
-    let mut content = "Guilds:\n".to_owned();
-    content.push_str(&guilds.join("\n"));
+    let guilds_field = EmbedField {
+        inline: false,
+        name: format!("Guilds ({})", guilds.len()),
+        value: if lines.is_empty() {
+            "None yet.".to_string()
+        } else {
+            join_field_lines(&lines, "\n")
+        },
+    };
 
-    context
-        .http
-        .create_message(message.channel_id)
-        .content(&content)?
-        .await?;
+    let embed = Embed {
+        author: None,
+        color: None,
+        description: None,
+        fields: vec![guilds_field],
+        footer: Some(get_command_footer(invocation.author)),
+        image: None,
+        kind: "rich".to_string(),
+        provider: None,
+        thumbnail: None,
+        timestamp: None,
+        title: None,
+        url: None,
+        video: None,
+    };
+
+    invocation.reply(context, None, &[embed], &[]).await?;
 
     Ok(())
 }
 
+/// Blend from blue for sparse graphs, through green, to orange for tightly knit ones.
+fn get_density_color(density: f32) -> u32 {
+    const SPARSE: u32 = 0x3498DB;
+    const MEDIUM: u32 = 0x2ECC71;
+    const DENSE: u32 = 0xE67E22;
+
+    let density = density.clamp(0.0, 1.0);
+
+    let (from, to, t) = if density < 0.5 {
+        (SPARSE, MEDIUM, density * 2.0)
+    } else {
+        (MEDIUM, DENSE, (density - 0.5) * 2.0)
+    };
+
+    blend_colors(from, to, t)
+}
+
+/// Parse a user mention (`<@id>` or `<@!id>`) or a bare user ID.
+fn parse_user_argument(argument: &str) -> Option<Id<UserMarker>> {
+    let id = match argument.strip_prefix("<@") {
+        Some(mention) => mention
+            .strip_prefix('!')
+            .unwrap_or(mention)
+            .strip_suffix('>')?,
+        None => argument,
+    };
+
+    Id::new_checked(id.parse().ok()?)
+}
+
+fn parse_role_argument(argument: &str) -> Option<Id<RoleMarker>> {
+    let id = match argument.strip_prefix("<@&") {
+        Some(mention) => mention.strip_suffix('>')?,
+        None => argument,
+    };
+
+    Id::new_checked(id.parse().ok()?)
+}
+
+fn parse_channel_argument(argument: &str) -> Option<Id<ChannelMarker>> {
+    let id = match argument.strip_prefix("<#") {
+        Some(mention) => mention.strip_suffix('>')?,
+        None => argument,
+    };
+
+    Id::new_checked(id.parse().ok()?)
+}
+
 fn sanitize_name_for_attachment(name: &str) -> String {
     let mut string = String::with_capacity(name.len());
     let mut prev_escaped = false;
@@ -301,10 +3743,40 @@ fn sanitize_name_for_attachment(name: &str) -> String {
     string
 }
 
-async fn render_dot(dot: &str) -> Result<Vec<u8>> {
-    let mut graphviz = process::Command::new("dot")
+/// Whether an executable called `name` is somewhere on `PATH`.
+fn is_on_path(name: &str) -> bool {
+    env::var_os("PATH").is_some_and(|paths| {
+        env::split_paths(&paths).any(|directory| directory.join(name).is_file())
+    })
+}
+
+/// Render DOT with GraphViz. `layout` picks the binary to run, and `None` leaves it to the
+/// graph's `layout` attribute. Missing binaries fall back to `dot`, which every install has.
+/// Waits its turn if too many renders are already running.
+async fn render_dot(
+    context: &Context,
+    dot: &str,
+    format: GraphFormat,
+    layout: Option<LayoutEngine>,
+) -> Result<Vec<u8>> {
+    // The binary name only ever comes from `LayoutEngine`, never from what a user typed.
+    let binary = match layout {
+        Some(engine) if is_on_path(engine.name()) => engine.name(),
+        Some(engine) => {
+            warn!(
+                "{} isn't installed, falling back to dot for the layout",
+                engine.name()
+            );
+            "dot"
+        }
+        None => "dot",
+    };
+
+    let _permit = context.rate_limiter.acquire_render().await;
+
+    let mut graphviz = process::Command::new(binary)
         .arg("-v")
-        .arg("-Tpng")
+        .arg(format!("-T{}", format.name()))
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
@@ -318,7 +3790,7 @@ async fn render_dot(dot: &str) -> Result<Vec<u8>> {
     let output = graphviz.wait_with_output().await?;
 
     if !output.status.success() {
-        anyhow::bail!("graphviz failed");
+        anyhow::bail!("{} failed to render {}", binary, format.name());
     }
 
     Ok(output.stdout)
@@ -368,7 +3840,27 @@ async fn add_png_shadow(input: &[u8], color_scheme: ColorScheme) -> Result<Vec<u
 
 #[cfg(test)]
 mod tests {
-    use super::sanitize_name_for_attachment;
+    use super::{
+        format_reason_distribution, format_slash_command_options, format_sparkline,
+        format_velocity, get_density_color, get_slash_commands, get_trend_arrow, is_allowed_to_run,
+        join_field_lines, parse_channel_argument, parse_duration_argument, parse_graph_arguments,
+        parse_role_argument, parse_user_argument, sanitize_name_for_attachment, ChannelArgument,
+        GraphArguments, GraphFormat, GraphMode, LayoutEngine,
+    };
+    use crate::context::Context;
+    use crate::social::graph::{ColorScheme, Palette};
+    use crate::social::inference::RelationshipChangeReason;
+    use serde_json::json;
+    use twilight_command_parser::Arguments;
+    use twilight_model::application::interaction::application_command::{
+        CommandDataOption, CommandOptionValue,
+    };
+    use twilight_model::gateway::event::Event;
+    use twilight_model::gateway::payload::incoming::{GuildCreate, MemberAdd};
+    use twilight_model::id::Id;
+
+    use std::collections::HashMap;
+    use std::time::Duration;
 
     #[test]
     fn test_sanitize_name_for_attachment() {
@@ -377,4 +3869,393 @@ mod tests {
             "Name_With_Spaces"
         );
     }
+
+    #[test]
+    fn test_get_density_color() {
+        assert_eq!(get_density_color(0.0), 0x3498DB);
+        assert_eq!(get_density_color(0.5), 0x2ECC71);
+        assert_eq!(get_density_color(1.0), 0xE67E22);
+        assert_eq!(get_density_color(2.0), 0xE67E22);
+    }
+
+    #[test]
+    fn test_parse_graph_arguments() {
+        assert_eq!(
+            parse_graph_arguments(Arguments::new(
+                "light --weight-threshold=7.5 transparent --undirected --colour-scheme=high-contrast --min-members=2 --no-isolated --include-bots"
+            ))
+            .unwrap(),
+            GraphArguments {
+                mode: GraphMode::Users,
+                format: GraphFormat::Png,
+                mermaid: false,
+                color_scheme: ColorScheme::Light,
+                transparent: true,
+                undirected: true,
+                weight_threshold: Some(7.5),
+                palette: Some(Palette::HighContrast),
+                min_members: 2,
+                no_isolated: true,
+                include_bots: true,
+                layout: None,
+                channel: None,
+                user: None,
+                depth: 1,
+            },
+        );
+
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("")).unwrap(),
+            GraphArguments {
+                mode: GraphMode::Users,
+                format: GraphFormat::Png,
+                mermaid: false,
+                color_scheme: ColorScheme::Dark,
+                transparent: false,
+                undirected: false,
+                weight_threshold: None,
+                palette: None,
+                min_members: 0,
+                no_isolated: false,
+                include_bots: false,
+                layout: None,
+                channel: None,
+                user: None,
+                depth: 1,
+            },
+        );
+
+        assert!(parse_graph_arguments(Arguments::new("--weight-threshold=abc")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("--weight-threshold=-1")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("--min-members=-1")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("--format=gif")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("--layout=osage")).is_err());
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("--layout=sfdp"))
+                .unwrap()
+                .layout,
+            Some(LayoutEngine::Sfdp),
+        );
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("light circo"))
+                .unwrap()
+                .layout,
+            Some(LayoutEngine::Circo),
+        );
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("--layout=sfdp --layout=auto"))
+                .unwrap()
+                .layout,
+            None,
+        );
+        assert!(
+            parse_graph_arguments(Arguments::new("--format=mermaid"))
+                .unwrap()
+                .mermaid
+        );
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("--format=pdf"))
+                .unwrap()
+                .format,
+            GraphFormat::Pdf,
+        );
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("svg light"))
+                .unwrap()
+                .format,
+            GraphFormat::Svg,
+        );
+        assert_eq!(
+            parse_graph_arguments(Arguments::new("--mode=channels"))
+                .unwrap()
+                .mode,
+            GraphMode::Channels,
+        );
+        assert!(parse_graph_arguments(Arguments::new("--mode=roles")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("--bogus")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("--colour-scheme=neon")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("purple")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("light opaque")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("light transparent extra")).is_err());
+    }
+
+    #[test]
+    fn test_parse_graph_arguments_channel() {
+        let arguments = parse_graph_arguments(Arguments::new("<#123> light")).unwrap();
+        assert_eq!(arguments.channel, Some(ChannelArgument::Id(Id::new(123))));
+        assert_eq!(arguments.color_scheme, ColorScheme::Light);
+
+        let arguments =
+            parse_graph_arguments(Arguments::new("light transparent #general")).unwrap();
+        assert_eq!(
+            arguments.channel,
+            Some(ChannelArgument::Name("general".to_string())),
+        );
+        assert!(arguments.transparent);
+
+        assert!(parse_graph_arguments(Arguments::new("<#abc>")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("#general --mode=channels")).is_err());
+    }
+
+    #[test]
+    fn test_parse_graph_arguments_user() {
+        let arguments = parse_graph_arguments(Arguments::new("<@123> light")).unwrap();
+        assert_eq!(arguments.user, Some(Id::new(123)));
+        assert_eq!(arguments.depth, 1);
+        assert_eq!(arguments.color_scheme, ColorScheme::Light);
+
+        let arguments = parse_graph_arguments(Arguments::new("light <@!123> 2")).unwrap();
+        assert_eq!(arguments.user, Some(Id::new(123)));
+        assert_eq!(arguments.depth, 2);
+        assert_eq!(arguments.color_scheme, ColorScheme::Light);
+
+        assert!(parse_graph_arguments(Arguments::new("<@123> 0")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("<@123> 4")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("<@abc>")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("<@123> #general")).is_err());
+        assert!(parse_graph_arguments(Arguments::new("<@123> --mode=channels")).is_err());
+    }
+
+    #[test]
+    fn test_format_slash_command_options() {
+        let commands = get_slash_commands();
+        let graph = commands
+            .iter()
+            .find(|command| command.name == "graph")
+            .unwrap();
+
+        // Discord doesn't promise an order, but the depth has to follow the user.
+        let options = vec![
+            ("options", CommandOptionValue::String("light".to_string())),
+            ("depth", CommandOptionValue::Integer(2)),
+            (
+                "format",
+                CommandOptionValue::String("--format=svg".to_string()),
+            ),
+            ("user", CommandOptionValue::User(Id::new(123))),
+        ]
+        .into_iter()
+        .map(|(name, value)| CommandDataOption {
+            name: name.to_string(),
+            value,
+        })
+        .collect::<Vec<_>>();
+
+        let formatted = format_slash_command_options(graph, &options);
+        assert_eq!(formatted, "<@123> 2 --format=svg light");
+
+        let arguments = parse_graph_arguments(Arguments::new(&formatted)).unwrap();
+        assert_eq!(arguments.user, Some(Id::new(123)));
+        assert_eq!(arguments.depth, 2);
+        assert_eq!(arguments.format, GraphFormat::Svg);
+        assert_eq!(arguments.color_scheme, ColorScheme::Light);
+    }
+
+    #[tokio::test]
+    async fn test_is_allowed_to_run() {
+        let context = Context::for_tests();
+        let guild_id = Id::new(100);
+        let (owner, manager, member) = (Id::new(2), Id::new(3), Id::new(4));
+
+        let role = |id: &str, permissions: &str| {
+            json!({
+                "id": id,
+                "name": id,
+                "color": 0,
+                "hoist": false,
+                "managed": false,
+                "mentionable": false,
+                "permissions": permissions,
+                "position": 0,
+            })
+        };
+
+        let guild = json!({
+            "id": "100",
+            "name": "Guild",
+            "owner_id": "2",
+            "afk_timeout": 300,
+            "default_message_notifications": 0,
+            "explicit_content_filter": 0,
+            "features": [],
+            "emojis": [],
+            "icon": null,
+            "large": false,
+            "mfa_level": 0,
+            "nsfw_level": 0,
+            "preferred_locale": "en-US",
+            "premium_progress_bar_enabled": false,
+            // @everyone can send messages, the moderator role can manage the server.
+            "roles": [role("100", "2048"), role("200", "32")],
+            "system_channel_flags": 0,
+            "verification_level": 0,
+        });
+        let guild: GuildCreate = serde_json::from_value(guild).unwrap();
+        context
+            .cache
+            .update(&Event::GuildCreate(Box::new(guild)))
+            .await;
+
+        for (user_id, roles) in [(manager, vec!["200"]), (member, vec![])] {
+            let member: MemberAdd = serde_json::from_value(json!({
+                "guild_id": "100",
+                "deaf": false,
+                "mute": false,
+                "flags": 0,
+                "joined_at": "2026-01-01T00:00:00.000000+00:00",
+                "roles": roles,
+                "user": {
+                    "id": user_id.to_string(),
+                    "username": format!("user-{}", user_id),
+                    "discriminator": "0001",
+                    "avatar": null,
+                    "public_flags": 0,
+                },
+            }))
+            .unwrap();
+            context
+                .cache
+                .update(&Event::MemberAdd(Box::new(member)))
+                .await;
+        }
+
+        for &user_id in &[owner, manager] {
+            for &command in &["graph", "stats", "dump", "help"] {
+                assert!(
+                    is_allowed_to_run(&context, command, Some(guild_id), user_id)
+                        .await
+                        .unwrap()
+                );
+            }
+        }
+
+        for &command in &[
+            "graph",
+            "stats",
+            "dump",
+            "network",
+            "leaderboard",
+            "simulate",
+        ] {
+            assert!(
+                !is_allowed_to_run(&context, command, Some(guild_id), member)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        for &command in &["help", "invite", "ping"] {
+            assert!(is_allowed_to_run(&context, command, Some(guild_id), member)
+                .await
+                .unwrap());
+        }
+
+        // There's nobody to keep the graph from outside of a server.
+        assert!(is_allowed_to_run(&context, "stats", None, member)
+            .await
+            .unwrap());
+    }
+
+    #[test]
+    fn test_parse_duration_argument() {
+        assert_eq!(
+            parse_duration_argument("12h").unwrap(),
+            Duration::from_secs(12 * 60 * 60),
+        );
+        assert_eq!(
+            parse_duration_argument("30d").unwrap(),
+            Duration::from_secs(30 * 24 * 60 * 60),
+        );
+        assert_eq!(
+            parse_duration_argument("2w").unwrap(),
+            Duration::from_secs(14 * 24 * 60 * 60),
+        );
+
+        for invalid in &["30", "d", "0d", "5m", "-1d", "1.5d"] {
+            assert!(parse_duration_argument(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_get_trend_arrow() {
+        assert_eq!(get_trend_arrow(5, 3), '\u{2191}');
+        assert_eq!(get_trend_arrow(3, 5), '\u{2193}');
+        assert_eq!(get_trend_arrow(4, 4), '\u{2192}');
+    }
+
+    #[test]
+    fn test_parse_user_argument() {
+        assert_eq!(parse_user_argument("<@123>"), Some(Id::new(123)));
+        assert_eq!(parse_user_argument("<@!123>"), Some(Id::new(123)));
+        assert_eq!(parse_user_argument("123"), Some(Id::new(123)));
+        assert_eq!(parse_user_argument("<@123"), None);
+        assert_eq!(parse_user_argument("<#123>"), None);
+        assert_eq!(parse_user_argument("<@&123>"), None);
+    }
+
+    #[test]
+    fn test_parse_role_argument() {
+        assert_eq!(parse_role_argument("<@&123>"), Some(Id::new(123)));
+        assert_eq!(parse_role_argument("123"), Some(Id::new(123)));
+        assert_eq!(parse_role_argument("<@123>"), None);
+        assert_eq!(parse_role_argument("<@&123"), None);
+    }
+
+    #[test]
+    fn test_parse_channel_argument() {
+        assert_eq!(parse_channel_argument("<#123>"), Some(Id::new(123)));
+        assert_eq!(parse_channel_argument("123"), Some(Id::new(123)));
+        assert_eq!(parse_channel_argument("<@123>"), None);
+        assert_eq!(parse_channel_argument("<#0>"), None);
+    }
+
+    #[test]
+    fn test_join_field_lines() {
+        let lines = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(join_field_lines(&lines, ", "), "a, b");
+
+        let lines = vec!["x".repeat(600), "y".repeat(600), "z".to_string()];
+        let value = join_field_lines(&lines, "\n");
+        assert!(value.len() <= 1024);
+        assert!(value.ends_with("and 2 more"));
+    }
+
+    #[test]
+    fn test_format_sparkline() {
+        assert_eq!(format_sparkline(&[0, 1, 4, 8]), " \u{2581}\u{2584}\u{2588}");
+        assert_eq!(format_sparkline(&[3, 3]), "\u{2588}\u{2588}");
+        assert_eq!(format_sparkline(&[0, 0]), "  ");
+        assert_eq!(format_sparkline(&[]), "");
+    }
+
+    #[test]
+    fn test_format_reason_distribution() {
+        let distribution = HashMap::from([
+            (RelationshipChangeReason::MessageIndirectMention, 45),
+            (RelationshipChangeReason::MessageDirectMention, 32),
+            (RelationshipChangeReason::Reaction, 23),
+        ]);
+        assert_eq!(
+            format_reason_distribution(&distribution),
+            "45% mention, 32% reply, 23% reaction",
+        );
+
+        assert_eq!(format_reason_distribution(&HashMap::new()), "None yet");
+    }
+
+    #[test]
+    fn test_format_velocity() {
+        assert_eq!(
+            format_velocity(47.0, 42.0),
+            "Average: 47 interactions/day this week (\u{2191} 12% vs last week)",
+        );
+        assert_eq!(
+            format_velocity(10.0, 20.0),
+            "Average: 10 interactions/day this week (\u{2193} 50% vs last week)",
+        );
+        assert_eq!(
+            format_velocity(3.0, 0.0),
+            "Average: 3 interactions/day this week",
+        );
+    }
 }
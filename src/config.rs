@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use twilight_model::gateway::ShardId;
+use twilight_model::id::marker::UserMarker;
+use twilight_model::id::Id;
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::env;
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use crate::cache::CacheConfig;
+use crate::social::graph::DecayConfig;
+
+pub fn get_optional_env(key: &str) -> Option<String> {
+    match env::var(key) {
+        Ok(value) => Some(value),
+        Err(env::VarError::NotPresent) => None,
+        Err(error) => panic!("{}", error),
+    }
+}
+
+fn get_env_flag(key: &str) -> bool {
+    match get_optional_env(key) {
+        Some(value) => matches!(value.to_ascii_lowercase().as_str(), "1" | "true" | "yes"),
+        None => false,
+    }
+}
+
+fn parse_user_ids(value: &str) -> Result<HashSet<Id<UserMarker>>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .ok()
+                .and_then(Id::new_checked)
+                .with_context(|| format!("{} is not a valid user ID", id))
+        })
+        .collect()
+}
+
+fn parse_optional_number(key: &str) -> Result<Option<u64>> {
+    get_optional_env(key)
+        .map(|value| value.parse())
+        .transpose()
+        .with_context(|| format!("invalid {}", key))
+}
+
+fn parse_optional_float(key: &str) -> Result<Option<f64>> {
+    get_optional_env(key)
+        .map(|value| value.parse())
+        .transpose()
+        .with_context(|| format!("invalid {}", key))
+}
+
+fn parse_decay_config() -> Result<DecayConfig> {
+    let default = DecayConfig::default();
+
+    let factor = parse_optional_float("DECAY_FACTOR")?.unwrap_or(default.factor);
+    if !(factor > 0.0 && factor <= 1.0) {
+        anyhow::bail!(
+            "DECAY_FACTOR must be more than 0 and at most 1, got {}",
+            factor
+        );
+    }
+
+    let days = |days: u64| Duration::from_secs(days * 24 * 60 * 60);
+    let hours = |hours: u64| Duration::from_secs(hours * 60 * 60);
+
+    let interval = parse_optional_number("DECAY_INTERVAL_HOURS")?.map_or(default.interval, hours);
+    if interval.is_zero() {
+        anyhow::bail!("DECAY_INTERVAL_HOURS must be at least 1");
+    }
+
+    Ok(DecayConfig {
+        factor,
+        cutoff: parse_optional_number("DECAY_CUTOFF_DAYS")?.map_or(default.cutoff, days),
+        min_weight: parse_optional_float("DECAY_MIN_WEIGHT")?
+            .map_or(default.min_weight, |min_weight| min_weight as f32),
+        interval,
+    })
+}
+
+fn parse_cache_capacity(key: &str, default: NonZeroUsize) -> Result<NonZeroUsize> {
+    match parse_optional_number(key)? {
+        Some(capacity) => usize::try_from(capacity)
+            .ok()
+            .and_then(NonZeroUsize::new)
+            .with_context(|| format!("{} must be at least 1", key)),
+        None => Ok(default),
+    }
+}
+
+/// Check a shard ID and count make sense together, they're either both set or both not.
+fn check_shard(shard_id: Option<u64>, shard_count: Option<u64>) -> Result<()> {
+    match (shard_id, shard_count) {
+        (None, None) => Ok(()),
+        (Some(shard_id), Some(shard_count)) => {
+            ShardId::new_checked(shard_id, shard_count).with_context(|| {
+                format!(
+                    "SHARD_ID must be less than SHARD_COUNT, got {} of {}",
+                    shard_id, shard_count,
+                )
+            })?;
+
+            Ok(())
+        }
+        _ => anyhow::bail!("SHARD_ID and SHARD_COUNT must be set together"),
+    }
+}
+
+/// Runtime options, read from the environment at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Record interactions for guild scheduled event RSVPs and attendance.
+    pub track_scheduled_events: bool,
+    /// Replay the events table into the in-memory graphs at startup, for
+    /// deployments that don't persist graphs to `DATA_DIR`.
+    pub rebuild_from_database: bool,
+    /// Watch member presences, and weight graph edges by how long both users were online.
+    /// Requires the privileged presence intent.
+    pub track_presence: bool,
+    /// Record new forum posts as interactions with the forum's moderators.
+    pub track_forums: bool,
+    /// Extra bot owners, in addition to the application's owner or team.
+    pub owners: HashSet<Id<UserMarker>>,
+    /// Which shard this instance connects as, for bots in too many guilds for one.
+    /// Guilds are split between shards by Discord, and each guild's graph only lives on one.
+    pub shard_id: Option<u64>,
+    pub shard_count: Option<u64>,
+    pub cache: CacheConfig,
+    /// How long after a change between two users further ones only count for a tenth as much.
+    pub cooldown_seconds: u64,
+    /// How long a guild waits between graph commands, since each one runs GraphViz.
+    pub graph_cooldown_seconds: u64,
+    /// How edges that have gone quiet are weakened over time.
+    pub decay: DecayConfig,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        let owners = match get_optional_env("DISCOGRAPH_OWNERS") {
+            Some(value) => parse_user_ids(&value).context("invalid DISCOGRAPH_OWNERS")?,
+            None => HashSet::new(),
+        };
+
+        let shard_id = parse_optional_number("SHARD_ID")?;
+        let shard_count = parse_optional_number("SHARD_COUNT")?;
+        check_shard(shard_id, shard_count)?;
+
+        let cooldown_seconds = parse_optional_number("COOLDOWN_SECONDS")?.unwrap_or(5);
+        let graph_cooldown_seconds = parse_optional_number("GRAPH_COOLDOWN_SECONDS")?.unwrap_or(60);
+
+        let default_cache = CacheConfig::default();
+        let cache = CacheConfig {
+            messages: parse_cache_capacity("CACHE_MESSAGES", default_cache.messages)?,
+        };
+
+        Ok(Config {
+            track_scheduled_events: get_env_flag("TRACK_SCHEDULED_EVENTS"),
+            rebuild_from_database: get_env_flag("REBUILD_FROM_DATABASE"),
+            track_presence: get_env_flag("TRACK_PRESENCE"),
+            track_forums: get_env_flag("TRACK_FORUMS"),
+            owners,
+            shard_id,
+            shard_count,
+            cache,
+            cooldown_seconds,
+            graph_cooldown_seconds,
+            decay: parse_decay_config()?,
+        })
+    }
+
+    /// The shard to connect as, the only one unless `SHARD_ID` and `SHARD_COUNT` are set.
+    pub fn shard(&self) -> ShardId {
+        match (self.shard_id, self.shard_count) {
+            (Some(shard_id), Some(shard_count)) => ShardId::new(shard_id, shard_count),
+            _ => ShardId::ONE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_shard, parse_user_ids};
+    use twilight_model::id::Id;
+
+    #[test]
+    fn test_parse_user_ids() {
+        let owners = parse_user_ids("123456789, 987654321,").unwrap();
+        assert_eq!(owners.len(), 2);
+        assert!(owners.contains(&Id::new(123456789)));
+        assert!(owners.contains(&Id::new(987654321)));
+
+        assert!(parse_user_ids("123,abc").is_err());
+        assert!(parse_user_ids("0").is_err());
+    }
+
+    #[test]
+    fn test_check_shard() {
+        assert!(check_shard(None, None).is_ok());
+        assert!(check_shard(Some(0), Some(2)).is_ok());
+        assert!(check_shard(Some(1), Some(2)).is_ok());
+
+        assert!(check_shard(Some(2), Some(2)).is_err());
+        assert!(check_shard(Some(0), Some(0)).is_err());
+        assert!(check_shard(Some(0), None).is_err());
+        assert!(check_shard(None, Some(2)).is_err());
+    }
+}
@@ -1,22 +1,87 @@
-use parking_lot::Mutex;
 use sqlx::MySqlPool;
 use twilight_http::Client;
+use twilight_model::gateway::ShardId;
 use twilight_model::id::marker::UserMarker;
 use twilight_model::id::Id;
 use twilight_model::user::CurrentUser;
 
 use std::collections::HashSet;
 use std::sync::Arc;
+use std::time::Duration;
 
 use crate::cache::Cache;
+use crate::config::Config;
+use crate::rate_limit::RateLimiter;
+use crate::session::Session;
 use crate::social::graph::SocialGraph;
+use crate::social::persistence::GuildWriteLocks;
+use crate::sync::CheckedMutex;
 
 #[derive(Clone)]
 pub struct Context {
+    pub config: Arc<Config>,
     pub user: Arc<CurrentUser>,
     pub owners: Arc<HashSet<Id<UserMarker>>>,
     pub http: Arc<Client>,
     pub cache: Arc<Cache>,
-    pub social: Arc<Mutex<SocialGraph>>,
+    pub social: Arc<CheckedMutex<SocialGraph>>,
+    pub session: Arc<Session>,
+    pub rate_limiter: Arc<RateLimiter>,
     pub pool: Option<MySqlPool>,
+    pub edge_writes: Arc<GuildWriteLocks>,
+    /// The shard's average heartbeat latency when the event was received.
+    pub gateway_latency: Option<Duration>,
+    /// The shard this instance is connected as, from `Config::shard`.
+    pub shard: ShardId,
+}
+
+#[cfg(test)]
+impl Context {
+    /// A context with default config, empty caches and graphs, and no database. The HTTP client
+    /// has no token, so anything that isn't cached fails instead of being fetched.
+    /// Must be called from within the runtime, which runs the cache's message writer.
+    pub fn for_tests() -> Self {
+        use crate::cache::CacheConfig;
+        use crate::social::graph::DecayConfig;
+        use tokio::runtime::Handle;
+
+        let http = Arc::new(Client::new(String::new()));
+
+        Context {
+            config: Arc::new(Config {
+                track_scheduled_events: false,
+                rebuild_from_database: false,
+                track_presence: false,
+                track_forums: false,
+                owners: HashSet::new(),
+                shard_id: None,
+                shard_count: None,
+                cache: CacheConfig::default(),
+                cooldown_seconds: 0,
+                graph_cooldown_seconds: 0,
+                decay: DecayConfig::default(),
+            }),
+            user: Arc::new(
+                serde_json::from_value(serde_json::json!({
+                    "id": "1",
+                    "username": "discograph",
+                    "discriminator": "0001",
+                    "avatar": null,
+                    "bot": true,
+                    "mfa_enabled": false,
+                }))
+                .unwrap(),
+            ),
+            owners: Arc::new(HashSet::new()),
+            http: http.clone(),
+            cache: Arc::new(Cache::new(http, CacheConfig::default(), &Handle::current())),
+            social: Arc::new(CheckedMutex::new(SocialGraph::new(None))),
+            session: Arc::new(Session::default()),
+            rate_limiter: Arc::new(RateLimiter::new(Duration::ZERO)),
+            pool: None,
+            edge_writes: Arc::new(GuildWriteLocks::default()),
+            gateway_latency: None,
+            shard: ShardId::ONE,
+        }
+    }
 }
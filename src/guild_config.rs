@@ -0,0 +1,100 @@
+use anyhow::Result;
+use sqlx::{MySqlPool, Row};
+use tracing::warn;
+use twilight_model::id::marker::GuildMarker;
+use twilight_model::id::Id;
+
+use crate::social::dot::DotAttributes;
+
+/// Per-guild settings, stored in the `guild_config` table.
+#[derive(Debug, Clone, Default)]
+pub struct GuildConfig {
+    /// Extra DOT attributes applied to every node in the guild's graphs.
+    pub dot_node_template: Option<DotAttributes>,
+    /// Extra DOT attributes applied to every edge in the guild's graphs.
+    pub dot_edge_template: Option<DotAttributes>,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DotTemplate {
+    Node,
+    Edge,
+}
+
+impl DotTemplate {
+    fn column(&self) -> &'static str {
+        match self {
+            DotTemplate::Node => "dot_node_template",
+            DotTemplate::Edge => "dot_edge_template",
+        }
+    }
+}
+
+/// Templates were validated when they were set, but re-check them in case the rules tightened.
+fn parse_stored_template(
+    guild_id: Id<GuildMarker>,
+    value: Option<String>,
+) -> Option<DotAttributes> {
+    let value = value?;
+
+    match value.parse() {
+        Ok(attributes) => Some(attributes),
+        Err(error) => {
+            warn!(
+                "ignoring invalid template for guild {}: {}",
+                guild_id, error
+            );
+            None
+        }
+    }
+}
+
+/// Load a guild's config, using the defaults when there's no database or no row.
+pub async fn get_guild_config(
+    pool: Option<&MySqlPool>,
+    guild_id: Id<GuildMarker>,
+) -> Result<GuildConfig> {
+    let pool = match pool {
+        Some(pool) => pool,
+        None => return Ok(GuildConfig::default()),
+    };
+
+    let row = sqlx::query(
+        "SELECT dot_node_template, dot_edge_template FROM guild_config WHERE guild = ?",
+    )
+    .bind(guild_id.get())
+    .fetch_optional(pool)
+    .await?;
+
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(GuildConfig::default()),
+    };
+
+    Ok(GuildConfig {
+        dot_node_template: parse_stored_template(guild_id, row.try_get("dot_node_template")?),
+        dot_edge_template: parse_stored_template(guild_id, row.try_get("dot_edge_template")?),
+    })
+}
+
+/// Store one of a guild's DOT templates, or clear it with `None`.
+pub async fn set_dot_template(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    template: DotTemplate,
+    attributes: Option<&DotAttributes>,
+) -> Result<()> {
+    // The column name comes from a fixed list, so this can't be used for injection.
+    let query = format!(
+        "INSERT INTO guild_config (guild, {0}) VALUES (?, ?) ON DUPLICATE KEY UPDATE {0} = VALUES({0})",
+        template.column(),
+    );
+
+    sqlx::query(&query)
+        .bind(guild_id.get())
+        .bind(attributes.map(|attributes| attributes.to_string()))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
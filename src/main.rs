@@ -1,39 +1,43 @@
 mod cache;
 mod commands;
+mod config;
 mod context;
+mod guild_config;
+mod rate_limit;
+mod session;
 mod social;
+mod sync;
+mod util;
 
 use anyhow::{Context as AnyhowContext, Result};
-use parking_lot::Mutex;
 use sqlx::mysql::MySqlPoolOptions;
 use sqlx::Connection;
 use tracing::{debug, error, info, warn};
-use twilight_gateway::{Config, Event, Shard};
-use twilight_http::{Client as HttpClient, Client};
+use twilight_gateway::{Config as GatewayConfig, Event, Shard};
+use twilight_http::Client as HttpClient;
 use twilight_model::gateway::payload::outgoing::UpdatePresence;
 use twilight_model::gateway::presence::{Activity, ActivityType, MinimalActivity, Status};
-use twilight_model::gateway::{CloseFrame, Intents, ShardId};
+use twilight_model::gateway::{CloseFrame, Intents};
 use twilight_model::id::marker::UserMarker;
 use twilight_model::id::Id;
 use twilight_model::oauth::team::TeamMembershipState;
+use twilight_model::oauth::Application;
 
 use std::collections::HashSet;
-use std::env;
 use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
 
 use crate::cache::Cache;
+use crate::config::{get_optional_env, Config};
 use crate::context::Context;
+use crate::rate_limit::RateLimiter;
+use crate::session::Session;
 use crate::social::graph::SocialGraph;
-
-fn get_optional_env(key: &str) -> Option<String> {
-    match env::var(key) {
-        Ok(value) => Some(value),
-        Err(env::VarError::NotPresent) => None,
-        Err(error) => panic!("{}", error),
-    }
-}
+use crate::social::persistence::{load_graph_from_file, save_graph_to_file, GuildWriteLocks};
+use crate::sync::CheckedMutex;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -60,6 +64,11 @@ async fn main() -> Result<()> {
 
         info!("database connection established");
 
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .context("database migrations failed")?;
+
         Some(pool)
     } else {
         debug!("DATABASE_URL not set");
@@ -67,6 +76,8 @@ async fn main() -> Result<()> {
         None
     };
 
+    let config = Arc::new(Config::from_env()?);
+
     let token = get_optional_env("DISCORD_TOKEN").context("missing discord bot token")?;
 
     // HTTP is separate from the gateway, so create a new client.
@@ -74,23 +85,105 @@ async fn main() -> Result<()> {
 
     // Just block on these, it simplifies the startup logic.
     let user = Arc::new(http.current_user().await?.model().await?);
-    let owners = Arc::new(get_application_owners(&http).await?);
+    let application = http.current_user_application().await?.model().await?;
+    let mut owners = get_application_owners(&application);
+    owners.extend(&config.owners);
+    let owners = Arc::new(owners);
 
-    let cache = Arc::new(Cache::new(http.clone()));
+    let cache = Arc::new(Cache::new(http.clone(), config.cache, &Handle::current()));
 
     let data_dir = get_optional_env("DATA_DIR").map(PathBuf::from);
-    let social = Arc::new(Mutex::new(SocialGraph::new(data_dir)));
+    let rebuild_from_database = config.rebuild_from_database && data_dir.is_none();
+    if config.rebuild_from_database && !rebuild_from_database {
+        warn!("REBUILD_FROM_DATABASE is ignored when DATA_DIR is set");
+    }
+
+    let social = Arc::new(CheckedMutex::new(SocialGraph::new(data_dir)));
+    let session = Arc::new(Session::default());
+    let edge_writes = Arc::new(GuildWriteLocks::default());
+    let rate_limiter = Arc::new(RateLimiter::new(Duration::from_secs(
+        config.graph_cooldown_seconds,
+    )));
+
+    // Graphs saved when we last shut down, which are quicker to load than replaying the database.
+    let graph_file = get_optional_env("GRAPH_FILE").map(PathBuf::from);
+    let loaded_graph_file = match &graph_file {
+        Some(path) if path.exists() => {
+            let graphs = load_graph_from_file(path)?;
+            social.lock().restore_all_graphs(graphs);
+
+            // Don't load the same graphs again if we don't shut down cleanly next time.
+            std::fs::remove_file(path)?;
+
+            info!("loaded graphs from {}", path.display());
+
+            true
+        }
+        _ => false,
+    };
+
+    if let (true, false, Some(pool)) = (rebuild_from_database, loaded_graph_file, &pool) {
+        social::rebuild_graphs_from_database(pool, &social).await?;
+    }
+
+    // Weaken edges that have gone quiet, the first pass is an interval after startup.
+    {
+        let (social, pool, edge_writes, decay) = (
+            social.clone(),
+            pool.clone(),
+            edge_writes.clone(),
+            config.decay,
+        );
+        tokio::spawn(async move {
+            let start = tokio::time::Instant::now() + decay.interval;
+            let mut interval = tokio::time::interval_at(start, decay.interval);
+
+            loop {
+                interval.tick().await;
+
+                if let Err(error) =
+                    social::decay_graphs(&social, pool.as_ref(), &edge_writes, &decay).await
+                {
+                    error!("failed to decay graphs: {:?}", error);
+                }
+            }
+        });
+    }
+
+    // Not fatal, prefix commands keep working and the commands from last time stay registered.
+    match commands::register_slash_commands(&http, application.id).await {
+        Ok(()) => info!("registered slash commands"),
+        Err(error) => warn!("failed to register slash commands: {:?}", error),
+    }
+
+    // Only some commands have slash versions so far, the rest still need prefix commands.
+    warn!("prefix commands are deprecated and will be replaced by slash commands");
 
-    let intents = Intents::GUILDS
+    let mut intents = Intents::GUILDS
         | Intents::GUILD_MESSAGES
         | Intents::GUILD_MESSAGE_REACTIONS
         | Intents::MESSAGE_CONTENT;
 
-    let config = Config::new(token, intents);
+    if config.track_scheduled_events {
+        intents |= Intents::GUILD_SCHEDULED_EVENTS;
+    }
+
+    if config.track_presence {
+        intents |= Intents::GUILD_PRESENCES;
+    }
+
+    let gateway_config = GatewayConfig::new(token, intents);
 
     // Configure gateway connection.
-    // TODO: Bring back multiple shards.
-    let mut shard = Shard::with_config(ShardId::ONE, config);
+    // Each instance runs a single shard, larger bots run an instance per shard.
+    let shard_id = config.shard();
+    info!(
+        "connecting as shard {} of {}",
+        shard_id.number(),
+        shard_id.total()
+    );
+
+    let mut shard = Shard::with_config(shard_id, gateway_config);
 
     let shutdown = Arc::new(AtomicBool::new(false));
 
@@ -154,16 +247,23 @@ async fn main() -> Result<()> {
         }
 
         // Update the cache with the event.
-        // Done before we spawn the tasks to ensure the cache is updated.
-        cache.update(&event);
+        // Done before we spawn the tasks to ensure the cache is updated. Message cache writes are
+        // queued instead, but ahead of anything the tasks queue.
+        cache.update(&event).await;
 
         let context = Context {
+            config: config.clone(),
             user: user.clone(),
             owners: owners.clone(),
             http: http.clone(),
             cache: cache.clone(),
             social: social.clone(),
+            session: session.clone(),
+            rate_limiter: rate_limiter.clone(),
             pool: pool.clone(),
+            edge_writes: edge_writes.clone(),
+            gateway_latency: shard.latency().average(),
+            shard: shard_id,
         };
 
         tokio::spawn(async move {
@@ -175,12 +275,25 @@ async fn main() -> Result<()> {
 
     info!("event stream ended, exiting");
 
+    if let Some(path) = &graph_file {
+        let social = social.lock();
+        match save_graph_to_file(path, social.get_all_graphs()) {
+            Ok(()) => info!("saved graphs to {}", path.display()),
+            Err(error) => error!("failed to save graphs to {}: {:?}", path.display(), error),
+        }
+    }
+
+    if let Some(pool) = &pool {
+        match social::save_graphs_to_database(pool, &social, &edge_writes).await {
+            Ok(()) => info!("saved graphs to the database"),
+            Err(error) => error!("failed to save graphs to the database: {:?}", error),
+        }
+    }
+
     Ok(())
 }
 
-async fn get_application_owners(http: &Client) -> Result<HashSet<Id<UserMarker>>> {
-    let info = http.current_user_application().await?.model().await?;
-
+fn get_application_owners(info: &Application) -> HashSet<Id<UserMarker>> {
     let mut owners = HashSet::new();
 
     if let Some(team) = &info.team {
@@ -193,10 +306,16 @@ async fn get_application_owners(http: &Client) -> Result<HashSet<Id<UserMarker>>
         owners.insert(owner.id);
     }
 
-    Ok(owners)
+    owners
 }
 
 async fn handle_event(context: &Context, event: &Event) -> Result<()> {
+    if commands::handle_interaction_event(context, event).await? {
+        return Ok(());
+    }
+
+    // The only caller, kept until prefix commands are removed.
+    #[allow(deprecated)]
     if commands::handle_event(context, event).await? {
         // If the command processor consumed it, don't do any more processing.
         return Ok(());
@@ -0,0 +1,83 @@
+use parking_lot::Mutex;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use twilight_model::id::marker::GuildMarker;
+use twilight_model::id::Id;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How many GraphViz processes can run at once, across every guild.
+const MAX_CONCURRENT_RENDERS: usize = 2;
+
+/// Limits on rendering graphs, since each render runs a GraphViz process that can take a while
+/// on big guilds.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// How long a guild waits between graph, network and compare commands.
+    cooldown: Duration,
+    last_render: Mutex<HashMap<Id<GuildMarker>, Instant>>,
+    renders: Semaphore,
+}
+
+impl RateLimiter {
+    pub fn new(cooldown: Duration) -> Self {
+        RateLimiter {
+            cooldown,
+            last_render: Mutex::new(HashMap::new()),
+            renders: Semaphore::new(MAX_CONCURRENT_RENDERS),
+        }
+    }
+
+    /// Start the guild's cooldown, or how much longer it has left if it's already running.
+    pub fn try_start_render(&self, guild_id: Id<GuildMarker>) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut last_render = self.last_render.lock();
+
+        if let Some(&started) = last_render.get(&guild_id) {
+            let elapsed = now.saturating_duration_since(started);
+            if elapsed < self.cooldown {
+                return Err(self.cooldown - elapsed);
+            }
+        }
+
+        last_render.insert(guild_id, now);
+
+        // Nobody else needs the finished cooldowns, so don't let them pile up.
+        let cooldown = self.cooldown;
+        last_render.retain(|_, &mut started| now.saturating_duration_since(started) < cooldown);
+
+        Ok(())
+    }
+
+    /// Wait for a turn to run GraphViz, which lasts until the permit is dropped.
+    pub async fn acquire_render(&self) -> SemaphorePermit<'_> {
+        // We never close the semaphore, so this can't fail.
+        self.renders.acquire().await.unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+    use twilight_model::id::Id;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_try_start_render() {
+        let limiter = RateLimiter::new(Duration::from_secs(60));
+        let (guild, other_guild) = (Id::new(1), Id::new(2));
+
+        assert!(limiter.try_start_render(guild).is_ok());
+
+        let remaining = limiter.try_start_render(guild).unwrap_err();
+        assert!(remaining > Duration::from_secs(59) && remaining <= Duration::from_secs(60));
+
+        // Each guild has its own cooldown.
+        assert!(limiter.try_start_render(other_guild).is_ok());
+
+        let limiter = RateLimiter::new(Duration::ZERO);
+        assert!(limiter.try_start_render(guild).is_ok());
+        assert!(limiter.try_start_render(guild).is_ok());
+    }
+}
@@ -0,0 +1,135 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use twilight_model::id::marker::{ChannelMarker, GuildMarker, UserMarker};
+use twilight_model::id::Id;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::social::graph::Palette;
+use crate::social::inference::{Interaction, RelationshipChange};
+
+/// Options last used with the graph command in a guild.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GraphPreferences {
+    pub weight_threshold: Option<f32>,
+    pub palette: Option<Palette>,
+}
+
+/// An interaction and the relationship changes inferred from it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessedInteraction {
+    pub interaction: Interaction,
+    pub changes: Vec<RelationshipChange>,
+}
+
+/// How long a forget-channel command waits to be confirmed.
+const FORGET_CHANNEL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A forget-channel command waiting for its author to confirm it.
+#[derive(Debug, Clone, Copy)]
+struct PendingForgetChannel {
+    user_id: Id<UserMarker>,
+    channel_id: Id<ChannelMarker>,
+    requested: Instant,
+}
+
+/// Per-guild command options remembered between invocations, so they don't need re-typing.
+/// Nothing here is persisted, it's all forgotten when the bot restarts.
+#[derive(Debug, Default)]
+pub struct Session {
+    graph: Mutex<HashMap<Id<GuildMarker>, GraphPreferences>>,
+    /// The most recently processed interaction in each guild and the changes it made,
+    /// for debugging inference.
+    last_interaction: Mutex<HashMap<Id<GuildMarker>, ProcessedInteraction>>,
+    pending_forget_channel: Mutex<HashMap<Id<GuildMarker>, PendingForgetChannel>>,
+}
+
+impl Session {
+    pub fn get_graph_preferences(&self, guild_id: Id<GuildMarker>) -> GraphPreferences {
+        self.graph
+            .lock()
+            .get(&guild_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn update_graph_preferences(
+        &self,
+        guild_id: Id<GuildMarker>,
+        update: impl FnOnce(&mut GraphPreferences),
+    ) {
+        update(self.graph.lock().entry(guild_id).or_default());
+    }
+
+    pub fn get_last_interaction(&self, guild_id: Id<GuildMarker>) -> Option<ProcessedInteraction> {
+        self.last_interaction.lock().get(&guild_id).cloned()
+    }
+
+    pub fn set_last_interaction(&self, processed: ProcessedInteraction) {
+        self.last_interaction
+            .lock()
+            .insert(processed.interaction.guild, processed);
+    }
+
+    /// Remember that a user asked to forget a channel, replacing any earlier request in the guild.
+    pub fn request_forget_channel(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) {
+        self.pending_forget_channel.lock().insert(
+            guild_id,
+            PendingForgetChannel {
+                user_id,
+                channel_id,
+                requested: Instant::now(),
+            },
+        );
+    }
+
+    /// Whether the user recently asked to forget the same channel, using up their request.
+    pub fn confirm_forget_channel(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) -> bool {
+        let mut pending = self.pending_forget_channel.lock();
+
+        match pending.get(&guild_id) {
+            Some(request)
+                if request.user_id == user_id
+                    && request.channel_id == channel_id
+                    && request.requested.elapsed() < FORGET_CHANNEL_TIMEOUT =>
+            {
+                pending.remove(&guild_id);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use twilight_model::id::Id;
+
+    #[test]
+    fn test_confirm_forget_channel() {
+        let session = Session::default();
+        let (guild, user, channel) = (Id::new(1), Id::new(2), Id::new(3));
+
+        assert!(!session.confirm_forget_channel(guild, user, channel));
+
+        session.request_forget_channel(guild, user, channel);
+        assert!(!session.confirm_forget_channel(guild, Id::new(4), channel));
+        assert!(!session.confirm_forget_channel(guild, user, Id::new(5)));
+        assert!(session.confirm_forget_channel(guild, user, channel));
+
+        // Each request can only be confirmed once.
+        assert!(!session.confirm_forget_channel(guild, user, channel));
+    }
+}
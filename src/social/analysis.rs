@@ -0,0 +1,180 @@
+use anyhow::Result;
+use sqlx::MySqlPool;
+use twilight_model::id::marker::{GuildMarker, UserMarker};
+use twilight_model::id::Id;
+
+use crate::social::graph::SocialGraph;
+use crate::social::history::{get_guild_interaction_count, get_interaction_counts};
+use crate::social::inference::{
+    RelationshipChangeReason, RelationshipStrength, RELATIONSHIP_DECAY_GLOBAL,
+};
+use crate::sync::CheckedMutex;
+use crate::util::get_current_timestamp_ms;
+
+use std::time::Duration;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// The fraction of the guild's directed edges, from 0 to 1, whose first recorded interaction
+/// was in the last `window_days` days.
+pub async fn network_growth_rate(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    window_days: u32,
+) -> Result<f64> {
+    let since_ms = get_current_timestamp_ms().saturating_sub(window_days as u64 * DAY_MS);
+
+    let (total, new): (i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*), CAST(COALESCE(SUM(first_seen > ?), 0) AS SIGNED) FROM (SELECT MIN(timestamp) AS first_seen FROM events WHERE guild = ? AND source != target GROUP BY source, target) AS edges",
+    )
+    .bind(since_ms)
+    .bind(guild_id.get())
+    .fetch_one(pool)
+    .await?;
+
+    if total == 0 {
+        return Ok(0.0);
+    }
+
+    Ok(new as f64 / total as f64)
+}
+
+/// The average number of interactions per day over the last `window_days` days.
+pub async fn get_interaction_velocity(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    window_days: u32,
+) -> Result<f64> {
+    get_interaction_velocity_before(pool, guild_id, window_days, get_current_timestamp_ms()).await
+}
+
+/// Like `get_interaction_velocity`, for the same length of time just before that, to compare
+/// against.
+pub async fn get_previous_interaction_velocity(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    window_days: u32,
+) -> Result<f64> {
+    let until_ms = get_current_timestamp_ms().saturating_sub(window_days as u64 * DAY_MS);
+
+    get_interaction_velocity_before(pool, guild_id, window_days, until_ms).await
+}
+
+async fn get_interaction_velocity_before(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    window_days: u32,
+    until_ms: u64,
+) -> Result<f64> {
+    if window_days == 0 {
+        return Ok(0.0);
+    }
+
+    let since_ms = until_ms.saturating_sub(window_days as u64 * DAY_MS);
+    let count = get_guild_interaction_count(pool, guild_id, since_ms, until_ms).await?;
+
+    Ok(count as f64 / window_days as f64)
+}
+
+/// How active each of the guild's users is, from 0 to 100, highest first.
+/// See `UserRelationshipGraphMap::activity_scores` for how it's worked out.
+pub async fn user_activity_scores(
+    pool: &MySqlPool,
+    social: &CheckedMutex<SocialGraph>,
+    guild_id: Id<GuildMarker>,
+) -> Result<Vec<(Id<UserMarker>, f64)>> {
+    let since_ms = get_current_timestamp_ms().saturating_sub(7 * DAY_MS);
+    let counts = get_interaction_counts(pool, guild_id, since_ms).await?;
+
+    let snapshot = {
+        let social = social.lock();
+        social.get_graph_snapshot(guild_id)
+    };
+
+    Ok(snapshot.build_guild_graph().activity_scores(&counts))
+}
+
+/// How active the user is compared to the rest of the guild, from 0 to 100.
+pub async fn user_activity_score(
+    pool: &MySqlPool,
+    social: &CheckedMutex<SocialGraph>,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+) -> Result<f64> {
+    let scores = user_activity_scores(pool, social, guild_id).await?;
+
+    Ok(scores
+        .into_iter()
+        .find(|&(id, _)| id == user_id)
+        .map_or(0.0, |(_, score)| score))
+}
+
+/// Estimate a pair's weight after `guild_interactions` more interactions in the guild,
+/// `additional_interactions` of which are mentions between the two of them.
+///
+/// Only the guild-wide decay is modelled, as there's no telling which channels the future
+/// interactions will be in. Both directions of the pair decay, and neither can go below zero.
+pub fn project_pair_weight(
+    current_weight: RelationshipStrength,
+    guild_interactions: u64,
+    additional_interactions: u64,
+) -> RelationshipStrength {
+    let decay = 2.0 * guild_interactions as RelationshipStrength * RELATIONSHIP_DECAY_GLOBAL;
+    let gained = additional_interactions as RelationshipStrength
+        * RelationshipChangeReason::MessageDirectMention.get_change_strength();
+
+    (current_weight + decay).max(0.0) + gained
+}
+
+/// The current weight between two users and a projection of it `period` from now, assuming
+/// the guild stays as busy as it was over the last `period`. See `project_pair_weight`.
+pub async fn simulate_pair_weight(
+    pool: &MySqlPool,
+    social: &CheckedMutex<SocialGraph>,
+    guild_id: Id<GuildMarker>,
+    users: (Id<UserMarker>, Id<UserMarker>),
+    period: Duration,
+    additional_interactions: u64,
+) -> Result<(RelationshipStrength, RelationshipStrength)> {
+    let now_ms = get_current_timestamp_ms();
+    let since_ms = now_ms.saturating_sub(period.as_millis() as u64);
+    let guild_interactions = get_guild_interaction_count(pool, guild_id, since_ms, now_ms).await?;
+
+    let snapshot = {
+        let social = social.lock();
+        social.get_graph_snapshot(guild_id)
+    };
+
+    let current_weight = snapshot
+        .build_guild_graph()
+        .get_pair_weight(users.0, users.1)
+        .unwrap_or_default();
+
+    // The simulated interactions are part of the guild's activity too.
+    let projected_weight = project_pair_weight(
+        current_weight,
+        guild_interactions + additional_interactions,
+        additional_interactions,
+    );
+
+    Ok((current_weight, projected_weight))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::project_pair_weight;
+    use crate::social::inference::RelationshipChangeReason;
+
+    #[test]
+    fn test_project_pair_weight() {
+        let mention = RelationshipChangeReason::MessageDirectMention.get_change_strength();
+
+        assert_eq!(project_pair_weight(5.0, 0, 0), 5.0);
+        assert_eq!(project_pair_weight(5.0, 0, 3), 5.0 + 3.0 * mention);
+
+        // Decay lowers the weight, but never below zero.
+        assert!(project_pair_weight(5.0, 1000, 0) < 5.0);
+        assert_eq!(project_pair_weight(0.1, 1_000_000, 0), 0.0);
+        assert_eq!(project_pair_weight(0.1, 1_000_000, 2), 2.0 * mention);
+    }
+}
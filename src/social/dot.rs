@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Attributes that only change how a node or edge looks. Anything that can read files
+/// (`image`, `shapefile`, ...), replace our labels, or affect the rest of the graph is left out.
+const ALLOWED_ATTRIBUTES: [&str; 22] = [
+    "arrowhead",
+    "arrowsize",
+    "arrowtail",
+    "color",
+    "dir",
+    "distortion",
+    "fillcolor",
+    "fixedsize",
+    "fontcolor",
+    "fontname",
+    "fontsize",
+    "gradientangle",
+    "height",
+    "margin",
+    "orientation",
+    "penwidth",
+    "peripheries",
+    "regular",
+    "shape",
+    "sides",
+    "skew",
+    "style",
+];
+
+/// A validated list of DOT attributes, like `shape=hexagon fontname="Arial"`.
+///
+/// Only a small, safe subset of DOT is accepted, and the attributes are always re-serialized
+/// with quoted values, so they can't escape the attribute list they're placed in.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct DotAttributes(Vec<(String, String)>);
+
+impl DotAttributes {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromStr for DotAttributes {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut attributes = Vec::new();
+        let mut chars = s.chars().peekable();
+
+        loop {
+            while matches!(chars.peek(), Some(&c) if c.is_whitespace() || c == ',') {
+                chars.next();
+            }
+
+            if chars.peek().is_none() {
+                break;
+            }
+
+            let mut key = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+
+                key.push(c);
+                chars.next();
+            }
+
+            if key.is_empty() {
+                bail!(
+                    "expected an attribute name, found {:?}",
+                    chars.peek().unwrap()
+                );
+            }
+
+            if !ALLOWED_ATTRIBUTES.contains(&key.as_str()) {
+                bail!("{} is not an allowed attribute", key);
+            }
+
+            while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+                chars.next();
+            }
+
+            if chars.next() != Some('=') {
+                bail!("expected = after {}", key);
+            }
+
+            while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+                chars.next();
+            }
+
+            let mut value = String::new();
+            if chars.peek() == Some(&'"') {
+                chars.next();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('"') => value.push('"'),
+                            _ => bail!("only \\\" escapes are allowed in the value of {}", key),
+                        },
+                        Some(c) if c.is_control() => {
+                            bail!("control characters aren't allowed in the value of {}", key)
+                        }
+                        Some(c) => value.push(c),
+                        None => bail!("unterminated string in the value of {}", key),
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '#' | '-')) {
+                        break;
+                    }
+
+                    value.push(c);
+                    chars.next();
+                }
+
+                if value.is_empty() {
+                    bail!("expected a value for {}", key);
+                }
+            }
+
+            attributes.push((key, value));
+        }
+
+        Ok(DotAttributes(attributes))
+    }
+}
+
+impl fmt::Display for DotAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+
+            write!(f, "{} = \"{}\"", key, value.replace('"', "\\\""))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DotAttributes;
+
+    #[test]
+    fn test_parse_dot_attributes() {
+        let attributes: DotAttributes = "shape=hexagon fontname=\"Arial\", penwidth = 2.5"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            attributes.to_string(),
+            "shape = \"hexagon\", fontname = \"Arial\", penwidth = \"2.5\"",
+        );
+
+        let attributes: DotAttributes = "color=\"a \\\" b\"".parse().unwrap();
+        assert_eq!(attributes.to_string(), "color = \"a \\\" b\"");
+
+        assert!("".parse::<DotAttributes>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_dot_attributes_rejects_injection() {
+        for input in &[
+            "image=\"/etc/passwd\"",
+            "label=hi",
+            "shape=box ] a -- b [",
+            "shape=box; 1 -- 2",
+            "color=\"red\\\\\"",
+            "color=\"red",
+            "color=<<b>red</b>>",
+            "color=\"red\nblue\"",
+            "shape",
+        ] {
+            assert!(input.parse::<DotAttributes>().is_err(), "{}", input);
+        }
+    }
+}
@@ -1,26 +1,43 @@
 use anyhow::Result as AnyhowResult;
 use futures::future::join_all;
+use lru::LruCache;
 use serde::de::{Deserialize, Deserializer, Error as DeserializerError, MapAccess, Visitor};
 use serde::ser::{Serialize, SerializeMap, Serializer};
-use tracing::error;
-use twilight_model::id::marker::{ChannelMarker, GuildMarker, UserMarker};
+use serde::Deserialize as DeserializeDerive;
+use serde::Serialize as SerializeDerive;
+use tracing::{debug, error};
+use twilight_model::guild::scheduled_event::{GuildScheduledEvent, Status as ScheduledEventStatus};
+use twilight_model::id::marker::{
+    ChannelMarker, GuildMarker, MessageMarker, ScheduledEventMarker, UserMarker,
+};
 use twilight_model::id::Id;
 use twilight_model::user::User;
 use unicode_segmentation::UnicodeSegmentation;
 
-use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::fs::File;
 use std::io::{ErrorKind as IoErrorKind, Read, Write};
-use std::num::ParseIntError;
+use std::num::{NonZeroUsize, ParseIntError};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use super::dot::DotAttributes;
 use super::inference::{
-    InferenceState, Interaction, RelationshipChange, RelationshipStrength, RELATIONSHIP_DECAY,
+    default_rules, InferenceRule, InferenceState, Interaction, RelationshipChange,
+    RelationshipChangeReason, RelationshipStrength, RELATIONSHIP_DECAY,
 };
-use crate::cache::CachedMember;
+use crate::cache::{CachedMember, CachedUser};
 use crate::context::Context;
-use crate::social::inference::{InteractionType, RELATIONSHIP_DECAY_GLOBAL};
+use crate::social::graph_utils::{
+    all_triangles, bfs_distances, bfs_neighbours, reachable_from, Neighbours,
+};
+use crate::social::history::get_edge_reasons;
+use crate::social::inference::{InteractionType, ScheduledEventState, RELATIONSHIP_DECAY_GLOBAL};
+use crate::social::presence::PresenceTracker;
+use crate::util::{blend_colors, get_current_timestamp_ms};
 
 // TODO: This doesn't handle counting wide characters very well,
 //       Probably want to pull in the unicode-width crate for that.
@@ -46,6 +63,196 @@ fn get_label(mut name: String) -> String {
     }
 }
 
+/// Escape a string for use inside a quoted (non-HTML) DOT label.
+/// Node labels are HTML-like and are entity-escaped separately.
+fn escape_dot_label(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '\\' | '"' | '<' | '>' | '{' | '}' | '|' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+const FONT_NAME: &str = "Noto Sans Display, Noto Emoji";
+
+const BG_LIGHT: u32 = 0xFFFFFF;
+const FG_LIGHT: u32 = 0x060607;
+const BG_DARK: u32 = 0x36393F;
+const FG_DARK: u32 = 0xFFFFFF;
+/// Outline and text for users who haven't been active recently, see `DotOptions::active_users`.
+const INACTIVE_COLOR: u32 = 0x808080;
+
+/// The background and foreground colors to draw with, from the color scheme and palette.
+fn get_dot_colors(options: &DotOptions) -> (u32, u32) {
+    let (bg_color, fg_color) = match options.color_scheme {
+        ColorScheme::Light => (BG_LIGHT, FG_LIGHT),
+        ColorScheme::Dark => (BG_DARK, FG_DARK),
+    };
+
+    let palette = options.palette.colors();
+
+    (
+        palette.background.unwrap_or(bg_color),
+        palette.font_color.unwrap_or(fg_color),
+    )
+}
+
+/// The Graphviz layout engines that can be asked for by name. Each is also the name of the
+/// Graphviz binary that runs it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LayoutEngine {
+    Dot,
+    Neato,
+    Fdp,
+    Sfdp,
+    Circo,
+    Twopi,
+}
+
+impl LayoutEngine {
+    pub const ALL: [LayoutEngine; 6] = [
+        LayoutEngine::Dot,
+        LayoutEngine::Neato,
+        LayoutEngine::Fdp,
+        LayoutEngine::Sfdp,
+        LayoutEngine::Circo,
+        LayoutEngine::Twopi,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayoutEngine::Dot => "dot",
+            LayoutEngine::Neato => "neato",
+            LayoutEngine::Fdp => "fdp",
+            LayoutEngine::Sfdp => "sfdp",
+            LayoutEngine::Circo => "circo",
+            LayoutEngine::Twopi => "twopi",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|engine| engine.name() == name)
+    }
+}
+
+/// Pick a Graphviz layout engine that suits a graph with `node_count` nodes.
+///
+/// Small graphs are few enough that `dot`'s ranked layout stays readable and is the tidiest.
+/// Past 20 nodes it turns into long rows, so we switch to `fdp`'s spring layout, which shows
+/// communities as clumps. `fdp` slows down badly in the hundreds of nodes, where `sfdp`, its
+/// multilevel version, is the only one that finishes in a reasonable time.
+pub fn select_layout_engine(node_count: usize) -> LayoutEngine {
+    match node_count {
+        0..=20 => LayoutEngine::Dot,
+        21..=200 => LayoutEngine::Fdp,
+        _ => LayoutEngine::Sfdp,
+    }
+}
+
+/// The layout and color attributes shared by every graph we draw.
+fn push_dot_graph_attributes(lines: &mut Vec<String>, options: &DotOptions, node_count: usize) {
+    let (bg_color, fg_color) = get_dot_colors(options);
+    let layout = options
+        .layout
+        .unwrap_or_else(|| select_layout_engine(node_count));
+
+    lines.push(String::from("    dpi = \"144\""));
+    lines.push(String::from("    pad = \"0.3\""));
+    lines.push(format!("    layout = \"{}\"", layout.name()));
+    lines.push(String::from("    K = \"0.1\""));
+    lines.push(String::from("    splines = \"true\""));
+    lines.push(String::from("    overlap = \"30:true\""));
+    lines.push(String::from("    outputorder = \"edgesfirst\""));
+    lines.push(format!("    color = \"#{:06X}\"", fg_color));
+    lines.push(format!("    fontcolor = \"#{:06X}\"", fg_color));
+
+    if options.transparent {
+        lines.push(String::from("    bgcolor = \"transparent\""));
+    } else {
+        lines.push(format!("    bgcolor = \"#{:06X}\"", bg_color));
+    }
+}
+
+/// Format a graph of channels linked by how many users they share, see
+/// `SocialGraph::channel_interaction_graph`.
+pub fn format_channel_dot(
+    edges: &ChannelGraph,
+    names: &HashMap<Id<ChannelMarker>, String>,
+    options: &DotOptions,
+) -> AnyhowResult<String> {
+    if edges.is_empty() {
+        anyhow::bail!("Not enough channels to create a graph");
+    }
+
+    let (bg_color, fg_color) = get_dot_colors(options);
+
+    let mut channels: Vec<_> = edges.keys().flat_map(|&(a, b)| [a, b]).collect();
+    channels.sort();
+    channels.dedup();
+
+    let mut edges: Vec<_> = edges.iter().collect();
+    edges.sort();
+
+    let mut lines = Vec::with_capacity(16 + channels.len() + edges.len() + 1);
+
+    lines.push(String::from("graph {"));
+    push_dot_graph_attributes(&mut lines, options, channels.len());
+    lines.push(format!(
+        "    node [ fontname = \"{}\", style = \"filled\", color = \"#{:06X}\", fillcolor = \"#{:06X}\", fontcolor = \"#{:06X}\" ]",
+        FONT_NAME, fg_color, bg_color, fg_color,
+    ));
+
+    for channel_id in channels {
+        let name = match names.get(&channel_id) {
+            Some(name) => format!("#{}", name),
+            None => channel_id.to_string(),
+        };
+
+        lines.push(format!(
+            "    {} [ label = \"{}\"{} ]",
+            channel_id,
+            escape_dot_label(&name),
+            format_extra_attributes(options.node_attributes.as_ref()),
+        ));
+    }
+
+    for (&(a, b), &shared_users) in edges {
+        lines.push(format!(
+            "    {} -- {} [ weight = \"{}\", penwidth = \"{}\", color = \"#{:06X}\"{} ]",
+            a,
+            b,
+            shared_users,
+            1.0 + (shared_users as f32).log10(),
+            fg_color,
+            format_extra_attributes(options.edge_attributes.as_ref()),
+        ));
+    }
+
+    lines.push(String::from("}"));
+
+    Ok(lines.join("\n"))
+}
+
+/// Attributes to append to a node or edge's list, which take priority over the earlier ones.
+fn format_extra_attributes(attributes: Option<&DotAttributes>) -> String {
+    match attributes {
+        Some(attributes) if !attributes.is_empty() => format!(", {}", attributes),
+        _ => String::new(),
+    }
+}
+
 fn calculate_luma(color: u32) -> f32 {
     let r = ((color >> 16) & 0xFF) as f32;
     let g = ((color >> 8) & 0xFF) as f32;
@@ -60,14 +267,304 @@ pub enum ColorScheme {
     Dark,
 }
 
+/// A built-in set of colors for the rendered graph, chosen with `--colour-scheme`.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum Palette {
+    /// Our usual colors, following the light or dark color scheme.
+    #[default]
+    Default,
+    Discord,
+    Pastel,
+    Monochrome,
+    HighContrast,
+}
+
+/// The colors a `Palette` draws with. Anything left as `None` follows the color scheme.
+#[derive(Debug, Clone, Copy)]
+pub struct PaletteColors {
+    /// Fill for a node, from its normalized total weight. Nodes are filled with the background
+    /// otherwise.
+    pub node_fill: Option<fn(RelationshipStrength) -> u32>,
+    /// Color for an edge, from its normalized weight. Edges match the text otherwise.
+    pub edge_color: Option<fn(RelationshipStrength) -> u32>,
+    pub background: Option<u32>,
+    pub font_color: Option<u32>,
+}
+
+/// Scales weights relative to the strongest in a graph, so a graph looks the same however busy
+/// its guild is. Only used for how things are drawn, the raw weights are kept everywhere else.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WeightNormalizer {
+    max_weight: RelationshipStrength,
+}
+
+impl WeightNormalizer {
+    pub fn new(weights: impl IntoIterator<Item = RelationshipStrength>) -> Self {
+        WeightNormalizer {
+            max_weight: weights.into_iter().fold(0.0, RelationshipStrength::max),
+        }
+    }
+
+    /// The weight as a fraction of the strongest, from 0 to 1.
+    pub fn normalize(&self, raw_weight: RelationshipStrength) -> RelationshipStrength {
+        if self.max_weight <= 0.0 {
+            return 0.0;
+        }
+
+        (raw_weight.max(0.0) / self.max_weight).min(1.0)
+    }
+}
+
+impl Palette {
+    pub const ALL: [Palette; 5] = [
+        Palette::Default,
+        Palette::Discord,
+        Palette::Pastel,
+        Palette::Monochrome,
+        Palette::HighContrast,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Palette::Default => "default",
+            Palette::Discord => "discord",
+            Palette::Pastel => "pastel",
+            Palette::Monochrome => "monochrome",
+            Palette::HighContrast => "high-contrast",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|palette| palette.name() == name)
+    }
+
+    pub fn colors(&self) -> PaletteColors {
+        match self {
+            Palette::Default => PaletteColors {
+                node_fill: None,
+                edge_color: None,
+                background: None,
+                font_color: None,
+            },
+            Palette::Discord => PaletteColors {
+                node_fill: Some(|weight| blend_colors(0x4E5058, 0x5865F2, weight)),
+                edge_color: Some(|weight| blend_colors(0x80848E, 0xFFFFFF, weight)),
+                background: Some(0x313338),
+                font_color: Some(0xFFFFFF),
+            },
+            Palette::Pastel => PaletteColors {
+                node_fill: Some(|weight| blend_colors(0xBDE0FE, 0xFFC8DD, weight)),
+                edge_color: Some(|weight| blend_colors(0xCDB4DB, 0x9D8DF1, weight)),
+                background: Some(0xFDF6F0),
+                font_color: Some(0x4A4A4A),
+            },
+            Palette::Monochrome => PaletteColors {
+                node_fill: Some(|weight| blend_colors(0xF0F0F0, 0x808080, weight)),
+                edge_color: Some(|weight| blend_colors(0xC0C0C0, 0x202020, weight)),
+                background: Some(0xFFFFFF),
+                font_color: Some(0x000000),
+            },
+            Palette::HighContrast => PaletteColors {
+                node_fill: Some(|_| 0xFFFF00),
+                edge_color: Some(|_| 0xFFFFFF),
+                background: Some(0x000000),
+                font_color: Some(0xFFFFFF),
+            },
+        }
+    }
+}
+
+/// How nodes are labelled in the rendered graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LabelMode {
+    /// The member's nickname or username, resolved from the cache.
+    DisplayName,
+    /// The raw user ID, without touching the cache.
+    UserId,
+    /// Sequential integers, without touching the cache.
+    Anonymous,
+}
+
+/// How `to_dot` should draw the graph.
+#[derive(Debug, Clone)]
+pub struct DotOptions {
+    pub color_scheme: ColorScheme,
+    pub palette: Palette,
+    pub transparent: bool,
+    pub label_mode: LabelMode,
+    /// Draw one edge per pair of users with the stronger direction's weight,
+    /// rather than an arrow for each direction.
+    pub undirected: bool,
+    /// Edges weaker than this are left out, along with any users left without edges.
+    pub weight_threshold: RelationshipStrength,
+    /// Extra attributes for every node, overriding our own.
+    pub node_attributes: Option<DotAttributes>,
+    /// Extra attributes for every edge, overriding our own.
+    pub edge_attributes: Option<DotAttributes>,
+    /// Users to fill with a specific color instead of outlining with their role color.
+    pub node_colors: HashMap<Id<UserMarker>, u32>,
+    /// Users connected to fewer other users than this are left out, along with their edges.
+    pub min_degree: usize,
+    /// `None` to pick one from the graph's size with `select_layout_engine`.
+    pub layout: Option<LayoutEngine>,
+    /// Users who've been active recently. Everyone else is grayed out, unless this is `None`.
+    pub active_users: Option<HashSet<Id<UserMarker>>>,
+    /// Draw bots too, as diamonds, rather than leaving them out.
+    pub include_bots: bool,
+}
+
+impl Default for DotOptions {
+    fn default() -> Self {
+        DotOptions {
+            color_scheme: ColorScheme::Dark,
+            palette: Palette::Default,
+            transparent: false,
+            label_mode: LabelMode::DisplayName,
+            undirected: false,
+            weight_threshold: 0.0,
+            node_attributes: None,
+            edge_attributes: None,
+            node_colors: HashMap::new(),
+            min_degree: 0,
+            layout: None,
+            active_users: None,
+            include_bots: false,
+        }
+    }
+}
+
+/// How edge weights along a path are combined into a single strength.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PathWeightAggregation {
+    Product,
+    /// The weakest link in the chain.
+    Minimum,
+    Sum,
+}
+
+/// A user who is in both of the ego graphs passed to `SocialGraph::merge_subgraphs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SharedNode {
+    pub user_id: Id<UserMarker>,
+    /// The total weight of the user's edges in the first graph.
+    pub first_strength: RelationshipStrength,
+    /// The total weight of the user's edges in the second graph.
+    pub second_strength: RelationshipStrength,
+}
+
+/// Statistics about a guild's graph, for when the graph itself isn't needed.
+#[derive(Debug, Clone, SerializeDerive)]
+pub struct GraphSummary {
+    pub guild_id: Id<GuildMarker>,
+    pub node_count: usize,
+    pub edge_count: usize,
+    /// Fraction of the possible directed edges between the nodes that exist.
+    pub density: f64,
+    /// The most connected users by their total edge weight, strongest first.
+    pub top_users: Vec<(Id<UserMarker>, f64)>,
+    /// Millisecond Unix timestamp of the most recent interaction, or 0 if there hasn't been one
+    /// since the bot started.
+    pub last_interaction_ms: u64,
+}
+
+/// Edges ready to be formatted, with what was looked up about the users in them.
+struct LabelledEdges {
+    edges: HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>,
+    /// Each user's label and role color. Users missing from here are left out.
+    names_and_colors: HashMap<Id<UserMarker>, (String, Option<u32>)>,
+    /// Users who are bots, only when `DotOptions::include_bots` kept them in.
+    bots: HashSet<Id<UserMarker>>,
+}
+
+/// A user in the JSON read by d3-force, see `UserRelationshipGraphMap::to_json_d3force`.
+#[derive(Debug, Clone, SerializeDerive)]
+struct D3Node {
+    id: String,
+    /// The display name, when `id` is the user's ID.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// The user's community, largest first.
+    group: usize,
+}
+
+#[derive(Debug, Clone, SerializeDerive)]
+struct D3Link {
+    source: String,
+    target: String,
+    value: RelationshipStrength,
+}
+
+#[derive(Debug, Clone, SerializeDerive)]
+struct D3Graph {
+    nodes: Vec<D3Node>,
+    links: Vec<D3Link>,
+}
+
+/// An edge in a JSON export, see `UserRelationshipGraphMap::to_json`.
+#[derive(Debug, Clone, PartialEq, SerializeDerive, DeserializeDerive)]
+struct JsonEdge {
+    source: Id<UserMarker>,
+    target: Id<UserMarker>,
+    weight: RelationshipStrength,
+    /// The reason recorded most often for the edge, if there's a database to ask.
+    reason: Option<RelationshipChangeReason>,
+}
+
+/// A guild's graph exported for other programs, with every user in it and every edge.
+#[derive(Debug, Clone, PartialEq, SerializeDerive, DeserializeDerive)]
+struct JsonGraph {
+    nodes: Vec<CachedUser>,
+    edges: Vec<JsonEdge>,
+}
+
+/// Statistics about a user's interactions in a guild, kept alongside the guild's graphs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, SerializeDerive)]
+pub struct NodeMetadata {
+    /// Millisecond Unix timestamp of the user's first interaction.
+    pub first_seen_ms: u64,
+    /// Millisecond Unix timestamp of the user's most recent interaction.
+    pub last_seen_ms: u64,
+    pub total_interactions_as_source: u64,
+    pub total_interactions_as_target: u64,
+    /// The user asked not to be tracked, so nothing new is inferred about them.
+    pub is_opted_out: bool,
+}
+
+impl NodeMetadata {
+    fn record(&mut self, timestamp: u64) {
+        if self.first_seen_ms == 0 || timestamp < self.first_seen_ms {
+            self.first_seen_ms = timestamp;
+        }
+
+        self.last_seen_ms = self.last_seen_ms.max(timestamp);
+    }
+}
+
+/// The key numbers for a guild's graph, for listing many guilds at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuildStats {
+    pub guild_id: Id<GuildMarker>,
+    pub node_count: usize,
+    pub edge_count: usize,
+    /// Millisecond Unix timestamp of the most recent interaction, or 0 if there hasn't been one
+    /// since the bot started.
+    pub last_interaction_ms: u64,
+}
+
 #[derive(Clone, Debug)]
 pub struct UserRelationshipGraphMap(
     HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>,
+    /// When each edge was last strengthened, as a millisecond Unix timestamp. This isn't written
+    /// to the data dir, so edges loaded from there have no entry until they next change.
+    HashMap<(Id<UserMarker>, Id<UserMarker>), u64>,
 );
 
 impl UserRelationshipGraphMap {
     fn new() -> Self {
-        UserRelationshipGraphMap(HashMap::new())
+        UserRelationshipGraphMap(HashMap::new(), HashMap::new())
     }
 
     fn new_from_path(path: &Path) -> std::io::Result<Self> {
@@ -92,7 +589,7 @@ impl UserRelationshipGraphMap {
     fn decay(&mut self, amount: RelationshipStrength) {
         let mut edges_to_remove = Vec::new();
 
-        for (&source_target, relationship) in self.iter_mut() {
+        for (&source_target, relationship) in self.0.iter_mut() {
             *relationship += amount;
 
             if *relationship <= 0.0 {
@@ -101,50 +598,187 @@ impl UserRelationshipGraphMap {
         }
 
         for source_target in edges_to_remove {
-            self.remove(&source_target);
+            self.0.remove(&source_target);
         }
     }
 
-    pub async fn to_dot(
-        &self,
-        context: &Context,
-        guild_id: Id<GuildMarker>,
-        requesting_user: Option<&User>,
-        color_scheme: ColorScheme,
-        transparent: bool,
-    ) -> AnyhowResult<String> {
-        // Gather all undirected edges.
-        let mut undirected_edges = HashMap::new();
-        for (&(source, target), new_weight) in &self.0 {
-            // Ignore self-connected edges.
-            if source == target {
-                continue;
-            }
+    /// Remove users left without any connections, returning how many there were.
+    /// Users only exist through their edges, so this drops edges that have no weight left.
+    pub fn prune_isolated_nodes(&mut self) -> usize {
+        let before = self.get_users().len();
+        self.remove_empty_edges();
 
-            // Sort the key to make it direction-independent.
-            let mut key = [source, target];
-            key.sort();
+        before - self.get_users().len()
+    }
 
-            // As we're collapsing directed edges, we need to sum the weights.
-            let weight: &mut RelationshipStrength = undirected_edges.entry(key).or_default();
-            *weight += new_weight;
+    fn remove_empty_edges(&mut self) {
+        self.0.retain(|_, weight| *weight > 0.0);
+    }
+
+    pub fn get_users(&self) -> HashSet<Id<UserMarker>> {
+        self.0
+            .keys()
+            .flat_map(|&(source, target)| [source, target])
+            .collect()
+    }
+
+    /// Strengthen the edge from `source` to `target`, creating it if needed. Negative weights
+    /// weaken it instead, removing it once nothing is left, the same as decay does.
+    /// Self-loops are never meaningful here, and trying to add one is a bug in the caller.
+    pub fn add_weight(
+        &mut self,
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+        weight: RelationshipStrength,
+    ) {
+        debug_assert_ne!(source, target, "attempted to add a self-loop");
+
+        if source == target {
+            error!("refusing to add self-loop for {}", source);
+            return;
         }
 
-        // Remove any edges that have a weight under the threshold and build a list of unique user IDs.
-        let mut user_ids = HashSet::new();
-        undirected_edges.retain(|&[source, target], weight| {
-            if *weight >= 1.0 {
-                user_ids.insert(source);
-                user_ids.insert(target);
+        let relationship = self.0.entry((source, target)).or_default();
+        *relationship += weight;
 
-                true
-            } else {
-                false
+        if weight < 0.0 && *relationship <= 0.0 {
+            self.0.remove(&(source, target));
+            self.1.remove(&(source, target));
+        }
+    }
+
+    /// Note that the edge changed at `timestamp`, unless we know of a later change.
+    fn touch(&mut self, source: Id<UserMarker>, target: Id<UserMarker>, timestamp: u64) {
+        if !self.0.contains_key(&(source, target)) {
+            return;
+        }
+
+        let last_updated = self.1.entry((source, target)).or_default();
+        *last_updated = (*last_updated).max(timestamp);
+    }
+
+    pub fn get_last_updated(&self, source: Id<UserMarker>, target: Id<UserMarker>) -> Option<u64> {
+        self.1.get(&(source, target)).copied()
+    }
+
+    /// Scale down every edge that hasn't changed since `cutoff_ms`, removing any that end up
+    /// below `min_weight`, and return how many were removed. Edges with no known last change
+    /// are treated as changing at `now_ms`, so they only start decaying a cutoff from now.
+    fn decay_stale_edges(
+        &mut self,
+        factor: f64,
+        cutoff_ms: u64,
+        now_ms: u64,
+        min_weight: RelationshipStrength,
+    ) -> usize {
+        let (weights, last_updated) = (&mut self.0, &mut self.1);
+
+        // Forget edges that other kinds of decay already removed.
+        last_updated.retain(|source_target, _| weights.contains_key(source_target));
+
+        let before = weights.len();
+        weights.retain(|&source_target, weight| {
+            if *last_updated.entry(source_target).or_insert(now_ms) >= cutoff_ms {
+                return true;
+            }
+
+            *weight = (*weight as f64 * factor) as RelationshipStrength;
+            if *weight >= min_weight {
+                return true;
             }
+
+            last_updated.remove(&source_target);
+            false
         });
 
-        // Load all color-affecting roles for the guild.
-        let roles = {
+        before - weights.len()
+    }
+
+    /// Adjust every edge weight in place, e.g. to normalize them.
+    pub fn scale_weights(
+        &mut self,
+        mut scale: impl FnMut(Id<UserMarker>, Id<UserMarker>) -> RelationshipStrength,
+    ) {
+        for (&(source, target), weight) in self.0.iter_mut() {
+            *weight *= scale(source, target);
+        }
+    }
+
+    /// The induced subgraph: every edge where both users are in `user_ids`.
+    pub fn subgraph(&self, user_ids: &HashSet<Id<UserMarker>>) -> Self {
+        UserRelationshipGraphMap(
+            self.0
+                .iter()
+                .filter(|((source, target), _)| {
+                    user_ids.contains(source) && user_ids.contains(target)
+                })
+                .map(|(&source_target, &weight)| (source_target, weight))
+                .collect(),
+            HashMap::new(),
+        )
+    }
+
+    /// The user's neighbourhood: everyone within `depth` hops of them in either direction,
+    /// and every edge between those users.
+    pub fn ego_graph(&self, user_id: Id<UserMarker>, depth: u32) -> Self {
+        let mut user_ids: HashSet<_> =
+            bfs_neighbours(&self.get_undirected_neighbours(), user_id, depth)
+                .into_iter()
+                .collect();
+        user_ids.insert(user_id);
+
+        self.subgraph(&user_ids)
+    }
+
+    /// Each user's outgoing edges, sorted by target. Users who are only ever targeted
+    /// are included with no edges, so every user in the graph has an entry.
+    pub fn to_adjacency_list(
+        &self,
+    ) -> HashMap<Id<UserMarker>, Vec<(Id<UserMarker>, RelationshipStrength)>> {
+        let mut adjacency: HashMap<_, Vec<_>> = HashMap::new();
+
+        for (&(source, target), &weight) in &self.0 {
+            adjacency.entry(source).or_default().push((target, weight));
+            adjacency.entry(target).or_default();
+        }
+
+        for neighbours in adjacency.values_mut() {
+            neighbours.sort_by_key(|&(target, _)| target);
+        }
+
+        adjacency
+    }
+
+    /// How close the displayed graph is to complete, from 0 (no edges) to 1 (everyone connected).
+    pub fn density(&self) -> f32 {
+        let undirected_edges = self.get_undirected_edges();
+        let node_count = undirected_edges
+            .keys()
+            .flatten()
+            .collect::<HashSet<_>>()
+            .len() as f32;
+
+        if node_count < 2.0 {
+            return 0.0;
+        }
+
+        let density = (2.0 * undirected_edges.len() as f32) / (node_count * (node_count - 1.0));
+
+        density.clamp(0.0, 1.0)
+    }
+
+    /// Resolve node labels and role colors through the cache for `LabelMode::DisplayName`.
+    async fn get_names_and_colors(
+        context: &Context,
+        guild_id: Id<GuildMarker>,
+        user_ids: &HashSet<Id<UserMarker>>,
+        include_bots: bool,
+    ) -> AnyhowResult<(
+        HashMap<Id<UserMarker>, (String, Option<u32>)>,
+        HashSet<Id<UserMarker>>,
+    )> {
+        // Snapshot all of the guild's roles for color lookups.
+        let roles_future = async {
             let role_futures = context
                 .cache
                 .get_guild(guild_id)
@@ -153,162 +787,635 @@ impl UserRelationshipGraphMap {
                 .into_iter()
                 .map(|role_id| context.cache.get_role(guild_id, role_id));
 
-            let mut roles: Vec<_> = join_all(role_futures)
+            let roles: HashMap<_, _> = join_all(role_futures)
                 .await
                 .into_iter()
-                .filter_map(|role| match role {
-                    Ok(role) if role.color != 0 => Some(role),
-                    _ => None,
-                })
+                .filter_map(|role| role.ok())
+                .map(|role| (role.id, role))
                 .collect();
 
-            roles.sort_unstable_by_key(|role| std::cmp::Reverse(role.position));
-
-            roles
+            AnyhowResult::<_>::Ok(roles)
         };
 
-        // Get the display name for each user ID, ignoring failed lookups or bots.
-        // TODO: This can be *very* slow if the user isn't in the cache..
-        let names_and_colors: HashMap<_, _> = {
-            let user_futures = user_ids
-                .iter()
-                .map(|&user_id| context.cache.get_user(user_id));
-
-            let member_futures = join_all(user_futures).await.into_iter().filter_map(|user| {
-                let user = user.ok()?;
+        // Look up all of the users and then their members in batches, ignoring failed lookups
+        // and bots unless they're wanted. All of it runs alongside the role lookups.
+        let users_future = async {
+            let user_ids: Vec<_> = user_ids.iter().copied().collect();
 
-                if user.bot {
-                    return None;
-                }
+            let users: Vec<_> = context
+                .cache
+                .get_or_fetch_users(&user_ids)
+                .await
+                .into_iter()
+                .filter(|user| include_bots || !user.bot)
+                .collect();
 
-                Some(async {
-                    let member = context.cache.get_member(guild_id, user.id).await;
+            let user_ids: Vec<_> = users.iter().map(|user| user.id).collect();
+            let mut members = context
+                .cache
+                .get_or_fetch_members(guild_id, &user_ids)
+                .await;
 
+            users
+                .into_iter()
+                .map(|user| {
+                    let member = members.remove(&user.id);
                     (user, member)
                 })
-            });
+                .collect::<Vec<_>>()
+        };
 
-            join_all(member_futures)
-                .await
-                .into_iter()
-                .map(|(user, member)| {
-                    let name = match &member {
-                        Ok(CachedMember {
-                            nick: Some(nick), ..
-                        }) => nick,
-                        _ => &user.name,
-                    }
-                    .to_owned();
+        let (roles, users) = futures::join!(roles_future, users_future);
+        let roles = roles?;
 
-                    let color = member.ok().and_then(|member| {
-                        let member_roles: HashSet<_> = member.roles.iter().cloned().collect();
+        let bots = users
+            .iter()
+            .filter(|(user, _)| user.bot)
+            .map(|(user, _)| user.id)
+            .collect();
 
-                        roles.iter().find_map(|role| {
-                            if member_roles.contains(&role.id) {
-                                Some(role.color)
-                            } else {
-                                None
-                            }
-                        })
-                    });
+        let names_and_colors = users
+            .into_iter()
+            .map(|(user, member)| {
+                let name = match &member {
+                    Some(CachedMember {
+                        nick: Some(nick), ..
+                    }) => nick,
+                    _ => &user.name,
+                }
+                .to_owned();
 
-                    (user.id, (name, color))
-                })
-                .collect()
-        };
+                let color = member
+                    .map(|member| member.highest_role_color(&roles))
+                    .filter(|&color| color != 0);
 
-        // Filter any edges that were to bots or we couldn't lookup and sum per-user weights.
-        let mut user_weights: HashMap<Id<UserMarker>, RelationshipStrength> = HashMap::new();
-        undirected_edges.retain(|[source, target], weight| {
-            let retain =
-                names_and_colors.contains_key(source) && names_and_colors.contains_key(target);
+                (user.id, (name, color))
+            })
+            .collect();
 
-            if retain {
-                let source_weight = user_weights.entry(*source).or_default();
-                *source_weight += *weight;
+        Ok((names_and_colors, bots))
+    }
 
-                let target_weight = user_weights.entry(*target).or_default();
-                *target_weight += *weight;
+    /// Collapse directed edges into undirected ones, dropping any under the display threshold.
+    fn get_undirected_edges(&self) -> HashMap<[Id<UserMarker>; 2], RelationshipStrength> {
+        let mut undirected_edges = self.get_pair_weights();
+
+        // Remove any edges that have a weight under the threshold.
+        undirected_edges.retain(|_, weight| *weight >= 1.0);
+
+        undirected_edges
+    }
+
+    /// The combined weight of both directions for each pair of users, keyed by the sorted pair.
+    fn get_pair_weights(&self) -> HashMap<[Id<UserMarker>; 2], RelationshipStrength> {
+        let mut undirected_edges = HashMap::new();
+        for (&(source, target), new_weight) in &self.0 {
+            // Ignore self-connected edges.
+            if source == target {
+                continue;
             }
 
-            retain
-        });
+            // Sort the key to make it direction-independent.
+            let mut key = [source, target];
+            key.sort();
 
-        if user_weights.is_empty() {
-            anyhow::bail!("Not enough users to create a graph");
+            // As we're collapsing directed edges, we need to sum the weights.
+            let weight: &mut RelationshipStrength = undirected_edges.entry(key).or_default();
+            *weight += new_weight;
         }
 
-        const FONT_NAME: &str = "Noto Sans Display, Noto Emoji";
+        undirected_edges
+    }
 
-        const BG_LIGHT: u32 = 0xFFFFFF;
-        const FG_LIGHT: u32 = 0x060607;
-        const BG_DARK: u32 = 0x36393F;
-        const FG_DARK: u32 = 0xFFFFFF;
+    /// Each user's neighbours, ignoring edge direction.
+    fn get_undirected_neighbours(&self) -> Neighbours {
+        let mut neighbours = Neighbours::new();
+        for [a, b] in self.get_pair_weights().into_keys() {
+            neighbours.entry(a).or_default().insert(b);
+            neighbours.entry(b).or_default().insert(a);
+        }
 
-        let (bg_color, fg_color) = match color_scheme {
-            ColorScheme::Light => (BG_LIGHT, FG_LIGHT),
-            ColorScheme::Dark => (BG_DARK, FG_DARK),
-        };
+        neighbours
+    }
 
-        let mut lines = Vec::with_capacity(16 + user_weights.len() + undirected_edges.len() + 1);
-
-        lines.push(String::from("graph {"));
-        lines.push(String::from("    dpi = \"144\""));
-        lines.push(String::from("    pad = \"0.3\""));
-        lines.push(String::from("    layout = \"fdp\""));
-        lines.push(String::from("    K = \"0.1\""));
-        lines.push(String::from("    splines = \"true\""));
-        lines.push(String::from("    overlap = \"30:true\""));
-        lines.push(String::from("    outputorder = \"edgesfirst\""));
-        lines.push(format!("    color = \"#{:06X}\"", fg_color));
-        lines.push(format!("    fontcolor = \"#{:06X}\"", fg_color));
-
-        if transparent {
-            lines.push(String::from("    bgcolor = \"transparent\""));
-        } else {
-            lines.push(format!("    bgcolor = \"#{:06X}\"", bg_color));
+    /// Score how active each user is, from 0 to 100, highest first. The score is the average
+    /// of the user's weighted degree and recency-weighted interaction count, each relative to
+    /// the most active user, and the fraction of everyone else they've interacted with.
+    /// `interaction_counts` has each user's `(total, recent)` interactions, with recent ones
+    /// counted again so they're worth double.
+    pub fn activity_scores(
+        &self,
+        interaction_counts: &HashMap<Id<UserMarker>, (u64, u64)>,
+    ) -> Vec<(Id<UserMarker>, f64)> {
+        let mut degrees: HashMap<Id<UserMarker>, f64> = HashMap::new();
+        for (&(source, target), &weight) in &self.0 {
+            *degrees.entry(source).or_default() += weight as f64;
+            *degrees.entry(target).or_default() += weight as f64;
         }
 
-        if let Some(user) = requesting_user {
-            let guild = context.cache.get_guild(guild_id).await?;
+        let neighbours = self.get_undirected_neighbours();
 
-            let member = context
-                .cache
-                .get_member(guild_id, context.user.id)
-                .await
-                .ok();
+        let activity = |user_id| {
+            let (total, recent) = interaction_counts.get(user_id).copied().unwrap_or_default();
+            (total + recent) as f64
+        };
 
-            let nickname = match &member {
-                Some(CachedMember {
-                    nick: Some(nick), ..
-                }) => nick,
-                _ => &context.user.name,
-            };
+        let users: HashSet<_> = degrees.keys().chain(interaction_counts.keys()).collect();
 
-            let safe_name = user.name.replace('\\', "\\\\").replace('"', "\\\"");
-            let safe_nickname = nickname.replace('\\', "\\\\").replace('"', "\\\"");
-            let safe_guild_name = guild.name.replace('\\', "\\\\").replace('"', "\\\"");
+        let max_degree = degrees.values().copied().fold(0.0, f64::max);
+        let max_activity = users
+            .iter()
+            .map(|user_id| activity(user_id))
+            .fold(0.0, f64::max);
+        let others = degrees.len().saturating_sub(1) as f64;
 
-            // TODO: Add a timestamp.
-            let label = format!(
-                "Generated for {}#{:04} by {} in {}",
-                safe_name, user.discriminator, safe_nickname, safe_guild_name,
-            );
+        let fraction = |value: f64, max: f64| if max > 0.0 { value / max } else { 0.0 };
 
-            lines.push(format!("    label = \"{}\"", label));
-            lines.push(String::from("    labelloc = \"bottom\""));
-            lines.push(String::from("    labeljust = \"left\""));
-            lines.push(format!("    fontname = \"{}\"", FONT_NAME));
-        }
+        let mut scores: Vec<_> = users
+            .into_iter()
+            .map(|user_id| {
+                let degree = degrees.get(user_id).copied().unwrap_or_default();
+                let partners = neighbours.get(user_id).map_or(0, HashSet::len) as f64;
 
-        lines.push(format!("    node [ fontname = \"{}\" ]", FONT_NAME));
+                let score = (fraction(degree, max_degree)
+                    + fraction(activity(user_id), max_activity)
+                    + fraction(partners, others))
+                    / 3.0;
 
-        for (user_id, weight) in &user_weights {
-            let (name, role_color) = names_and_colors.get(user_id).unwrap().clone();
-            let width = 1.0 + weight.log10();
+                (*user_id, score * 100.0)
+            })
+            .collect();
 
-            // TODO: This could be a lot more efficient.
-            let mut label = get_label(name.to_owned())
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        scores
+    }
+
+    /// The fraction of pairs of the user's neighbours that are also neighbours of each other,
+    /// ignoring edge direction. Users with fewer than two neighbours have a coefficient of 0.
+    pub fn cluster_coefficient(&self, user_id: Id<UserMarker>) -> f64 {
+        Self::get_cluster_coefficient(&self.get_undirected_neighbours(), user_id)
+    }
+
+    /// The mean `cluster_coefficient` over every user in the graph.
+    pub fn average_cluster_coefficient(&self) -> f64 {
+        let neighbours = self.get_undirected_neighbours();
+        if neighbours.is_empty() {
+            return 0.0;
+        }
+
+        let total: f64 = neighbours
+            .keys()
+            .map(|&user_id| Self::get_cluster_coefficient(&neighbours, user_id))
+            .sum();
+
+        total / neighbours.len() as f64
+    }
+
+    fn get_cluster_coefficient(neighbours: &Neighbours, user_id: Id<UserMarker>) -> f64 {
+        let degree = match neighbours.get(&user_id) {
+            Some(user_neighbours) if user_neighbours.len() >= 2 => user_neighbours.len(),
+            _ => return 0.0,
+        };
+
+        let triangles = all_triangles(neighbours, user_id) as usize;
+
+        (2 * triangles) as f64 / (degree * (degree - 1)) as f64
+    }
+
+    /// The fewest-hops path between two users in either direction, including both ends.
+    /// Ties are broken by user ID so the result is stable.
+    pub fn get_path(
+        &self,
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+    ) -> Option<Vec<Id<UserMarker>>> {
+        let mut neighbours: HashMap<Id<UserMarker>, Vec<Id<UserMarker>>> = HashMap::new();
+        for [a, b] in self.get_pair_weights().into_keys() {
+            neighbours.entry(a).or_default().push(b);
+            neighbours.entry(b).or_default().push(a);
+        }
+
+        for users in neighbours.values_mut() {
+            users.sort();
+        }
+
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::from([source]);
+        previous.insert(source, source);
+
+        while let Some(user_id) = queue.pop_front() {
+            if user_id == target {
+                let mut path = vec![target];
+                while let Some(&last) = path.last() {
+                    if last == source {
+                        break;
+                    }
+
+                    path.push(previous[&last]);
+                }
+
+                path.reverse();
+                return Some(path);
+            }
+
+            for &neighbour in neighbours.get(&user_id).into_iter().flatten() {
+                if let Entry::Vacant(entry) = previous.entry(neighbour) {
+                    entry.insert(user_id);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// The most hops between any two users, ignoring edge direction, or `None` if some users
+    /// can't reach each other. Exact for graphs of up to `EXACT_DIAMETER_LIMIT` users, larger
+    /// graphs are estimated from breadth-first searches out of a sample of the users.
+    pub fn diameter(&self) -> Option<usize> {
+        let neighbours = self.get_undirected_neighbours();
+
+        let mut user_ids: Vec<_> = neighbours.keys().copied().collect();
+        user_ids.sort();
+
+        if user_ids.len() <= EXACT_DIAMETER_LIMIT {
+            return Self::get_exact_diameter(&neighbours, &user_ids);
+        }
+
+        // Spread the sample evenly over the users so the estimate is stable between runs.
+        let step = user_ids.len() / DIAMETER_SAMPLE_SIZE;
+
+        let mut diameter = 0;
+        for &source in user_ids.iter().step_by(step).take(DIAMETER_SAMPLE_SIZE) {
+            let distances = bfs_distances(&neighbours, source, None);
+
+            if distances.len() != user_ids.len() {
+                return None;
+            }
+
+            diameter = diameter.max(distances.into_values().max().unwrap_or_default() as usize);
+        }
+
+        Some(diameter)
+    }
+
+    /// Floyd-Warshall over every pair of users, which is O(n^3) so only used for small graphs.
+    fn get_exact_diameter(neighbours: &Neighbours, user_ids: &[Id<UserMarker>]) -> Option<usize> {
+        const UNREACHABLE: usize = usize::MAX / 2;
+
+        let count = user_ids.len();
+        let index: HashMap<_, _> = user_ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (id, i))
+            .collect();
+
+        let mut distances = vec![UNREACHABLE; count * count];
+        for (i, user_id) in user_ids.iter().enumerate() {
+            distances[i * count + i] = 0;
+
+            for neighbour in &neighbours[user_id] {
+                distances[i * count + index[neighbour]] = 1;
+            }
+        }
+
+        for k in 0..count {
+            for i in 0..count {
+                let via_k = distances[i * count + k];
+                if via_k == UNREACHABLE {
+                    continue;
+                }
+
+                for j in 0..count {
+                    let distance = via_k + distances[k * count + j];
+                    if distance < distances[i * count + j] {
+                        distances[i * count + j] = distance;
+                    }
+                }
+            }
+        }
+
+        let diameter = distances.into_iter().max().unwrap_or_default();
+        if diameter == UNREACHABLE {
+            return None;
+        }
+
+        Some(diameter)
+    }
+
+    /// Combine the weights along the shortest path between two users.
+    pub fn get_path_weight(
+        &self,
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+        aggregation: PathWeightAggregation,
+    ) -> Option<RelationshipStrength> {
+        let path = self.get_path(source, target)?;
+
+        // A path from a user to themselves has no edges, and no meaningful strength.
+        if path.len() < 2 {
+            return None;
+        }
+
+        let pair_weights = self.get_pair_weights();
+        let weights = path.windows(2).map(|pair| {
+            let mut key = [pair[0], pair[1]];
+            key.sort();
+
+            pair_weights[&key]
+        });
+
+        Some(match aggregation {
+            PathWeightAggregation::Product => weights.product(),
+            PathWeightAggregation::Minimum => weights.fold(RelationshipStrength::MAX, f32::min),
+            PathWeightAggregation::Sum => weights.sum(),
+        })
+    }
+
+    /// The combined weight of both directions between two users, if they're connected at all.
+    pub fn get_pair_weight(
+        &self,
+        a: Id<UserMarker>,
+        b: Id<UserMarker>,
+    ) -> Option<RelationshipStrength> {
+        let forward = self.0.get(&(a, b));
+        let backward = self.0.get(&(b, a));
+
+        match (forward, backward) {
+            (None, None) => None,
+            _ => Some(forward.copied().unwrap_or_default() + backward.copied().unwrap_or_default()),
+        }
+    }
+
+    /// Group users into connected components of the displayed (undirected, thresholded) graph.
+    /// Each community is ordered by how strongly its members are connected, strongest first.
+    pub fn get_communities(&self) -> Vec<Vec<Id<UserMarker>>> {
+        let undirected_edges = self.get_undirected_edges();
+
+        let mut neighbours = Neighbours::new();
+        let mut strength: HashMap<Id<UserMarker>, RelationshipStrength> = HashMap::new();
+        for (&[a, b], &weight) in &undirected_edges {
+            neighbours.entry(a).or_default().insert(b);
+            neighbours.entry(b).or_default().insert(a);
+            *strength.entry(a).or_default() += weight;
+            *strength.entry(b).or_default() += weight;
+        }
+
+        let mut visited = HashSet::new();
+        let mut communities = Vec::new();
+
+        for &start in neighbours.keys() {
+            if visited.contains(&start) {
+                continue;
+            }
+
+            let mut community: Vec<_> = reachable_from(&neighbours, start).into_iter().collect();
+            visited.extend(community.iter().copied());
+
+            community.sort_by(|a, b| strength[b].total_cmp(&strength[a]).then(a.cmp(b)));
+            communities.push(community);
+        }
+
+        communities
+    }
+
+    /// The edges to draw, for pairs of users whose combined weight passes the display threshold.
+    /// Either one edge per direction, or a single edge per pair (keyed by the sorted pair) with
+    /// the stronger direction's weight.
+    fn get_display_edges(
+        &self,
+        undirected: bool,
+    ) -> HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength> {
+        let mut edges = HashMap::new();
+
+        for [a, b] in self.get_undirected_edges().into_keys() {
+            let forward = self.0.get(&(a, b)).copied().unwrap_or_default();
+            let backward = self.0.get(&(b, a)).copied().unwrap_or_default();
+
+            if undirected {
+                edges.insert((a, b), forward.max(backward));
+                continue;
+            }
+
+            if forward > 0.0 {
+                edges.insert((a, b), forward);
+            }
+
+            if backward > 0.0 {
+                edges.insert((b, a), backward);
+            }
+        }
+
+        edges
+    }
+
+    /// Remove edges to or from users connected to fewer than `min_degree` other users.
+    /// Degrees are counted once, before anything is removed.
+    fn retain_min_degree(
+        edges: &mut HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>,
+        min_degree: usize,
+    ) {
+        if min_degree == 0 {
+            return;
+        }
+
+        let mut neighbours: HashMap<Id<UserMarker>, HashSet<Id<UserMarker>>> = HashMap::new();
+        for &(source, target) in edges.keys() {
+            neighbours.entry(source).or_default().insert(target);
+            neighbours.entry(target).or_default().insert(source);
+        }
+
+        let has_min_degree = |user_id: &Id<UserMarker>| neighbours[user_id].len() >= min_degree;
+        edges.retain(|(source, target), _| has_min_degree(source) && has_min_degree(target));
+    }
+
+    /// The edges to draw after filtering, and what was looked up about the users in them.
+    async fn get_labelled_edges(
+        &self,
+        context: &Context,
+        guild_id: Id<GuildMarker>,
+        options: &DotOptions,
+    ) -> AnyhowResult<LabelledEdges> {
+        let mut edges = self.get_display_edges(options.undirected);
+        edges.retain(|_, weight| *weight >= options.weight_threshold);
+        Self::retain_min_degree(&mut edges, options.min_degree);
+
+        // Build a list of unique user IDs.
+        let user_ids: HashSet<_> = edges
+            .keys()
+            .flat_map(|&(source, target)| [source, target])
+            .collect();
+
+        // Bots are only known about when we look users up for their names.
+        let (names_and_colors, bots) = match options.label_mode {
+            LabelMode::DisplayName => {
+                Self::get_names_and_colors(context, guild_id, &user_ids, options.include_bots)
+                    .await?
+            }
+            LabelMode::UserId => (
+                user_ids
+                    .iter()
+                    .map(|&user_id| (user_id, (user_id.to_string(), None)))
+                    .collect(),
+                HashSet::new(),
+            ),
+            LabelMode::Anonymous => {
+                let mut user_ids: Vec<_> = user_ids.iter().copied().collect();
+                user_ids.sort();
+
+                (
+                    user_ids
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, user_id)| (user_id, ((i + 1).to_string(), None)))
+                        .collect(),
+                    HashSet::new(),
+                )
+            }
+        };
+
+        Ok(LabelledEdges {
+            edges,
+            names_and_colors,
+            bots,
+        })
+    }
+
+    /// Mermaid flowchart source for the graph, for pasting somewhere that renders it.
+    /// Only the filtering options are used, Mermaid picks its own colors.
+    pub async fn to_mermaid(
+        &self,
+        context: &Context,
+        guild_id: Id<GuildMarker>,
+        options: &DotOptions,
+    ) -> AnyhowResult<String> {
+        let labelled = self.get_labelled_edges(context, guild_id, options).await?;
+
+        Self::format_mermaid(labelled.edges, &labelled.names_and_colors, options)
+    }
+
+    pub async fn to_dot(
+        &self,
+        context: &Context,
+        guild_id: Id<GuildMarker>,
+        requesting_user: Option<&User>,
+        options: &DotOptions,
+    ) -> AnyhowResult<String> {
+        let label_mode = options.label_mode;
+        let labelled = self.get_labelled_edges(context, guild_id, options).await?;
+
+        let label_user = requesting_user.filter(|_| label_mode == LabelMode::DisplayName);
+
+        let graph_label = match label_user {
+            Some(user) => {
+                let guild = context.cache.get_guild(guild_id).await?;
+
+                let member = context
+                    .cache
+                    .get_member(guild_id, context.user.id)
+                    .await
+                    .ok();
+
+                let nickname = match &member {
+                    Some(CachedMember {
+                        nick: Some(nick), ..
+                    }) => nick,
+                    _ => &context.user.name,
+                };
+
+                // TODO: Add a timestamp.
+                Some(format!(
+                    "Generated for {}#{:04} by {} in {}",
+                    escape_dot_label(&user.name),
+                    user.discriminator,
+                    escape_dot_label(nickname),
+                    escape_dot_label(&guild.name),
+                ))
+            }
+            None => None,
+        };
+
+        Self::format_dot(
+            labelled.edges,
+            &labelled.names_and_colors,
+            &labelled.bots,
+            requesting_user.map(|user| user.id),
+            graph_label.as_deref(),
+            options,
+        )
+    }
+
+    /// Format the DOT source once all of the names have been resolved.
+    fn format_dot(
+        mut edges: HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>,
+        names_and_colors: &HashMap<Id<UserMarker>, (String, Option<u32>)>,
+        bots: &HashSet<Id<UserMarker>>,
+        requesting_user_id: Option<Id<UserMarker>>,
+        graph_label: Option<&str>,
+        options: &DotOptions,
+    ) -> AnyhowResult<String> {
+        // Filter any edges that were to bots or we couldn't lookup and sum per-user weights.
+        let mut user_weights: HashMap<Id<UserMarker>, RelationshipStrength> = HashMap::new();
+        edges.retain(|(source, target), weight| {
+            let retain =
+                names_and_colors.contains_key(source) && names_and_colors.contains_key(target);
+
+            if retain {
+                let source_weight = user_weights.entry(*source).or_default();
+                *source_weight += *weight;
+
+                let target_weight = user_weights.entry(*target).or_default();
+                *target_weight += *weight;
+            }
+
+            retain
+        });
+
+        if user_weights.is_empty() {
+            anyhow::bail!("Not enough users to create a graph");
+        }
+
+        let palette = options.palette.colors();
+        let (bg_color, fg_color) = get_dot_colors(options);
+
+        let mut lines = Vec::with_capacity(16 + user_weights.len() + edges.len() + 1);
+
+        let (graph_kind, edge_op) = if options.undirected {
+            ("graph", "--")
+        } else {
+            ("digraph", "->")
+        };
+
+        lines.push(format!("{} {{", graph_kind));
+        push_dot_graph_attributes(&mut lines, options, user_weights.len());
+
+        if let Some(label) = graph_label {
+            lines.push(format!("    label = \"{}\"", label));
+            lines.push(String::from("    labelloc = \"bottom\""));
+            lines.push(String::from("    labeljust = \"left\""));
+            lines.push(format!("    fontname = \"{}\"", FONT_NAME));
+        }
+
+        lines.push(format!("    node [ fontname = \"{}\" ]", FONT_NAME));
+
+        // Sort everything so the same graph always produces the same DOT.
+        let mut user_weights: Vec<_> = user_weights.into_iter().collect();
+        user_weights.sort_by_key(|&(user_id, _)| user_id);
+
+        let mut edges: Vec<_> = edges.into_iter().collect();
+        edges.sort_by_key(|&(pair, _)| pair);
+
+        let node_normalizer = WeightNormalizer::new(user_weights.iter().map(|&(_, weight)| weight));
+        let edge_normalizer = WeightNormalizer::new(edges.iter().map(|&(_, weight)| weight));
+
+        for (user_id, weight) in &user_weights {
+            let (name, role_color) = names_and_colors.get(user_id).unwrap().clone();
+            let normalized_weight = node_normalizer.normalize(*weight);
+            let width = 1.0 + 2.0 * normalized_weight;
+
+            // TODO: This could be a lot more efficient.
+            let mut label = get_label(name.to_owned())
                 .replace('&', "&amp;")
                 .replace('"', "&quot;")
                 .replace('\'', "&#x27;")
@@ -316,299 +1423,2676 @@ impl UserRelationshipGraphMap {
                 .replace('>', "&gt;")
                 .replace('\\', "\\\\");
 
-            let mut peripheries = 1;
-            let mut color = fg_color;
-            let mut fillcolor = bg_color;
-            let mut fontcolor = fg_color;
+            let mut peripheries = 1;
+            let mut color = fg_color;
+            let mut fillcolor = match palette.node_fill {
+                Some(node_fill) => node_fill(normalized_weight),
+                None => bg_color,
+            };
+            let mut fontcolor = fg_color;
+
+            if let Some(role_color) = role_color {
+                color = role_color;
+            }
+
+            let inactive = options
+                .active_users
+                .as_ref()
+                .is_some_and(|active_users| !active_users.contains(user_id));
+            if inactive {
+                color = INACTIVE_COLOR;
+                fontcolor = INACTIVE_COLOR;
+            }
+
+            if let Some(&node_color) = options.node_colors.get(user_id) {
+                color = node_color;
+                fillcolor = node_color;
+            }
+
+            if let Some(requesting_user_id) = requesting_user_id {
+                // Invert the colors if it is the requesting user.
+                if *user_id == requesting_user_id {
+                    // Make the text bold.
+                    label = format!("<B>{}</B>", label);
+
+                    peripheries = 2;
+
+                    fillcolor = color;
+                }
+            }
+
+            // Bots are only here when they were asked for, so make them stand out.
+            let shape = if bots.contains(user_id) {
+                ", shape = \"diamond\""
+            } else {
+                ""
+            };
+
+            if fillcolor != bg_color {
+                // Select text color based on fill contrast.
+                fontcolor = if calculate_luma(fillcolor) > 186.0 {
+                    FG_LIGHT
+                } else {
+                    FG_DARK
+                };
+            }
+
+            lines.push(format!(
+                "    {} [ label = <{}>, penwidth = \"{}\", style = \"filled\", peripheries = \"{}\", color = \"#{:06X}\", fillcolor = \"#{:06X}\", fontcolor = \"#{:06X}\"{}{} ]",
+                user_id,
+                label,
+                width,
+                peripheries,
+                color,
+                fillcolor,
+                fontcolor,
+                shape,
+                format_extra_attributes(options.node_attributes.as_ref()),
+            ));
+        }
+
+        for ((source, target), weight) in edges {
+            // Single directions can be much weaker than the pair, so keep them visible.
+            let normalized_weight = edge_normalizer.normalize(weight);
+            let width = 0.5 + 2.5 * normalized_weight;
+            let color = match palette.edge_color {
+                Some(edge_color) => edge_color(normalized_weight),
+                None => fg_color,
+            };
+
+            lines.push(format!(
+                "    {} {} {} [ weight = \"{}\", penwidth = \"{}\", color = \"#{:06X}\", arrowsize = \"0.5\"{} ]",
+                source,
+                edge_op,
+                target,
+                weight,
+                width,
+                color,
+                format_extra_attributes(options.edge_attributes.as_ref()),
+            ));
+        }
+
+        lines.push(String::from("}"));
+
+        Ok(lines.join("\n"))
+    }
+
+    /// JSON in the `{"nodes": [...], "links": [...]}` shape d3-force expects, with each node's
+    /// `group` set to its community so they can be colored by it.
+    pub async fn to_json_d3force(
+        &self,
+        context: &Context,
+        guild_id: Id<GuildMarker>,
+        options: &DotOptions,
+    ) -> AnyhowResult<String> {
+        let labelled = self.get_labelled_edges(context, guild_id, options).await?;
+
+        let mut communities = self.get_communities();
+        communities.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+
+        Self::format_d3force(
+            labelled.edges,
+            &labelled.names_and_colors,
+            &communities,
+            options.label_mode,
+        )
+    }
+
+    /// The graph as a `JsonGraph`, for other programs. Edges add up every kind of interaction
+    /// between a pair, so each one is labelled with the reason recorded most often.
+    pub async fn to_json(
+        &self,
+        context: &Context,
+        guild_id: Id<GuildMarker>,
+    ) -> AnyhowResult<String> {
+        let reasons = match &context.pool {
+            Some(pool) => get_edge_reasons(pool, guild_id).await?,
+            None => HashMap::new(),
+        };
+
+        let mut user_ids: Vec<_> = self
+            .keys()
+            .flat_map(|&(source, target)| [source, target])
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        user_ids.sort();
+
+        let mut found: HashMap<_, _> = context
+            .cache
+            .get_or_fetch_users(&user_ids)
+            .await
+            .into_iter()
+            .map(|user| (user.id, user))
+            .collect();
+
+        // Users we can't fetch any more still have their edges, so keep them under their ID.
+        let users = user_ids
+            .into_iter()
+            .map(|user_id| {
+                found.remove(&user_id).unwrap_or_else(|| {
+                    debug!("exporting {} without a name", user_id);
+
+                    CachedUser {
+                        id: user_id,
+                        name: user_id.to_string(),
+                        discriminator: 0,
+                        avatar: None,
+                        bot: false,
+                    }
+                })
+            })
+            .collect();
+
+        self.format_json(users, &reasons)
+    }
+
+    /// Format the JSON export once all of the users have been fetched.
+    fn format_json(
+        &self,
+        nodes: Vec<CachedUser>,
+        reasons: &HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipChangeReason>,
+    ) -> AnyhowResult<String> {
+        let mut edges: Vec<_> = self
+            .iter()
+            .map(|(&(source, target), &weight)| JsonEdge {
+                source,
+                target,
+                weight,
+                reason: reasons.get(&(source, target)).copied(),
+            })
+            .collect();
+        edges.sort_by_key(|edge| (edge.source, edge.target));
+
+        Ok(serde_json::to_string_pretty(&JsonGraph { nodes, edges })?)
+    }
+
+    /// Read back a JSON export, only used to check exports don't lose anything.
+    #[cfg(test)]
+    fn from_json(json: &str) -> AnyhowResult<(Self, Vec<CachedUser>)> {
+        let exported: JsonGraph = serde_json::from_str(json)?;
+
+        let mut graph = UserRelationshipGraphMap::new();
+        for edge in exported.edges {
+            graph.add_weight(edge.source, edge.target, edge.weight);
+        }
+
+        Ok((graph, exported.nodes))
+    }
+
+    /// Format the d3-force JSON once all of the names have been resolved.
+    fn format_d3force(
+        mut edges: HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>,
+        names_and_colors: &HashMap<Id<UserMarker>, (String, Option<u32>)>,
+        communities: &[Vec<Id<UserMarker>>],
+        label_mode: LabelMode,
+    ) -> AnyhowResult<String> {
+        edges.retain(|(source, target), _| {
+            names_and_colors.contains_key(source) && names_and_colors.contains_key(target)
+        });
+
+        if edges.is_empty() {
+            anyhow::bail!("Not enough users to create a graph");
+        }
+
+        let groups: HashMap<_, _> = communities
+            .iter()
+            .enumerate()
+            .flat_map(|(group, community)| community.iter().map(move |&user_id| (user_id, group)))
+            .collect();
+
+        // The labels are already unique unless they're display names, so only use IDs then.
+        let get_id = |user_id: &Id<UserMarker>| match label_mode {
+            LabelMode::DisplayName => user_id.to_string(),
+            _ => names_and_colors[user_id].0.clone(),
+        };
+
+        let mut user_ids: Vec<_> = edges
+            .keys()
+            .flat_map(|&(source, target)| [source, target])
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        user_ids.sort();
+
+        let mut edges: Vec<_> = edges.into_iter().collect();
+        edges.sort_by_key(|&(pair, _)| pair);
+
+        let graph = D3Graph {
+            nodes: user_ids
+                .iter()
+                .map(|user_id| D3Node {
+                    id: get_id(user_id),
+                    name: match label_mode {
+                        LabelMode::DisplayName => Some(names_and_colors[user_id].0.clone()),
+                        _ => None,
+                    },
+                    group: groups.get(user_id).copied().unwrap_or(communities.len()),
+                })
+                .collect(),
+            links: edges
+                .iter()
+                .map(|((source, target), weight)| D3Link {
+                    source: get_id(source),
+                    target: get_id(target),
+                    value: *weight,
+                })
+                .collect(),
+        };
+
+        Ok(serde_json::to_string_pretty(&graph)?)
+    }
+
+    /// Format the Mermaid source once all of the names have been resolved.
+    fn format_mermaid(
+        mut edges: HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>,
+        names_and_colors: &HashMap<Id<UserMarker>, (String, Option<u32>)>,
+        options: &DotOptions,
+    ) -> AnyhowResult<String> {
+        edges.retain(|(source, target), _| {
+            names_and_colors.contains_key(source) && names_and_colors.contains_key(target)
+        });
+
+        if edges.is_empty() {
+            anyhow::bail!("Not enough users to create a graph");
+        }
+
+        let mut user_ids: Vec<_> = edges
+            .keys()
+            .flat_map(|&(source, target)| [source, target])
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        user_ids.sort();
+
+        let mut edges: Vec<_> = edges.into_iter().collect();
+        edges.sort_by_key(|&(pair, _)| pair);
+
+        let edge_op = if options.undirected { "---" } else { "-->" };
+
+        let mut lines = Vec::with_capacity(1 + user_ids.len() + edges.len());
+        lines.push(String::from("graph LR"));
+
+        for user_id in user_ids {
+            let (name, _) = &names_and_colors[&user_id];
+
+            // Mermaid has no escape for quotes inside a label, only its own entity codes.
+            lines.push(format!(
+                "    u{}[\"{}\"]",
+                user_id,
+                name.replace('#', "#35;").replace('"', "#quot;"),
+            ));
+        }
+
+        for ((source, target), weight) in edges {
+            lines.push(format!(
+                "    u{} {}|{:.1}| u{}",
+                source, edge_op, weight, target
+            ));
+        }
+
+        Ok(lines.join("\n"))
+    }
+}
+
+impl std::ops::Deref for UserRelationshipGraphMap {
+    type Target = HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Serialize for UserRelationshipGraphMap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (k, v) in &self.0 {
+            let k = format!("{}:{}", k.0, k.1);
+            map.serialize_entry(&k, v)?;
+        }
+        map.end()
+    }
+}
+
+struct UserRelationshipGraphMapVisitor();
+
+impl UserRelationshipGraphMapVisitor {
+    fn new() -> Self {
+        UserRelationshipGraphMapVisitor()
+    }
+}
+
+impl<'de> Visitor<'de> for UserRelationshipGraphMapVisitor {
+    // The type that our Visitor is going to produce.
+    type Value = UserRelationshipGraphMap;
+
+    // Format a message stating what data this Visitor expects to receive.
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a very special map")
+    }
+
+    // Deserialize UserRelationshipGraphMapVisitor from an abstract "map" provided by the
+    // Deserializer. The MapAccess input is a callback provided by
+    // the Deserializer to let us see each entry in the map.
+    fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
+    where
+        M: MapAccess<'de>,
+    {
+        let mut map = UserRelationshipGraphMap(
+            HashMap::with_capacity(access.size_hint().unwrap_or(0)),
+            HashMap::new(),
+        );
+
+        // While there are entries remaining in the input, add them into our map.
+        while let Some((key, value)) = access.next_entry::<&str, RelationshipStrength>()? {
+            let err = "expected exactly 2 numbers separated by :";
+
+            let mut iter = key.split(':');
+            let mut get_int = || {
+                iter.next()
+                    .ok_or_else(|| M::Error::custom(err))?
+                    .parse()
+                    .map_err(|e: ParseIntError| M::Error::custom(e.to_string()))
+            };
+            let k1 = get_int()?;
+            let k2 = get_int()?;
+            if iter.next().is_some() {
+                return Err(M::Error::custom(err));
+            }
+
+            // Older data files may contain self-loops, which are just dropped.
+            if k1 != k2 {
+                map.0.insert((Id::new(k1), Id::new(k2)), value);
+            }
+        }
+
+        Ok(map)
+    }
+}
+
+// This is the trait that informs Serde how to deserialize MyMap.
+impl<'de> Deserialize<'de> for UserRelationshipGraphMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Instantiate our Visitor and ask the Deserializer to drive
+        // it over the input data, resulting in an instance of MyMap.
+        deserializer.deserialize_map(UserRelationshipGraphMapVisitor::new())
+    }
+}
+
+/// Every guild's graphs, one for each channel.
+/// How many users each pair of channels has in common, keyed by the pair in ascending order.
+pub type ChannelGraph = HashMap<(Id<ChannelMarker>, Id<ChannelMarker>), u64>;
+
+pub type GuildGraphs =
+    HashMap<Id<GuildMarker>, HashMap<Id<ChannelMarker>, UserRelationshipGraphMap>>;
+
+/// A user's reaction to a message with one emoji, see `crate::cache::reaction_key`.
+pub type ReactionKey = (Id<MessageMarker>, Id<UserMarker>, String);
+
+/// A source and target, identifying an edge within a channel's graph.
+pub type UserPair = (Id<UserMarker>, Id<UserMarker>);
+
+/// When each source and target pair in a guild last got a full-strength change.
+type Cooldowns = HashMap<(Id<UserMarker>, Id<UserMarker>, Id<GuildMarker>), Instant>;
+
+/// An edge as it's stored in the `graph_edges` table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StoredEdge {
+    pub channel: Id<ChannelMarker>,
+    pub source: Id<UserMarker>,
+    pub target: Id<UserMarker>,
+    pub weight: RelationshipStrength,
+    /// When the edge was last strengthened, as a millisecond Unix timestamp.
+    pub last_updated: u64,
+}
+
+/// How often and how hard `SocialGraph::decay` weakens edges that have gone quiet.
+#[derive(Debug, Clone, Copy)]
+pub struct DecayConfig {
+    /// What stale edges' weights are multiplied by on each pass.
+    pub factor: f64,
+    /// How long an edge has to go without changing before it starts decaying.
+    pub cutoff: Duration,
+    /// Decayed edges lighter than this are removed.
+    pub min_weight: RelationshipStrength,
+    /// How long to wait between passes.
+    pub interval: Duration,
+}
+
+impl Default for DecayConfig {
+    fn default() -> Self {
+        DecayConfig {
+            factor: 0.9,
+            cutoff: Duration::from_secs(30 * 24 * 60 * 60),
+            min_weight: 0.05,
+            interval: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+}
+
+/// How a channel with its own graph is related to another one, currently only a thread and the
+/// channel it was started in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ChannelRelation {
+    pub parent: Id<ChannelMarker>,
+}
+
+fn merge_channel_graphs(
+    channels: &HashMap<Id<ChannelMarker>, UserRelationshipGraphMap>,
+) -> UserRelationshipGraphMap {
+    let mut guild_graph = UserRelationshipGraphMap::new();
+    for channel_graph in channels.values() {
+        for (&(source, target), &weight) in channel_graph.iter() {
+            guild_graph.add_weight(source, target, weight);
+        }
+    }
+
+    guild_graph
+}
+
+/// A read-only copy of a guild's channel graphs, taken so that the expensive work of merging
+/// and rendering them can happen without holding the `SocialGraph` lock.
+#[derive(Debug)]
+pub struct FrozenGraph {
+    channels: HashMap<Id<ChannelMarker>, UserRelationshipGraphMap>,
+}
+
+impl FrozenGraph {
+    pub fn build_guild_graph(&self) -> UserRelationshipGraphMap {
+        merge_channel_graphs(&self.channels)
+    }
+}
+
+// TODO: Just keeping this note here, but it is a rather general thing - we've got a lot of HashMap
+//       objects around using Discord snowflakes as keys, which are out of user control and thus do
+//       not need secure, anti-DoS hashing. We could probably increase HashMap performance a tonne
+//       by avoiding the default secure hashing and just truncating them to fit / XOR for tuples.
+#[derive(Debug)]
+pub struct SocialGraph {
+    data_dir: Option<PathBuf>,
+    graph: GuildGraphs,
+    state: HashMap<(Id<GuildMarker>, Id<ChannelMarker>), InferenceState>,
+    scheduled_events: HashMap<Id<ScheduledEventMarker>, ScheduledEventState>,
+    pub presence: PresenceTracker,
+    /// Number of `apply` calls since isolated nodes were last pruned.
+    applies_since_prune: usize,
+    /// When each guild last had an interaction applied, as a millisecond Unix timestamp.
+    last_interaction: HashMap<Id<GuildMarker>, u64>,
+    node_metadata: HashMap<Id<GuildMarker>, HashMap<Id<UserMarker>, NodeMetadata>>,
+    /// See `apply_cooldown`.
+    cooldowns: Cooldowns,
+    /// What each recent reaction added to its edge, so removing it takes back the same amount.
+    reaction_weights: LruCache<ReactionKey, RelationshipStrength>,
+    rules: Vec<Box<dyn InferenceRule>>,
+    /// Names of the rules each guild has turned off.
+    disabled_rules: HashMap<Id<GuildMarker>, HashSet<&'static str>>,
+    /// Threads get graphs of their own, this remembers which channel each one belongs to.
+    channel_relations: HashMap<Id<GuildMarker>, HashMap<Id<ChannelMarker>, ChannelRelation>>,
+}
+
+/// The most users to find the exact diameter of, larger graphs are estimated.
+const EXACT_DIAMETER_LIMIT: usize = 500;
+
+/// How many users to search from when estimating the diameter of a large graph.
+const DIAMETER_SAMPLE_SIZE: usize = 50;
+
+/// How many users to include in a `GraphSummary`.
+const SUMMARY_TOP_USERS: usize = 5;
+
+/// How many `apply` calls to make between pruning isolated nodes from the guild.
+const APPLIES_PER_PRUNE: usize = 1000;
+
+/// How much of their usual weight changes keep while their pair is cooling down.
+const COOLDOWN_WEIGHT_FACTOR: RelationshipStrength = 0.1;
+
+/// How many reactions to remember the weight of, see `match_reaction_weights`.
+const REMEMBERED_REACTIONS: usize = 100_000;
+
+impl SocialGraph {
+    pub fn new(data_dir: Option<PathBuf>) -> Self {
+        SocialGraph {
+            data_dir,
+            graph: HashMap::new(),
+            state: HashMap::new(),
+            scheduled_events: HashMap::new(),
+            presence: PresenceTracker::default(),
+            applies_since_prune: 0,
+            last_interaction: HashMap::new(),
+            node_metadata: HashMap::new(),
+            cooldowns: HashMap::new(),
+            reaction_weights: LruCache::new(NonZeroUsize::new(REMEMBERED_REACTIONS).unwrap()),
+            rules: default_rules(),
+            disabled_rules: HashMap::new(),
+            channel_relations: HashMap::new(),
+        }
+    }
+
+    /// Helper function to run inference with the right state.
+    pub fn infer(&mut self, interaction: &Interaction) -> Vec<RelationshipChange> {
+        let mut changes = Vec::new();
+
+        let disabled_rules = self.disabled_rules.get(&interaction.guild);
+        let rules =
+            self.rules.iter().map(|rule| rule.as_ref()).filter(|rule| {
+                disabled_rules.is_none_or(|disabled| !disabled.contains(rule.name()))
+            });
+
+        self.state
+            .entry((interaction.guild, interaction.channel))
+            .or_insert_with(InferenceState::new)
+            .infer(&mut changes, interaction, rules);
+
+        // Replying to, reacting to, or mentioning yourself doesn't say anything about your
+        // relationships, and the graph doesn't accept self-loops.
+        changes.retain(|change| {
+            change.source != change.target
+                && !self.is_opted_out(interaction.guild, change.source)
+                && !self.is_opted_out(interaction.guild, change.target)
+        });
+
+        changes
+    }
+
+    /// Every inference rule's name, and whether it's enabled in the guild.
+    pub fn get_rules(&self, guild_id: Id<GuildMarker>) -> Vec<(&'static str, bool)> {
+        let disabled_rules = self.disabled_rules.get(&guild_id);
+
+        self.rules
+            .iter()
+            .map(|rule| {
+                let enabled = disabled_rules.is_none_or(|disabled| !disabled.contains(rule.name()));
+                (rule.name(), enabled)
+            })
+            .collect()
+    }
+
+    /// Turn an inference rule on or off for a guild, failing if there's no rule by that name.
+    pub fn set_rule_enabled(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        name: &str,
+        enabled: bool,
+    ) -> AnyhowResult<()> {
+        let name = self
+            .rules
+            .iter()
+            .map(|rule| rule.name())
+            .find(|&rule_name| rule_name == name)
+            .ok_or_else(|| anyhow::anyhow!("{} is not a recognized rule", name))?;
+
+        let disabled_rules = self.disabled_rules.entry(guild_id).or_default();
+        if enabled {
+            disabled_rules.remove(name);
+        } else {
+            disabled_rules.insert(name);
+        }
+
+        Ok(())
+    }
+
+    /// Weaken changes between pairs that already had one within `cooldown` of the interaction,
+    /// so a burst of rapid-fire mentions doesn't count for much more than a single one.
+    /// Changes at full strength start a new cooldown for their pair. Changes that weaken an edge
+    /// are left alone, `match_reaction_weights` sizes reaction removals.
+    pub fn apply_cooldown(
+        &mut self,
+        interaction: &Interaction,
+        changes: &mut [RelationshipChange],
+        cooldown: Duration,
+    ) {
+        let now = interaction.when;
+        self.cooldowns
+            .retain(|_, &mut started| now.saturating_duration_since(started) < cooldown);
+
+        for change in changes.iter_mut().filter(|change| change.weight > 0.0) {
+            match self
+                .cooldowns
+                .entry((change.source, change.target, interaction.guild))
+            {
+                Entry::Occupied(_) => {
+                    debug!(
+                        "{} -> {} is cooling down, reducing weight to {}x",
+                        change.source, change.target, COOLDOWN_WEIGHT_FACTOR,
+                    );
+
+                    change.weight *= COOLDOWN_WEIGHT_FACTOR;
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(now);
+                }
+            }
+        }
+    }
+
+    /// Make a reaction's removal take back exactly what adding it did, after any cooldown.
+    /// Call this with the final changes for a reaction, before applying them. Removals of
+    /// reactions we didn't see added, like ones from before a restart, are dropped, as they
+    /// might have been weakened and we'd risk taking away weight from other interactions.
+    pub fn match_reaction_weights(
+        &mut self,
+        interaction: &Interaction,
+        reaction: ReactionKey,
+        changes: &mut Vec<RelationshipChange>,
+    ) {
+        match interaction.what {
+            InteractionType::Reaction => {
+                let added = changes.iter().map(|change| change.weight).sum();
+                self.reaction_weights.put(reaction, added);
+            }
+            InteractionType::ReactionRemoval => match self.reaction_weights.pop(&reaction) {
+                Some(added) => {
+                    for change in changes.iter_mut() {
+                        change.weight = -added;
+                    }
+                }
+                None => {
+                    debug!("not undoing unknown reaction {:?}", reaction);
+                    changes.clear();
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Apply a set of relationship changes to the graph.
+    pub fn apply(&mut self, interaction: &Interaction, changes: &[RelationshipChange]) {
+        self.apply_without_saving(interaction, changes);
+
+        // TODO: Do we want to be writing this every update?
+        // TODO: Maybe we should use a proper database for the backing store? for all of this?
+        self.save_graph(interaction.guild, interaction.channel);
+    }
+
+    /// Replay already-inferred interactions in order, like calling `apply` for each, but only
+    /// writing each touched graph to the data dir once at the end.
+    pub fn rebuild_from_changes(
+        &mut self,
+        changes: impl Iterator<Item = (Interaction, Vec<RelationshipChange>)>,
+    ) {
+        let mut touched = HashSet::new();
+
+        for (interaction, changes) in changes {
+            self.apply_without_saving(&interaction, &changes);
+            touched.insert((interaction.guild, interaction.channel));
+        }
+
+        for (guild_id, channel_id) in touched {
+            self.save_graph(guild_id, channel_id);
+        }
+    }
+
+    fn apply_without_saving(&mut self, interaction: &Interaction, changes: &[RelationshipChange]) {
+        let guild_id = interaction.guild;
+        let channel_id = interaction.channel;
+
+        self.record_interaction_time(guild_id, interaction.timestamp);
+        for change in changes {
+            self.record_node_interaction(
+                guild_id,
+                change.source,
+                change.target,
+                interaction.timestamp,
+            );
+        }
+
+        // Decay all of the guild channel's graphs a tiny bit.
+        if Self::decays_guild(interaction) {
+            if let Some(guild_graphs) = self.graph.get_mut(&guild_id) {
+                for graph in guild_graphs.values_mut() {
+                    graph.decay(RELATIONSHIP_DECAY_GLOBAL);
+                }
+            }
+        }
+
+        self.applies_since_prune += 1;
+        if self.applies_since_prune >= APPLIES_PER_PRUNE {
+            self.applies_since_prune = 0;
+            self.prune_isolated_nodes(guild_id);
+        }
+
+        let graph = self.get_graph(guild_id, channel_id);
+
+        graph.decay(RELATIONSHIP_DECAY);
+
+        for change in changes {
+            graph.add_weight(change.source, change.target, change.weight);
+
+            if change.weight > 0.0 {
+                graph.touch(change.source, change.target, interaction.timestamp);
+            }
+        }
+    }
+
+    fn decays_guild(interaction: &Interaction) -> bool {
+        interaction.what == InteractionType::Message && !interaction.source_is_bot
+    }
+
+    /// The edges in the interaction's channel that `changes` were applied to, as they are now,
+    /// along with the pairs whose edges were removed by them. Edges that were only decayed
+    /// aren't included.
+    pub fn get_changed_edges(
+        &self,
+        interaction: &Interaction,
+        changes: &[RelationshipChange],
+    ) -> (Vec<StoredEdge>, Vec<UserPair>) {
+        let graph = self
+            .graph
+            .get(&interaction.guild)
+            .and_then(|channels| channels.get(&interaction.channel));
+
+        let pairs: HashSet<_> = changes
+            .iter()
+            .map(|change| (change.source, change.target))
+            .collect();
+
+        let mut edges = Vec::new();
+        let mut removed = Vec::new();
+        for (source, target) in pairs {
+            match graph.and_then(|graph| graph.get(&(source, target))) {
+                Some(&weight) => edges.push(StoredEdge {
+                    channel: interaction.channel,
+                    source,
+                    target,
+                    weight,
+                    last_updated: graph
+                        .and_then(|graph| graph.get_last_updated(source, target))
+                        .unwrap_or(interaction.timestamp),
+                }),
+                None => removed.push((source, target)),
+            }
+        }
+
+        (edges, removed)
+    }
+
+    fn save_graph(&self, guild_id: Id<GuildMarker>, channel_id: Id<ChannelMarker>) {
+        let (data_dir, graph) = match (&self.data_dir, self.graph.get(&guild_id)) {
+            (Some(data_dir), Some(guild)) => match guild.get(&channel_id) {
+                Some(graph) => (data_dir.clone(), graph),
+                None => return,
+            },
+            _ => return,
+        };
+
+        let data_path = Self::graph_data_file_name(data_dir, guild_id, channel_id);
+        if let Err(err) = graph.save_to_path(&data_path) {
+            error!(
+                "failed to store on-disk data for ({}, {}): {}",
+                guild_id, channel_id, err,
+            );
+        }
+    }
+
+    /// Add weight to an edge directly, skipping inference, decay and writing to the data dir,
+    /// for setting up tests. Takes the reason as it's stored in the events table.
+    #[cfg(test)]
+    pub fn apply_from_db_row(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+        reason: u8,
+        weight: RelationshipStrength,
+    ) -> AnyhowResult<()> {
+        use super::inference::RelationshipChangeReason;
+        use std::convert::TryFrom;
+
+        RelationshipChangeReason::try_from(reason)?;
+
+        // Self-loops were recorded before inference filtered them out.
+        if source == target {
+            return Ok(());
+        }
+
+        let graph = self.get_graph(guild_id, channel_id);
+        graph.add_weight(source, target, weight);
+
+        Ok(())
+    }
+
+    /// Weaken every edge that hasn't been strengthened in `cutoff` by `decay_factor`, so people
+    /// who stopped talking drift apart, and remove the ones left below `min_weight`. Returns how
+    /// many edges were removed. Unlike the decay in `apply`, this reaches quiet channels too.
+    pub fn decay(
+        &mut self,
+        decay_factor: f64,
+        cutoff: Duration,
+        min_weight: RelationshipStrength,
+    ) -> usize {
+        let now = get_current_timestamp_ms();
+        let cutoff_ms = now.saturating_sub(cutoff.as_millis() as u64);
+
+        let mut removed = 0;
+        for channels in self.graph.values_mut() {
+            for graph in channels.values_mut() {
+                removed += graph.decay_stale_edges(decay_factor, cutoff_ms, now, min_weight);
+            }
+        }
+
+        let guild_ids: Vec<_> = self.graph.keys().copied().collect();
+        for guild_id in guild_ids {
+            self.save_guild_graphs(guild_id);
+        }
+
+        removed
+    }
+
+    // TODO: Do we want to do this on the client-side instead? Probably.
+    /// All of the guild's channel graphs merged into one, which is empty for guilds we haven't
+    /// seen any interactions in.
+    pub fn build_guild_graph(&self, guild_id: Id<GuildMarker>) -> UserRelationshipGraphMap {
+        match self.graph.get(&guild_id) {
+            Some(guild) => merge_channel_graphs(guild),
+            None => UserRelationshipGraphMap::new(),
+        }
+    }
+
+    /// The guild graph around one user, out to `depth` hops. `None` if they don't have any edges.
+    pub fn build_ego_graph(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        depth: u8,
+    ) -> Option<UserRelationshipGraphMap> {
+        let graph = self
+            .build_guild_graph(guild_id)
+            .ego_graph(user_id, depth.into());
+        if graph.is_empty() {
+            return None;
+        }
+
+        Some(graph)
+    }
+
+    /// A guild's channel graphs, with each thread's graph merged into its parent channel's if
+    /// `merge_threads` is set.
+    pub fn get_channel_graphs(
+        &self,
+        guild_id: Id<GuildMarker>,
+        merge_threads: bool,
+    ) -> HashMap<Id<ChannelMarker>, UserRelationshipGraphMap> {
+        let channels = match self.graph.get(&guild_id) {
+            Some(channels) => channels,
+            None => return HashMap::new(),
+        };
+
+        let relations = match self.channel_relations.get(&guild_id) {
+            Some(relations) if merge_threads => relations,
+            _ => return channels.clone(),
+        };
+
+        let mut merged = HashMap::new();
+        for (&channel_id, graph) in channels {
+            let channel_id = relations
+                .get(&channel_id)
+                .map_or(channel_id, |relation| relation.parent);
+
+            let merged_graph = merged
+                .entry(channel_id)
+                .or_insert_with(UserRelationshipGraphMap::new);
+            for (&(source, target), &weight) in graph.iter() {
+                merged_graph.add_weight(source, target, weight);
+            }
+        }
+
+        merged
+    }
+
+    /// One channel's graph, with the graphs of threads started in it merged in. It's empty if
+    /// there haven't been any interactions there.
+    pub fn build_channel_graph(
+        &self,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) -> UserRelationshipGraphMap {
+        let mut graph = UserRelationshipGraphMap::new();
+
+        let channels = match self.graph.get(&guild_id) {
+            Some(channels) => channels,
+            None => return graph,
+        };
+
+        for (&id, channel_graph) in channels {
+            let parent = self
+                .get_channel_relation(guild_id, id)
+                .map_or(id, |relation| relation.parent);
+            if parent != channel_id {
+                continue;
+            }
+
+            for (&(source, target), &weight) in channel_graph.iter() {
+                graph.add_weight(source, target, weight);
+            }
+        }
+
+        graph
+    }
+
+    /// Every guild's graphs, for saving them all at once.
+    pub fn get_all_graphs(&self) -> &GuildGraphs {
+        &self.graph
+    }
+
+    /// Replace every guild's graphs, like with ones saved before a restart.
+    pub fn restore_all_graphs(&mut self, graphs: GuildGraphs) {
+        self.graph = graphs;
+    }
+
+    /// Copy a guild's graphs out so they can be used after the lock is released.
+    /// Guilds we haven't seen any interactions in get an empty copy.
+    pub fn get_graph_snapshot(&self, guild_id: Id<GuildMarker>) -> Arc<FrozenGraph> {
+        Arc::new(FrozenGraph {
+            channels: self.graph.get(&guild_id).cloned().unwrap_or_default(),
+        })
+    }
+
+    /// Combine two ego graphs into one, returning the users who appear in both.
+    /// Both graphs are expected to come from the same guild graph, so edges they share
+    /// have the same weight.
+    pub fn merge_subgraphs(
+        a: &UserRelationshipGraphMap,
+        b: &UserRelationshipGraphMap,
+    ) -> (UserRelationshipGraphMap, Vec<SharedNode>) {
+        fn get_strengths(
+            graph: &UserRelationshipGraphMap,
+        ) -> HashMap<Id<UserMarker>, RelationshipStrength> {
+            let mut strengths = HashMap::new();
+            for (&(source, target), &weight) in graph.iter() {
+                *strengths.entry(source).or_default() += weight;
+                *strengths.entry(target).or_default() += weight;
+            }
+
+            strengths
+        }
+
+        let mut merged = a.clone();
+        for (&source_target, &weight) in b.iter() {
+            let merged_weight = merged.0.entry(source_target).or_insert(weight);
+            *merged_weight = merged_weight.max(weight);
+        }
+
+        let first_strengths = get_strengths(a);
+        let second_strengths = get_strengths(b);
+
+        let mut shared: Vec<_> = first_strengths
+            .iter()
+            .filter_map(|(&user_id, &first_strength)| {
+                Some(SharedNode {
+                    user_id,
+                    first_strength,
+                    second_strength: *second_strengths.get(&user_id)?,
+                })
+            })
+            .collect();
+
+        shared.sort_by_key(|node| node.user_id);
+
+        (merged, shared)
+    }
+
+    /// Note that the guild had an interaction at `timestamp`, if it's newer than the last one.
+    pub fn record_interaction_time(&mut self, guild_id: Id<GuildMarker>, timestamp: u64) {
+        let last = self.last_interaction.entry(guild_id).or_default();
+        *last = (*last).max(timestamp);
+    }
+
+    /// Note that `source` did something involving `target` at `timestamp`.
+    pub fn record_node_interaction(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+        timestamp: u64,
+    ) {
+        let metadata = self.node_metadata.entry(guild_id).or_default();
+
+        let source = metadata.entry(source).or_default();
+        source.record(timestamp);
+        source.total_interactions_as_source += 1;
+
+        let target = metadata.entry(target).or_default();
+        target.record(timestamp);
+        target.total_interactions_as_target += 1;
+    }
+
+    /// Statistics about a user's interactions in the guild, if they've had any.
+    pub fn get_node_metadata(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> Option<NodeMetadata> {
+        self.node_metadata
+            .get(&guild_id)?
+            .get(&user_id)
+            .copied()
+            .filter(|metadata| metadata.last_seen_ms != 0)
+    }
+
+    /// Whether the user has asked us to stop tracking their interactions in the guild.
+    pub fn is_opted_out(&self, guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> bool {
+        self.node_metadata
+            .get(&guild_id)
+            .and_then(|metadata| metadata.get(&user_id))
+            .is_some_and(|metadata| metadata.is_opted_out)
+    }
+
+    /// Stop or restart inferring anything about the user in the guild. Their existing
+    /// connections are kept, and fade away with decay like any others.
+    pub fn set_opted_out(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        opted_out: bool,
+    ) {
+        self.node_metadata
+            .entry(guild_id)
+            .or_default()
+            .entry(user_id)
+            .or_default()
+            .is_opted_out = opted_out;
+    }
+
+    /// How long the guild's graph has been accumulating interactions, from the earliest one
+    /// we know of. `None` if there haven't been any.
+    pub fn get_graph_age(&self, guild_id: Id<GuildMarker>) -> Option<Duration> {
+        let first_seen_ms = self
+            .node_metadata
+            .get(&guild_id)?
+            .values()
+            .map(|metadata| metadata.first_seen_ms)
+            .filter(|&first_seen_ms| first_seen_ms != 0)
+            .min()?;
+
+        Some(Duration::from_millis(
+            get_current_timestamp_ms().saturating_sub(first_seen_ms),
+        ))
+    }
+
+    /// Summarize a guild's graph without merging its channel graphs together.
+    pub fn summarize(&self, guild_id: Id<GuildMarker>) -> Option<GraphSummary> {
+        let guild = self.graph.get(&guild_id)?;
+
+        let mut edges = HashSet::new();
+        let mut strengths: HashMap<Id<UserMarker>, f64> = HashMap::new();
+        for graph in guild.values() {
+            for (&(source, target), &weight) in graph.iter() {
+                edges.insert((source, target));
+                *strengths.entry(source).or_default() += weight as f64;
+                *strengths.entry(target).or_default() += weight as f64;
+            }
+        }
+
+        let node_count = strengths.len();
+        let possible_edges = node_count * node_count.saturating_sub(1);
+        let density = if possible_edges > 0 {
+            edges.len() as f64 / possible_edges as f64
+        } else {
+            0.0
+        };
+
+        let mut top_users: Vec<_> = strengths.into_iter().collect();
+        top_users.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        top_users.truncate(SUMMARY_TOP_USERS);
+
+        Some(GraphSummary {
+            guild_id,
+            node_count,
+            edge_count: edges.len(),
+            density,
+            top_users,
+            last_interaction_ms: self
+                .last_interaction
+                .get(&guild_id)
+                .copied()
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Remove users left without any connections in the guild, returning how many there were.
+    /// Users only exist through their edges, so this drops edges that have no weight left and
+    /// counts the users that disappear with them.
+    pub fn prune_isolated_nodes(&mut self, guild_id: Id<GuildMarker>) -> usize {
+        let guild = match self.graph.get_mut(&guild_id) {
+            Some(guild) => guild,
+            None => return 0,
+        };
+
+        let mut connected = HashSet::new();
+        let mut before = HashSet::new();
+        for graph in guild.values_mut() {
+            before.extend(graph.get_users());
+            graph.remove_empty_edges();
+            connected.extend(graph.get_users());
+        }
+
+        let pruned = before.difference(&connected).count();
+        debug!("pruned {} isolated nodes from guild {}", pruned, guild_id);
+
+        pruned
+    }
+
+    /// How strongly two users are connected through the shortest path between them.
+    pub fn get_path_weight(
+        &self,
+        guild_id: Id<GuildMarker>,
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+        aggregation: PathWeightAggregation,
+    ) -> Option<RelationshipStrength> {
+        self.build_guild_graph(guild_id)
+            .get_path_weight(source, target, aggregation)
+    }
+
+    /// The guild graph restricted to `user_ids`, for commands that only draw some users.
+    pub fn get_subgraph(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_ids: &HashSet<Id<UserMarker>>,
+    ) -> UserRelationshipGraphMap {
+        self.build_guild_graph(guild_id).subgraph(user_ids)
+    }
+
+    /// The guild graph as an adjacency list, for algorithms that walk it repeatedly.
+    pub fn to_adjacency_list(
+        &self,
+        guild_id: Id<GuildMarker>,
+    ) -> HashMap<Id<UserMarker>, Vec<(Id<UserMarker>, RelationshipStrength)>> {
+        self.build_guild_graph(guild_id).to_adjacency_list()
+    }
+
+    /// Total weight of edges from other users to this one across the guild's channels,
+    /// a measure of how popular they are.
+    pub fn in_degree(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> RelationshipStrength {
+        self.sum_guild_edges(guild_id, |_, target| target == user_id)
+    }
+
+    /// Total weight of edges from this user to others across the guild's channels,
+    /// a measure of how sociable they are.
+    pub fn out_degree(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+    ) -> RelationshipStrength {
+        self.sum_guild_edges(guild_id, |source, _| source == user_id)
+    }
+
+    fn sum_guild_edges(
+        &self,
+        guild_id: Id<GuildMarker>,
+        filter: impl Fn(Id<UserMarker>, Id<UserMarker>) -> bool,
+    ) -> RelationshipStrength {
+        let guild = match self.graph.get(&guild_id) {
+            Some(guild) => guild,
+            None => return 0.0,
+        };
+
+        guild
+            .values()
+            .flat_map(|graph| graph.iter())
+            .filter(|(&(source, target), _)| filter(source, target))
+            .map(|(_, weight)| weight)
+            .sum()
+    }
+
+    /// How likely the user's connections across the guild are to be connected to each other.
+    pub fn cluster_coefficient(&self, guild_id: Id<GuildMarker>, user_id: Id<UserMarker>) -> f64 {
+        self.build_guild_graph(guild_id)
+            .cluster_coefficient(user_id)
+    }
+
+    /// The maximum social distance in the guild, see `UserRelationshipGraphMap::diameter`.
+    pub fn graph_diameter(&self, guild_id: Id<GuildMarker>) -> Option<usize> {
+        let graph = self.build_guild_graph(guild_id);
+        if graph.is_empty() {
+            return None;
+        }
+
+        graph.diameter()
+    }
+
+    /// The mean cluster coefficient of every user in the guild, a measure of how tight-knit it is.
+    pub fn average_cluster_coefficient(&self, guild_id: Id<GuildMarker>) -> f64 {
+        self.build_guild_graph(guild_id)
+            .average_cluster_coefficient()
+    }
+
+    /// The guild's communities, largest first.
+    pub fn get_communities_by_size(&self, guild_id: Id<GuildMarker>) -> Vec<Vec<Id<UserMarker>>> {
+        let mut communities = self.build_guild_graph(guild_id).get_communities();
+
+        communities.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+
+        communities
+    }
+
+    // TODO: Temporary hack for debug command.
+    /// Link a guild's channels by how many of the same users have interactions in both.
+    /// Threads are counted as part of their channel.
+    pub fn channel_interaction_graph(&self, guild_id: Id<GuildMarker>) -> Option<ChannelGraph> {
+        if !self.graph.contains_key(&guild_id) {
+            return None;
+        }
+
+        let mut channels: Vec<_> = self
+            .get_channel_graphs(guild_id, true)
+            .iter()
+            .map(|(&channel_id, graph)| (channel_id, graph.get_users()))
+            .collect();
+        channels.sort_by_key(|&(channel_id, _)| channel_id);
+
+        let mut edges = HashMap::new();
+        for (i, (a, a_users)) in channels.iter().enumerate() {
+            for (b, b_users) in &channels[(i + 1)..] {
+                let shared_users = a_users.intersection(b_users).count() as u64;
+                if shared_users > 0 {
+                    edges.insert((*a, *b), shared_users);
+                }
+            }
+        }
+
+        Some(edges)
+    }
+
+    /// Stats for every guild with a graph, most recently active first.
+    pub fn list_guilds_with_stats(&self) -> Vec<GuildStats> {
+        let mut guilds: Vec<_> = self
+            .graph
+            .iter()
+            .map(|(&guild_id, guild)| {
+                let mut users = HashSet::new();
+                let mut edges = HashSet::new();
+                for graph in guild.values() {
+                    for &(source, target) in graph.0.keys() {
+                        users.insert(source);
+                        users.insert(target);
+                        edges.insert((source, target));
+                    }
+                }
+
+                GuildStats {
+                    guild_id,
+                    node_count: users.len(),
+                    edge_count: edges.len(),
+                    last_interaction_ms: self
+                        .last_interaction
+                        .get(&guild_id)
+                        .copied()
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        guilds.sort_by(|a, b| {
+            b.last_interaction_ms
+                .cmp(&a.last_interaction_ms)
+                .then(a.guild_id.cmp(&b.guild_id))
+        });
+
+        guilds
+    }
+
+    /// A guild's channel graphs, starting with none for guilds we haven't seen before.
+    pub fn get_or_create_guild_graph(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+    ) -> &mut HashMap<Id<ChannelMarker>, UserRelationshipGraphMap> {
+        self.graph.entry(guild_id).or_default()
+    }
+
+    pub(crate) fn get_graph(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) -> &mut UserRelationshipGraphMap {
+        let data_dir = self.data_dir.clone();
+
+        self.get_or_create_guild_graph(guild_id)
+            .entry(channel_id)
+            .or_insert_with(|| {
+                let existing_graph = data_dir.and_then(|data_dir| {
+                    let data_path = Self::graph_data_file_name(data_dir, guild_id, channel_id);
+                    match UserRelationshipGraphMap::new_from_path(&data_path) {
+                        Ok(graph) => Some(graph),
+                        Err(err) if err.kind() == IoErrorKind::NotFound => None,
+                        Err(err) => {
+                            error!(
+                                "failed to load on-disk data for ({}, {}): {}",
+                                guild_id, channel_id, err,
+                            );
+
+                            None
+                        }
+                    }
+                });
+
+                existing_graph.unwrap_or_else(UserRelationshipGraphMap::new)
+            })
+    }
+
+    /// Fill in any of the guild's channel graphs that are still empty from saved edges.
+    /// Graphs that already have edges are newer than the saved ones, so they're left alone.
+    /// Returns how many graphs were restored.
+    pub fn restore_empty_channel_graphs(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        edges: impl IntoIterator<Item = StoredEdge>,
+    ) -> usize {
+        let mut restored: HashMap<Id<ChannelMarker>, UserRelationshipGraphMap> = HashMap::new();
+        for edge in edges {
+            let graph = restored
+                .entry(edge.channel)
+                .or_insert_with(UserRelationshipGraphMap::new);
+            graph.add_weight(edge.source, edge.target, edge.weight);
+            graph.touch(edge.source, edge.target, edge.last_updated);
+        }
+
+        let channels = self.get_or_create_guild_graph(guild_id);
+
+        let mut count = 0;
+        for (channel_id, graph) in restored {
+            let existing = channels
+                .entry(channel_id)
+                .or_insert_with(UserRelationshipGraphMap::new);
+            if existing.is_empty() {
+                *existing = graph;
+                count += 1;
+            }
+        }
+
+        count
+    }
+
+    pub fn remove_guild(&mut self, guild_id: Id<GuildMarker>) {
+        let channels = self.graph.remove(&guild_id);
+
+        if let Some(channels) = channels {
+            for &channel_id in channels.keys() {
+                self.state.remove(&(guild_id, channel_id));
+            }
+        }
+
+        self.channel_relations.remove(&guild_id);
+        self.scheduled_events
+            .retain(|_, event| event.guild != guild_id);
+        self.presence.remove_guild(guild_id);
+    }
+
+    /// Write all of a guild's graphs to the data dir.
+    pub fn save_guild_graphs(&self, guild_id: Id<GuildMarker>) {
+        if let Some(guild) = self.graph.get(&guild_id) {
+            for &channel_id in guild.keys() {
+                self.save_graph(guild_id, channel_id);
+            }
+        }
+    }
+
+    /// Remember that a channel is a thread in `parent`.
+    pub fn set_channel_relation(
+        &mut self,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+        relation: ChannelRelation,
+    ) {
+        self.channel_relations
+            .entry(guild_id)
+            .or_default()
+            .insert(channel_id, relation);
+    }
+
+    pub fn get_channel_relation(
+        &self,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) -> Option<ChannelRelation> {
+        self.channel_relations
+            .get(&guild_id)?
+            .get(&channel_id)
+            .copied()
+    }
+
+    pub fn remove_channel(&mut self, guild_id: Id<GuildMarker>, channel_id: Id<ChannelMarker>) {
+        self.state.remove(&(guild_id, channel_id));
+
+        if let Some(relations) = self.channel_relations.get_mut(&guild_id) {
+            relations.remove(&channel_id);
+        }
+
+        if let Some(channels) = self.graph.get_mut(&guild_id) {
+            channels.remove(&channel_id);
+        }
+    }
+
+    /// Drop a channel's graph and its copy in the data dir, leaving the guild's other channels
+    /// as they are. The channel starts again from an empty graph if anyone talks in it.
+    pub fn forget_channel(&mut self, guild_id: Id<GuildMarker>, channel_id: Id<ChannelMarker>) {
+        self.state.remove(&(guild_id, channel_id));
+
+        if let Some(channels) = self.graph.get_mut(&guild_id) {
+            channels.remove(&channel_id);
+        }
+
+        let data_dir = match &self.data_dir {
+            Some(data_dir) => data_dir.clone(),
+            None => return,
+        };
+
+        let data_path = Self::graph_data_file_name(data_dir, guild_id, channel_id);
+        match std::fs::remove_file(&data_path) {
+            Ok(()) => (),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => error!(
+                "failed to remove on-disk data {}: {}",
+                data_path.display(),
+                err,
+            ),
+        }
+    }
+
+    pub fn get_scheduled_event_mut(
+        &mut self,
+        event_id: Id<ScheduledEventMarker>,
+    ) -> Option<&mut ScheduledEventState> {
+        self.scheduled_events.get_mut(&event_id)
+    }
+
+    /// Track the latest state of a scheduled event, returning the attendance
+    /// interaction to process if the event has just started.
+    pub fn update_scheduled_event(&mut self, event: &GuildScheduledEvent) -> Option<Interaction> {
+        let state = self
+            .scheduled_events
+            .entry(event.id)
+            .or_insert_with(|| ScheduledEventState::from(event));
+
+        let started = state.status != ScheduledEventStatus::Active
+            && event.status == ScheduledEventStatus::Active;
+
+        state.channel = event.channel_id;
+        state.creator = event.creator_id.or(state.creator);
+        state.status = event.status;
+
+        if started {
+            Interaction::new_from_scheduled_event_start(state)
+        } else {
+            None
+        }
+    }
+
+    pub fn remove_scheduled_event(&mut self, event_id: Id<ScheduledEventMarker>) {
+        self.scheduled_events.remove(&event_id);
+    }
+
+    fn graph_data_file_name(
+        data_dir: PathBuf,
+        guild_id: Id<GuildMarker>,
+        channel_id: Id<ChannelMarker>,
+    ) -> PathBuf {
+        let mut file_name = data_dir;
+        file_name.push(format!("{}_{}.json", guild_id, channel_id));
+        file_name
+    }
+}
+
+#[cfg(test)]
+mod escape_dot_label_tests {
+    use super::escape_dot_label;
+
+    #[test]
+    fn test_special_characters() {
+        assert_eq!(
+            escape_dot_label("a\"b<c>d{e}f|g\nh\\i"),
+            "a\\\"b\\<c\\>d\\{e\\}f\\|g\\nh\\\\i"
+        );
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(escape_dot_label("plain name"), "plain name");
+    }
+}
+
+#[cfg(test)]
+mod social_graph_tests {
+    use super::{
+        ChannelRelation, GuildStats, NodeMetadata, PathWeightAggregation, SharedNode, SocialGraph,
+        StoredEdge, COOLDOWN_WEIGHT_FACTOR, EXACT_DIAMETER_LIMIT,
+    };
+    use crate::social::inference::test_interactions::interaction;
+    use crate::social::inference::{
+        Interaction, InteractionType, RelationshipChange, RelationshipChangeReason,
+        RELATIONSHIP_DECAY, RELATIONSHIP_DECAY_GLOBAL,
+    };
+    use crate::util::get_current_timestamp_ms;
+    use twilight_model::id::Id;
+
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_rebuild_from_changes() {
+        let guild = Id::new(1);
+        let (alice, bob) = (Id::new(3), Id::new(4));
+
+        let interaction = interaction(InteractionType::Message, Some(4), &[]);
+
+        let changes = vec![RelationshipChange::new(
+            alice,
+            bob,
+            RelationshipChangeReason::MessageDirectMention,
+        )];
+
+        let mut social = SocialGraph::new(None);
+        social.rebuild_from_changes(vec![(interaction.clone(), changes.clone()); 2].into_iter());
+
+        let graph = social.build_guild_graph(guild);
+
+        // The second interaction decays the first change before adding its own.
+        let expected = 2.0 * RelationshipChangeReason::MessageDirectMention.get_change_strength()
+            + RELATIONSHIP_DECAY_GLOBAL
+            + RELATIONSHIP_DECAY;
+        assert_eq!(graph.len(), 1);
+        assert!((graph[&(alice, bob)] - expected).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_infer_drops_self_loops() {
+        let guild = Id::new(1);
+
+        let interaction = interaction(InteractionType::Message, Some(3), &[3]);
+
+        let mut social = SocialGraph::new(None);
+        let changes = social.infer(&interaction);
+        assert!(changes.is_empty());
+
+        social.apply(&interaction, &changes);
+        assert!(social.build_guild_graph(guild).is_empty());
+    }
+
+    #[test]
+    fn test_restore_empty_channel_graphs() {
+        let guild = Id::new(1);
+        let (empty_channel, busy_channel) = (Id::new(2), Id::new(3));
+        let (alice, bob) = (Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        social.get_graph(guild, empty_channel);
+        social
+            .apply_from_db_row(guild, busy_channel, alice, bob, 2, 3.0)
+            .unwrap();
+
+        let edge = |channel, weight| StoredEdge {
+            channel,
+            source: alice,
+            target: bob,
+            weight,
+            last_updated: 1,
+        };
+
+        let restored = social.restore_empty_channel_graphs(
+            guild,
+            vec![edge(empty_channel, 1.5), edge(busy_channel, 10.0)],
+        );
+        assert_eq!(restored, 1);
+
+        let graphs = social.get_channel_graphs(guild, false);
+        assert_eq!(graphs[&empty_channel].get(&(alice, bob)), Some(&1.5));
+        assert_eq!(graphs[&empty_channel].get(&(bob, alice)), None);
+        assert_eq!(graphs[&busy_channel].get(&(alice, bob)), Some(&3.0));
+    }
+
+    #[test]
+    fn test_forget_channel() {
+        let guild = Id::new(1);
+        let (forgotten_channel, other_channel) = (Id::new(2), Id::new(3));
+        let (alice, bob) = (Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        for &channel in &[forgotten_channel, other_channel] {
+            social
+                .apply_from_db_row(guild, channel, alice, bob, 2, 3.0)
+                .unwrap();
+        }
+
+        social.forget_channel(guild, forgotten_channel);
+
+        let graphs = social.get_channel_graphs(guild, false);
+        assert!(!graphs.contains_key(&forgotten_channel));
+        assert_eq!(graphs[&other_channel].get(&(alice, bob)), Some(&3.0));
+    }
+
+    #[test]
+    fn test_changed_edges() {
+        let guild = Id::new(1);
+        let (channel, other_channel) = (Id::new(2), Id::new(3));
+        let (alice, bob) = (Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        social
+            .apply_from_db_row(guild, other_channel, alice, bob, 2, 3.0)
+            .unwrap();
+
+        let mut interaction = Interaction {
+            timestamp: 10,
+            source: alice,
+            ..interaction(InteractionType::Message, Some(5), &[])
+        };
+
+        let changes = social.infer(&interaction);
+        social.apply(&interaction, &changes);
+
+        // The other channel was decayed too, but only the changed edge needs saving.
+        let (edges, removed) = social.get_changed_edges(&interaction, &changes);
+        assert_eq!(edges.len(), 1);
+        assert_eq!(
+            (edges[0].channel, edges[0].source, edges[0].target),
+            (channel, alice, bob),
+        );
+        assert_eq!(edges[0].last_updated, 10);
+        assert!(removed.is_empty());
+
+        // Taking back more than the edge has removes it.
+        interaction.what = InteractionType::ReactionRemoval;
+        let mut changes = changes;
+        for change in &mut changes {
+            change.weight = -100.0;
+        }
+        social.apply(&interaction, &changes);
+
+        let (edges, removed) = social.get_changed_edges(&interaction, &changes);
+        assert!(edges.is_empty());
+        assert_eq!(removed, vec![(alice, bob)]);
+    }
+
+    #[test]
+    fn test_disabled_rules() {
+        let guild = Id::new(1);
+
+        let interaction = interaction(InteractionType::Message, Some(4), &[5]);
+
+        let mut social = SocialGraph::new(None);
+        assert!(social.set_rule_enabled(guild, "unknown", false).is_err());
+
+        social.set_rule_enabled(guild, "mention", false).unwrap();
+        assert!(social.get_rules(guild).contains(&("mention", false)));
+        assert!(social.get_rules(Id::new(9)).contains(&("mention", true)));
+
+        let changes = social.infer(&interaction);
+        assert!(changes
+            .iter()
+            .all(|change| change.reason != RelationshipChangeReason::MessageIndirectMention));
+        assert!(changes
+            .iter()
+            .any(|change| change.reason == RelationshipChangeReason::MessageDirectMention));
+    }
+
+    #[test]
+    fn test_opted_out_users_are_ignored() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        social.set_opted_out(guild, carol, true);
+        assert!(social.is_opted_out(guild, carol));
+        assert!(!social.is_opted_out(Id::new(9), carol));
+        assert!(social.get_node_metadata(guild, carol).is_none());
+
+        let interaction = interaction(InteractionType::Message, Some(4), &[5]);
+        let changes = social.infer(&interaction);
+        assert!(changes
+            .iter()
+            .all(|change| change.source != carol && change.target != carol));
+        assert!(changes
+            .iter()
+            .any(|change| (change.source, change.target) == (alice, bob)));
+
+        social.set_opted_out(guild, carol, false);
+        assert!(social
+            .infer(&interaction)
+            .iter()
+            .any(|change| change.target == carol));
+    }
+
+    #[test]
+    fn test_build_channel_graph() {
+        let guild = Id::new(1);
+        let (channel, thread, other_channel) = (Id::new(2), Id::new(3), Id::new(4));
+        let (alice, bob, carol) = (Id::new(5), Id::new(6), Id::new(7));
+
+        let mut social = SocialGraph::new(None);
+        social.set_channel_relation(guild, thread, ChannelRelation { parent: channel });
+        for &(channel_id, target) in &[(channel, bob), (thread, bob), (other_channel, carol)] {
+            social
+                .apply_from_db_row(guild, channel_id, alice, target, 2, 1.0)
+                .unwrap();
+        }
+
+        let graph = social.build_channel_graph(guild, channel);
+        assert_eq!(graph.len(), 1);
+        assert_eq!(graph[&(alice, bob)], 2.0);
+
+        assert!(social.build_channel_graph(guild, Id::new(8)).is_empty());
+    }
+
+    #[test]
+    fn test_build_ego_graph() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
+        let (alice, bob, carol, dave) = (Id::new(3), Id::new(4), Id::new(5), Id::new(6));
+
+        let mut social = SocialGraph::new(None);
+        for &(source, target) in &[(alice, bob), (bob, carol), (carol, dave)] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, 1.0)
+                .unwrap();
+        }
+
+        assert_eq!(social.build_ego_graph(guild, alice, 1).unwrap().len(), 1);
+        assert_eq!(social.build_ego_graph(guild, alice, 2).unwrap().len(), 2);
+        assert_eq!(social.build_ego_graph(guild, bob, 1).unwrap().len(), 2);
+        assert!(social.build_ego_graph(guild, Id::new(7), 1).is_none());
+    }
+
+    #[test]
+    fn test_decay() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
+        let (alice, bob, carol, dave) = (Id::new(3), Id::new(4), Id::new(5), Id::new(6));
+        let day = 24 * 60 * 60 * 1000;
+        let now = get_current_timestamp_ms();
+
+        let mut social = SocialGraph::new(None);
+        for &(target, timestamp) in &[(bob, now - 40 * day), (carol, now - day)] {
+            let interaction = Interaction {
+                timestamp,
+                ..interaction(InteractionType::Message, None, &[target.get()])
+            };
+            let changes = vec![RelationshipChange::new(
+                alice,
+                target,
+                RelationshipChangeReason::MessageIndirectMention,
+            )];
+            social.apply(&interaction, &changes);
+        }
+
+        // Replayed without a timestamp, so we can't tell how old it is.
+        social
+            .apply_from_db_row(guild, channel, alice, dave, 3, 0.5)
+            .unwrap();
+
+        let before = social.build_guild_graph(guild);
+        let removed = social.decay(0.5, Duration::from_secs(30 * 24 * 60 * 60), 0.1);
+        let after = social.build_guild_graph(guild);
+
+        assert_eq!(removed, 0);
+        assert_eq!(after[&(alice, bob)], before[&(alice, bob)] * 0.5);
+        assert_eq!(after[&(alice, carol)], before[&(alice, carol)]);
+        assert_eq!(after[&(alice, dave)], before[&(alice, dave)]);
+
+        // Only stale edges are removed once they're light enough.
+        let removed = social.decay(0.5, Duration::from_secs(30 * 24 * 60 * 60), 1.0);
+        let after = social.build_guild_graph(guild);
+
+        assert_eq!(removed, 1);
+        assert!(!after.contains_key(&(alice, bob)));
+        assert_eq!(after.len(), 2);
+    }
+
+    #[test]
+    fn test_apply_cooldown() {
+        let (alice, bob) = (Id::new(3), Id::new(4));
+        let cooldown = Duration::from_secs(5);
+        let started = Instant::now();
+
+        let interaction_at = |when| Interaction {
+            when,
+            ..interaction(InteractionType::Message, Some(4), &[4])
+        };
+        let weight = RelationshipChangeReason::MessageDirectMention.get_change_strength();
+
+        let mut social = SocialGraph::new(None);
+        let mut apply_at = |when| {
+            let mut changes = vec![RelationshipChange::new(
+                alice,
+                bob,
+                RelationshipChangeReason::MessageDirectMention,
+            )];
+            social.apply_cooldown(&interaction_at(when), &mut changes, cooldown);
+            changes[0].weight
+        };
+
+        assert_eq!(apply_at(started), weight);
+        assert_eq!(
+            apply_at(started + Duration::from_secs(1)),
+            weight * COOLDOWN_WEIGHT_FACTOR,
+        );
+
+        // The cooldown runs from the last full-strength change.
+        assert_eq!(apply_at(started + Duration::from_secs(5)), weight);
+    }
+
+    #[test]
+    fn test_get_communities_by_size() {
+        let guild = Id::new(1);
+        let (alice, bob, carol, dave, erin) =
+            (Id::new(3), Id::new(4), Id::new(5), Id::new(6), Id::new(7));
+
+        let mut social = SocialGraph::new(None);
+        for &(channel, source, target) in &[
+            (Id::new(2), alice, bob),
+            (Id::new(2), bob, carol),
+            (Id::new(8), carol, alice),
+            (Id::new(8), dave, erin),
+        ] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, 2.0)
+                .unwrap();
+        }
+
+        let communities = social.get_communities_by_size(guild);
+        assert_eq!(communities.len(), 2);
+        assert_eq!(communities[0], vec![alice, bob, carol]);
+        assert_eq!(communities[1], vec![dave, erin]);
+
+        assert!(social.get_communities_by_size(Id::new(9)).is_empty());
+    }
+
+    #[test]
+    fn test_get_subgraph() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        for &(source, target) in &[(alice, bob), (bob, carol), (carol, alice)] {
+            social
+                .apply_from_db_row(guild, Id::new(2), source, target, 2, 1.0)
+                .unwrap();
+        }
+
+        let full = social.build_guild_graph(guild);
+
+        let all_users = [alice, bob, carol].iter().copied().collect();
+        assert_eq!(*social.get_subgraph(guild, &all_users), *full);
+
+        let some_users = [alice, bob].iter().copied().collect();
+        let subgraph = social.get_subgraph(guild, &some_users);
+        assert_eq!(subgraph.len(), 1);
+        assert!(subgraph.contains_key(&(alice, bob)));
+    }
+
+    #[test]
+    fn test_to_adjacency_list() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        for &(source, target, weight) in
+            &[(alice, carol, 1.0), (alice, bob, 2.0), (bob, alice, 3.0)]
+        {
+            social
+                .apply_from_db_row(guild, Id::new(2), source, target, 2, weight)
+                .unwrap();
+        }
+
+        let adjacency = social.to_adjacency_list(guild);
+        assert_eq!(adjacency[&alice], vec![(bob, 2.0), (carol, 1.0)]);
+        assert_eq!(adjacency[&bob], vec![(alice, 3.0)]);
+        assert!(adjacency[&carol].is_empty());
+    }
+
+    #[test]
+    fn test_prune_isolated_nodes() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        social
+            .apply_from_db_row(guild, Id::new(2), alice, bob, 2, 1.0)
+            .unwrap();
+        social
+            .apply_from_db_row(guild, Id::new(2), alice, carol, 2, 0.0)
+            .unwrap();
+
+        assert_eq!(social.prune_isolated_nodes(guild), 1);
+        assert_eq!(social.build_guild_graph(guild).len(), 1);
+        assert_eq!(social.prune_isolated_nodes(guild), 0);
+    }
+
+    #[test]
+    fn test_prune_isolated_nodes_from_copy() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
 
-            if let Some(role_color) = role_color {
-                color = role_color;
-            }
+        let mut social = SocialGraph::new(None);
+        social
+            .apply_from_db_row(guild, Id::new(2), alice, bob, 2, 1.0)
+            .unwrap();
+        social
+            .apply_from_db_row(guild, Id::new(2), alice, carol, 2, 0.0)
+            .unwrap();
 
-            if let Some(user) = requesting_user {
-                // Invert the colors if it is the requesting user.
-                if *user_id == user.id {
-                    // Make the text bold.
-                    label = format!("<B>{}</B>", label);
+        let mut graph = social.build_guild_graph(guild);
+        assert_eq!(graph.prune_isolated_nodes(), 1);
+        assert_eq!(graph.len(), 1);
 
-                    peripheries = 2;
+        // The live graph is left alone.
+        assert_eq!(social.build_guild_graph(guild).len(), 2);
+    }
 
-                    fillcolor = color;
+    #[test]
+    fn test_get_path_weight() {
+        let guild = Id::new(1);
+        let (alice, bob, carol, dave) = (Id::new(3), Id::new(4), Id::new(5), Id::new(6));
 
-                    // Select text color based on fill contrast.
-                    fontcolor = if calculate_luma(fillcolor) > 186.0 {
-                        FG_LIGHT
-                    } else {
-                        FG_DARK
-                    };
-                }
-            }
+        let mut social = SocialGraph::new(None);
+        for &(source, target, weight) in &[(alice, bob, 2.0), (carol, bob, 3.0), (dave, dave, 1.0)]
+        {
+            social
+                .apply_from_db_row(guild, Id::new(2), source, target, 2, weight)
+                .unwrap();
+        }
 
-            lines.push(format!(
-                "    {} [ label = <{}>, penwidth = \"{}\", style = \"filled\", peripheries = \"{}\", color = \"#{:06X}\", fillcolor = \"#{:06X}\", fontcolor = \"#{:06X}\" ]",
-                user_id,
-                label,
-                width,
-                peripheries,
-                color,
-                fillcolor,
-                fontcolor,
-            ));
+        let graph = social.build_guild_graph(guild);
+        assert_eq!(graph.get_path(alice, carol), Some(vec![alice, bob, carol]));
+        assert_eq!(graph.get_path(alice, dave), None);
+
+        let path_weight = |aggregation| social.get_path_weight(guild, alice, carol, aggregation);
+        assert_eq!(path_weight(PathWeightAggregation::Product), Some(6.0));
+        assert_eq!(path_weight(PathWeightAggregation::Minimum), Some(2.0));
+        assert_eq!(path_weight(PathWeightAggregation::Sum), Some(5.0));
+    }
+
+    #[test]
+    fn test_in_and_out_degree() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        for &(channel, source, target, weight) in &[
+            (Id::new(2), alice, bob, 2.0),
+            (Id::new(6), alice, bob, 1.0),
+            (Id::new(2), carol, bob, 0.5),
+            (Id::new(2), bob, alice, 0.25),
+        ] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, weight)
+                .unwrap();
         }
 
-        for (key, weight) in undirected_edges {
-            let width = 1.0 + weight.log10();
-            lines.push(format!(
-                "    {} -- {} [ weight = \"{}\", penwidth = \"{}\", color = \"#{:06X}\" ]",
-                key[0], key[1], weight, width, fg_color,
-            ));
+        assert_eq!(social.in_degree(guild, bob), 3.5);
+        assert_eq!(social.out_degree(guild, bob), 0.25);
+        assert_eq!(social.out_degree(guild, alice), 3.0);
+        assert_eq!(social.in_degree(guild, carol), 0.0);
+        assert_eq!(social.in_degree(Id::new(9), alice), 0.0);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let guild = Id::new(1);
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
+
+        let mut social = SocialGraph::new(None);
+        for &(channel, source, target, weight) in &[
+            (Id::new(2), alice, bob, 2.0),
+            (Id::new(6), alice, bob, 1.0),
+            (Id::new(2), carol, bob, 0.5),
+        ] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, weight)
+                .unwrap();
         }
+        social.record_interaction_time(guild, 20);
+        social.record_interaction_time(guild, 10);
 
-        lines.push(String::from("}"));
+        let summary = social.summarize(guild).unwrap();
+        assert_eq!(summary.guild_id, guild);
+        assert_eq!(summary.node_count, 3);
+        assert_eq!(summary.edge_count, 2);
+        assert!((summary.density - 2.0 / 6.0).abs() < 1e-9);
+        assert_eq!(
+            summary.top_users,
+            vec![(bob, 3.5), (alice, 3.0), (carol, 0.5)]
+        );
+        assert_eq!(summary.last_interaction_ms, 20);
 
-        Ok(lines.join("\n"))
+        assert!(social.summarize(Id::new(9)).is_none());
     }
-}
 
-impl std::ops::Deref for UserRelationshipGraphMap {
-    type Target = HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipStrength>;
+    #[test]
+    fn test_list_guilds_with_stats() {
+        let (quiet, busy) = (Id::new(1), Id::new(2));
+        let (alice, bob, carol) = (Id::new(3), Id::new(4), Id::new(5));
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+        let mut social = SocialGraph::new(None);
+        for &(guild, channel, source, target) in &[
+            (quiet, Id::new(6), alice, bob),
+            (busy, Id::new(6), alice, bob),
+            (busy, Id::new(7), alice, bob),
+            (busy, Id::new(7), carol, bob),
+        ] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, 1.0)
+                .unwrap();
+        }
+        social.record_interaction_time(quiet, 10);
+        social.record_interaction_time(busy, 20);
+
+        assert_eq!(
+            social.list_guilds_with_stats(),
+            vec![
+                GuildStats {
+                    guild_id: busy,
+                    node_count: 3,
+                    edge_count: 2,
+                    last_interaction_ms: 20,
+                },
+                GuildStats {
+                    guild_id: quiet,
+                    node_count: 2,
+                    edge_count: 1,
+                    last_interaction_ms: 10,
+                },
+            ],
+        );
     }
-}
 
-impl std::ops::DerefMut for UserRelationshipGraphMap {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+    #[test]
+    fn test_channel_interaction_graph() {
+        let guild = Id::new(1);
+        let (general, memes, lonely) = (Id::new(2), Id::new(3), Id::new(4));
+        let (alice, bob, carol, dave, erin) =
+            (Id::new(5), Id::new(6), Id::new(7), Id::new(8), Id::new(9));
+
+        let mut social = SocialGraph::new(None);
+        for &(channel, source, target) in &[
+            (general, alice, bob),
+            (general, carol, bob),
+            (memes, bob, alice),
+            (memes, carol, dave),
+            (lonely, erin, dave),
+        ] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, 1.0)
+                .unwrap();
+        }
+
+        assert_eq!(
+            social.channel_interaction_graph(guild).unwrap(),
+            HashMap::from([((general, memes), 3), ((memes, lonely), 1)]),
+        );
+        assert!(social.channel_interaction_graph(Id::new(10)).is_none());
     }
-}
 
-impl Serialize for UserRelationshipGraphMap {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut map = serializer.serialize_map(Some(self.0.len()))?;
-        for (k, v) in &self.0 {
-            let k = format!("{}:{}", k.0, k.1);
-            map.serialize_entry(&k, v)?;
+    #[test]
+    fn test_node_metadata() {
+        let guild = Id::new(1);
+        let (alice, bob) = (Id::new(3), Id::new(4));
+
+        let mut social = SocialGraph::new(None);
+        social.record_node_interaction(guild, alice, bob, 20);
+        social.record_node_interaction(guild, bob, alice, 10);
+        social.record_node_interaction(guild, alice, bob, 30);
+
+        assert_eq!(
+            social.get_node_metadata(guild, alice),
+            Some(NodeMetadata {
+                first_seen_ms: 10,
+                last_seen_ms: 30,
+                total_interactions_as_source: 2,
+                total_interactions_as_target: 1,
+                is_opted_out: false,
+            })
+        );
+        assert!(social.get_node_metadata(guild, Id::new(5)).is_none());
+        assert!(social.get_node_metadata(Id::new(9), alice).is_none());
+    }
+
+    #[test]
+    fn test_get_graph_age() {
+        let guild = Id::new(1);
+        let (alice, bob) = (Id::new(3), Id::new(4));
+        let day = Duration::from_secs(86400);
+
+        let mut social = SocialGraph::new(None);
+        assert!(social.get_graph_age(guild).is_none());
+
+        let now = get_current_timestamp_ms();
+        social.record_node_interaction(guild, alice, bob, now - 3 * day.as_millis() as u64);
+        social.record_node_interaction(guild, bob, alice, now - day.as_millis() as u64);
+
+        let age = social.get_graph_age(guild).unwrap();
+        assert!(age >= 3 * day && age < 4 * day);
+    }
+
+    #[test]
+    fn test_merge_subgraphs() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
+        let (alice, bob, carol, dave) = (Id::new(3), Id::new(4), Id::new(5), Id::new(6));
+
+        let mut social = SocialGraph::new(None);
+        for &(source, target, weight) in &[
+            (alice, carol, 1.0),
+            (bob, carol, 2.0),
+            (bob, dave, 0.5),
+            (carol, dave, 0.25),
+        ] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, weight)
+                .unwrap();
         }
-        map.end()
+
+        let graph = social.build_guild_graph(guild);
+
+        let first = graph.ego_graph(alice, 1);
+        assert_eq!(first.len(), 1);
+
+        let second = graph.ego_graph(bob, 1);
+        assert_eq!(second.len(), 3);
+
+        let (merged, shared) = SocialGraph::merge_subgraphs(&first, &second);
+        assert_eq!(merged.len(), 4);
+        assert_eq!(
+            shared,
+            vec![SharedNode {
+                user_id: carol,
+                first_strength: 1.0,
+                second_strength: 2.25,
+            }]
+        );
     }
-}
 
-struct UserRelationshipGraphMapVisitor();
+    #[test]
+    fn test_cluster_coefficient() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
+        let (alice, bob, carol, dave) = (Id::new(3), Id::new(4), Id::new(5), Id::new(6));
 
-impl UserRelationshipGraphMapVisitor {
-    fn new() -> Self {
-        UserRelationshipGraphMapVisitor()
+        // A triangle between alice, bob and carol, with dave hanging off alice.
+        let mut social = SocialGraph::new(None);
+        for &(source, target) in &[(alice, bob), (bob, carol), (carol, alice), (alice, dave)] {
+            social
+                .apply_from_db_row(guild, channel, source, target, 2, 1.0)
+                .unwrap();
+        }
+
+        assert!((social.cluster_coefficient(guild, alice) - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(social.cluster_coefficient(guild, bob), 1.0);
+        assert_eq!(social.cluster_coefficient(guild, dave), 0.0);
+
+        let expected = (1.0 / 3.0 + 1.0 + 1.0 + 0.0) / 4.0;
+        assert!((social.average_cluster_coefficient(guild) - expected).abs() < 1e-9);
+        assert_eq!(social.average_cluster_coefficient(Id::new(9)), 0.0);
     }
-}
 
-impl<'de> Visitor<'de> for UserRelationshipGraphMapVisitor {
-    // The type that our Visitor is going to produce.
-    type Value = UserRelationshipGraphMap;
+    #[test]
+    fn test_unseen_guild_has_empty_graph() {
+        let guild = Id::new(1);
 
-    // Format a message stating what data this Visitor expects to receive.
-    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-        formatter.write_str("a very special map")
+        let mut social = SocialGraph::new(None);
+        assert!(social.build_guild_graph(guild).is_empty());
+        assert!(social
+            .get_graph_snapshot(guild)
+            .build_guild_graph()
+            .is_empty());
+
+        assert!(social.get_or_create_guild_graph(guild).is_empty());
+        assert!(social.get_all_graphs().contains_key(&guild));
     }
 
-    // Deserialize UserRelationshipGraphMapVisitor from an abstract "map" provided by the
-    // Deserializer. The MapAccess input is a callback provided by
-    // the Deserializer to let us see each entry in the map.
-    fn visit_map<M>(self, mut access: M) -> Result<Self::Value, M::Error>
-    where
-        M: MapAccess<'de>,
-    {
-        let mut map =
-            UserRelationshipGraphMap(HashMap::with_capacity(access.size_hint().unwrap_or(0)));
+    #[test]
+    fn test_graph_diameter() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
+        let users: Vec<_> = (3..9).map(Id::new).collect();
 
-        // While there are entries remaining in the input, add them into our map.
-        while let Some((key, value)) = access.next_entry::<&str, RelationshipStrength>()? {
-            let err = "expected exactly 2 numbers separated by :";
+        // A chain of six users is five hops end to end.
+        let mut social = SocialGraph::new(None);
+        for pair in users.windows(2) {
+            social
+                .apply_from_db_row(guild, channel, pair[0], pair[1], 2, 1.0)
+                .unwrap();
+        }
 
-            let mut iter = key.split(':');
-            let mut get_int = || {
-                iter.next()
-                    .ok_or_else(|| M::Error::custom(err))?
-                    .parse()
-                    .map_err(|e: ParseIntError| M::Error::custom(e.to_string()))
-            };
-            let k1 = get_int()?;
-            let k2 = get_int()?;
-            if iter.next().is_some() {
-                return Err(M::Error::custom(err));
-            }
+        assert_eq!(social.graph_diameter(guild), Some(5));
+
+        social
+            .apply_from_db_row(guild, channel, Id::new(20), Id::new(21), 2, 1.0)
+            .unwrap();
+        assert_eq!(social.graph_diameter(guild), None);
+        assert_eq!(social.graph_diameter(Id::new(9)), None);
+    }
+
+    #[test]
+    fn test_estimated_graph_diameter() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
 
-            map.insert((Id::new(k1), Id::new(k2)), value);
+        // A ring is too big for the exact search, but every user is the same distance from
+        // the furthest one, so the estimate is exact.
+        let count = EXACT_DIAMETER_LIMIT as u64 + 100;
+        let mut social = SocialGraph::new(None);
+        for i in 0..count {
+            social
+                .apply_from_db_row(
+                    guild,
+                    channel,
+                    Id::new(i + 1),
+                    Id::new((i + 1) % count + 1),
+                    2,
+                    1.0,
+                )
+                .unwrap();
         }
 
-        Ok(map)
+        assert_eq!(social.graph_diameter(guild), Some(count as usize / 2));
     }
 }
 
-// This is the trait that informs Serde how to deserialize MyMap.
-impl<'de> Deserialize<'de> for UserRelationshipGraphMap {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        // Instantiate our Visitor and ask the Deserializer to drive
-        // it over the input data, resulting in an instance of MyMap.
-        deserializer.deserialize_map(UserRelationshipGraphMapVisitor::new())
+#[cfg(test)]
+mod to_dot_tests {
+    use super::{
+        escape_dot_label, select_layout_engine, DotOptions, LabelMode, LayoutEngine,
+        UserRelationshipGraphMap, WeightNormalizer,
+    };
+    use crate::cache::CachedUser;
+    use crate::social::inference::RelationshipChangeReason;
+    use twilight_model::id::Id;
+
+    use std::collections::{HashMap, HashSet};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    #[test]
+    fn test_get_display_edges() {
+        let (alice, bob, carol) = (Id::new(1), Id::new(2), Id::new(3));
+
+        let mut graph = UserRelationshipGraphMap::new();
+        graph.add_weight(alice, bob, 2.0);
+        graph.add_weight(bob, alice, 0.5);
+        graph.add_weight(bob, carol, 0.5);
+
+        let directed = graph.get_display_edges(false);
+        assert_eq!(
+            directed,
+            HashMap::from([((alice, bob), 2.0), ((bob, alice), 0.5)]),
+        );
+
+        let undirected = graph.get_display_edges(true);
+        assert_eq!(undirected, HashMap::from([((alice, bob), 2.0)]));
     }
-}
 
-// TODO: Just keeping this note here, but it is a rather general thing - we've got a lot of HashMap
-//       objects around using Discord snowflakes as keys, which are out of user control and thus do
-//       not need secure, anti-DoS hashing. We could probably increase HashMap performance a tonne
-//       by avoiding the default secure hashing and just truncating them to fit / XOR for tuples.
-#[derive(Debug)]
-pub struct SocialGraph {
-    data_dir: Option<PathBuf>,
-    graph: HashMap<Id<GuildMarker>, HashMap<Id<ChannelMarker>, UserRelationshipGraphMap>>,
-    state: HashMap<(Id<GuildMarker>, Id<ChannelMarker>), InferenceState>,
-}
+    #[test]
+    fn test_inactive_users_are_grayed_out() {
+        let (alice, bob) = (Id::new(1), Id::new(2));
 
-impl SocialGraph {
-    pub fn new(data_dir: Option<PathBuf>) -> Self {
-        SocialGraph {
-            data_dir,
-            graph: HashMap::new(),
-            state: HashMap::new(),
-        }
+        let edges = HashMap::from([((alice, bob), 2.0)]);
+        let names = HashMap::from([
+            (alice, ("Alice".to_string(), None)),
+            (bob, ("Bob".to_string(), None)),
+        ]);
+        let options = DotOptions {
+            active_users: Some(HashSet::from([alice])),
+            ..DotOptions::default()
+        };
+
+        let dot = UserRelationshipGraphMap::format_dot(
+            edges,
+            &names,
+            &HashSet::new(),
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+        let node_line = |user_id| {
+            dot.lines()
+                .find(|line| line.starts_with(&format!("    {} [", user_id)))
+                .unwrap()
+        };
+
+        assert!(!node_line(alice).contains("#808080"));
+        assert!(node_line(bob).contains("color = \"#808080\""));
     }
 
-    /// Helper function to run inference with the right state.
-    pub fn infer(&mut self, interaction: &Interaction) -> Vec<RelationshipChange> {
-        let mut changes = Vec::new();
+    #[test]
+    fn test_bots_are_diamonds() {
+        let (alice, bot) = (Id::new(1), Id::new(2));
 
-        self.state
-            .entry((interaction.guild, interaction.channel))
-            .or_insert_with(InferenceState::new)
-            .infer(&mut changes, interaction);
+        let edges = HashMap::from([((alice, bot), 2.0)]);
+        let names = HashMap::from([
+            (alice, ("Alice".to_string(), None)),
+            (bot, ("Bot".to_string(), None)),
+        ]);
 
-        changes
+        let dot = UserRelationshipGraphMap::format_dot(
+            edges,
+            &names,
+            &HashSet::from([bot]),
+            None,
+            None,
+            &DotOptions::default(),
+        )
+        .unwrap();
+        let node_line = |user_id| {
+            dot.lines()
+                .find(|line| line.starts_with(&format!("    {} [", user_id)))
+                .unwrap()
+        };
+
+        assert!(!node_line(alice).contains("diamond"));
+        assert!(node_line(bot).contains("shape = \"diamond\""));
     }
 
-    /// Apply a set of relationship changes to the graph.
-    pub fn apply(&mut self, interaction: &Interaction, changes: &[RelationshipChange]) {
-        let data_dir = self.data_dir.clone();
-        let guild_id = interaction.guild;
-        let channel_id = interaction.channel;
+    #[test]
+    fn test_select_layout_engine() {
+        assert_eq!(select_layout_engine(2), LayoutEngine::Dot);
+        assert_eq!(select_layout_engine(20), LayoutEngine::Dot);
+        assert_eq!(select_layout_engine(21), LayoutEngine::Fdp);
+        assert_eq!(select_layout_engine(200), LayoutEngine::Fdp);
+        assert_eq!(select_layout_engine(201), LayoutEngine::Sfdp);
+    }
 
-        // Decay all of the guild channel's graphs a tiny bit.
-        if interaction.what == InteractionType::Message && !interaction.source_is_bot {
-            if let Some(guild_graphs) = self.graph.get_mut(&guild_id) {
-                for graph in guild_graphs.values_mut() {
-                    graph.decay(RELATIONSHIP_DECAY_GLOBAL);
-                }
-            }
-        }
+    #[test]
+    fn test_weight_normalizer() {
+        let normalizer = WeightNormalizer::new(vec![2.0, 50.0, 10.0]);
+        assert_eq!(normalizer.normalize(50.0), 1.0);
+        assert_eq!(normalizer.normalize(10.0), 0.2);
+        assert_eq!(normalizer.normalize(100.0), 1.0);
+        assert_eq!(normalizer.normalize(-1.0), 0.0);
 
-        let graph = self.get_graph(guild_id, channel_id);
+        let empty = WeightNormalizer::new(Vec::new());
+        assert_eq!(empty.normalize(5.0), 0.0);
+    }
 
-        graph.decay(RELATIONSHIP_DECAY);
+    #[test]
+    fn test_json_round_trip() {
+        let (alice, bob, carol) = (Id::new(1), Id::new(2), Id::new(3));
 
-        for change in changes {
-            let weight = graph.entry((change.source, change.target)).or_default();
+        let mut graph = UserRelationshipGraphMap::new();
+        graph.add_weight(alice, bob, 2.5);
+        graph.add_weight(bob, alice, 0.125);
+        graph.add_weight(carol, alice, 1.0);
 
-            *weight += change.reason.get_change_strength();
-        }
+        let users: Vec<_> = [(alice, "alice", 1), (bob, "bob", 42), (carol, "carol", 0)]
+            .iter()
+            .map(|&(id, name, discriminator)| CachedUser {
+                id,
+                name: name.to_string(),
+                discriminator,
+                avatar: None,
+                bot: false,
+            })
+            .collect();
 
-        if let Some(data_dir) = data_dir {
-            // TODO: Do we want to be writing this every update?
-            // TODO: Maybe we should use a proper database for the backing store? for all of this?
-            let data_path = Self::graph_data_file_name(data_dir, guild_id, channel_id);
-            if let Err(err) = graph.save_to_path(&data_path) {
-                error!(
-                    "failed to store on-disk data for ({}, {}): {}",
-                    interaction.guild, interaction.channel, err,
-                );
-            }
+        let reasons =
+            HashMap::from([((alice, bob), RelationshipChangeReason::MessageDirectMention)]);
+
+        let json = graph.format_json(users.clone(), &reasons).unwrap();
+        let (restored, restored_users) = UserRelationshipGraphMap::from_json(&json).unwrap();
+
+        assert_eq!(restored_users, users);
+        assert_eq!(restored.len(), graph.len());
+        for (pair, weight) in graph.iter() {
+            assert_eq!(restored[pair], *weight);
         }
+
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            json["nodes"][1],
+            serde_json::json!({ "id": "2", "name": "bob", "discriminator": 42 }),
+        );
+        assert_eq!(
+            json["edges"][0],
+            serde_json::json!({
+                "source": "1",
+                "target": "2",
+                "weight": 2.5,
+                "reason": "MessageDirectMention",
+            }),
+        );
+        assert_eq!(json["edges"][1]["reason"], serde_json::Value::Null);
     }
 
-    // TODO: Do we want to do this on the client-side instead? Probably.
-    pub fn build_guild_graph(&self, guild_id: Id<GuildMarker>) -> Option<UserRelationshipGraphMap> {
-        let guild = self.graph.get(&guild_id)?;
+    #[test]
+    fn test_format_d3force() {
+        let (alice, bob, carol, dave) = (Id::new(1), Id::new(2), Id::new(3), Id::new(4));
 
-        let mut guild_graph = UserRelationshipGraphMap::new();
-        for channel_graph in guild.values() {
-            for (&source_target, weight) in channel_graph.iter() {
-                let guild_weight = guild_graph.entry(source_target).or_default();
+        let edges = HashMap::from([
+            ((alice, bob), 2.0),
+            ((carol, dave), 0.5),
+            ((dave, alice), 1.0),
+        ]);
+        let names = HashMap::from([
+            (alice, ("Alice".to_string(), None)),
+            (bob, ("Bob".to_string(), None)),
+            (carol, ("Carol".to_string(), None)),
+        ]);
+        let communities = vec![vec![alice, bob], vec![carol]];
 
-                *guild_weight += weight;
-            }
-        }
+        let json = UserRelationshipGraphMap::format_d3force(
+            edges.clone(),
+            &names,
+            &communities,
+            LabelMode::DisplayName,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "nodes": [
+                    { "id": "1", "name": "Alice", "group": 0 },
+                    { "id": "2", "name": "Bob", "group": 0 },
+                ],
+                "links": [{ "source": "1", "target": "2", "value": 2.0 }],
+            }),
+        );
 
-        Some(guild_graph)
+        let names = HashMap::from([
+            (alice, ("1".to_string(), None)),
+            (bob, ("2".to_string(), None)),
+            (carol, ("3".to_string(), None)),
+            (dave, ("4".to_string(), None)),
+        ]);
+        let json = UserRelationshipGraphMap::format_d3force(
+            edges,
+            &names,
+            &communities,
+            LabelMode::Anonymous,
+        )
+        .unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            json["nodes"][2],
+            serde_json::json!({ "id": "3", "group": 1 })
+        );
+        assert_eq!(
+            json["nodes"][3],
+            serde_json::json!({ "id": "4", "group": 2 })
+        );
+        assert_eq!(json["links"].as_array().unwrap().len(), 3);
     }
 
-    // TODO: Temporary hack for debug command.
-    pub fn get_all_guild_ids(&self) -> Vec<Id<GuildMarker>> {
-        self.graph.keys().copied().collect()
+    #[test]
+    fn test_format_mermaid() {
+        let (alice, bob, carol) = (Id::new(1), Id::new(2), Id::new(3));
+
+        let edges = HashMap::from([
+            ((alice, bob), 2.0),
+            ((bob, carol), 0.25),
+            ((carol, alice), 1.0),
+        ]);
+        let names = HashMap::from([
+            (alice, ("Alice \"A\"".to_string(), None)),
+            (bob, ("Bob #1".to_string(), None)),
+        ]);
+
+        let mermaid =
+            UserRelationshipGraphMap::format_mermaid(edges, &names, &DotOptions::default())
+                .unwrap();
+        assert_eq!(
+            mermaid,
+            "graph LR\n    u1[\"Alice #quot;A#quot;\"]\n    u2[\"Bob #35;1\"]\n    u1 -->|2.0| u2",
+        );
+
+        let undirected = DotOptions {
+            undirected: true,
+            ..DotOptions::default()
+        };
+        let edges = HashMap::from([((alice, bob), 2.0)]);
+        let mermaid = UserRelationshipGraphMap::format_mermaid(edges, &names, &undirected).unwrap();
+        assert!(mermaid.ends_with("u1 ---|2.0| u2"));
+
+        let edges = HashMap::from([((bob, carol), 1.0)]);
+        assert!(UserRelationshipGraphMap::format_mermaid(edges, &names, &undirected).is_err());
     }
 
-    pub(crate) fn get_graph(
-        &mut self,
-        guild_id: Id<GuildMarker>,
-        channel_id: Id<ChannelMarker>,
-    ) -> &mut UserRelationshipGraphMap {
-        let data_dir = self.data_dir.clone();
+    #[test]
+    fn test_activity_scores() {
+        let (alice, bob, carol, dave) = (Id::new(1), Id::new(2), Id::new(3), Id::new(4));
 
-        self.graph
-            .entry(guild_id)
-            .or_insert_with(HashMap::new)
-            .entry(channel_id)
-            .or_insert_with(|| {
-                let existing_graph = data_dir.and_then(|data_dir| {
-                    let data_path = Self::graph_data_file_name(data_dir, guild_id, channel_id);
-                    match UserRelationshipGraphMap::new_from_path(&data_path) {
-                        Ok(graph) => Some(graph),
-                        Err(err) if err.kind() == IoErrorKind::NotFound => None,
-                        Err(err) => {
-                            error!(
-                                "failed to load on-disk data for ({}, {}): {}",
-                                guild_id, channel_id, err,
-                            );
+        let mut graph = UserRelationshipGraphMap::new();
+        graph.add_weight(alice, bob, 3.0);
+        graph.add_weight(alice, carol, 1.0);
 
-                            None
-                        }
-                    }
-                });
+        // Dave has only been seen in the events table, not the graph.
+        let counts = HashMap::from([(alice, (4, 2)), (bob, (3, 0)), (dave, (1, 1))]);
 
-                existing_graph.unwrap_or_else(UserRelationshipGraphMap::new)
-            })
+        let scores = graph.activity_scores(&counts);
+        let users: Vec<_> = scores.iter().map(|&(user_id, _)| user_id).collect();
+        assert_eq!(users, vec![alice, bob, carol, dave]);
+
+        // Alice is the strongest, most active, and knows everyone else in the graph.
+        assert!((scores[0].1 - 100.0).abs() < 1e-9);
+        assert!((scores[1].1 - (0.75 + 0.5 + 0.5) / 3.0 * 100.0).abs() < 1e-9);
+        assert!((scores[2].1 - (0.25 + 0.0 + 0.5) / 3.0 * 100.0).abs() < 1e-9);
+        assert!((scores[3].1 - (2.0 / 6.0) / 3.0 * 100.0).abs() < 1e-9);
+
+        assert!(UserRelationshipGraphMap::new()
+            .activity_scores(&HashMap::new())
+            .is_empty());
     }
 
-    pub fn remove_guild(&mut self, guild_id: Id<GuildMarker>) {
-        let channels = self.graph.remove(&guild_id);
+    #[test]
+    fn test_retain_min_degree() {
+        let (alice, bob, carol, dave) = (Id::new(1), Id::new(2), Id::new(3), Id::new(4));
 
-        if let Some(channels) = channels {
-            for &channel_id in channels.keys() {
-                self.state.remove(&(guild_id, channel_id));
-            }
-        }
+        let mut edges = HashMap::from([
+            ((alice, bob), 1.0),
+            ((bob, alice), 1.0),
+            ((alice, carol), 1.0),
+            ((bob, carol), 1.0),
+            ((carol, dave), 1.0),
+        ]);
+
+        let mut unfiltered = edges.clone();
+        UserRelationshipGraphMap::retain_min_degree(&mut unfiltered, 0);
+        assert_eq!(unfiltered, edges);
+
+        // Dave only knows Carol, and a pair in both directions still counts once.
+        UserRelationshipGraphMap::retain_min_degree(&mut edges, 2);
+        assert_eq!(
+            edges,
+            HashMap::from([
+                ((alice, bob), 1.0),
+                ((bob, alice), 1.0),
+                ((alice, carol), 1.0),
+                ((bob, carol), 1.0),
+            ]),
+        );
     }
 
-    pub fn remove_channel(&mut self, guild_id: Id<GuildMarker>, channel_id: Id<ChannelMarker>) {
-        self.state.remove(&(guild_id, channel_id));
+    #[test]
+    fn test_dot_is_deterministic() {
+        let users: Vec<_> = (1..=20).map(Id::new).collect();
 
-        if let Some(channels) = self.graph.get_mut(&guild_id) {
-            channels.remove(&channel_id);
-        }
+        let names_and_colors: HashMap<_, _> = users
+            .iter()
+            .map(|&user_id| (user_id, (user_id.to_string(), None)))
+            .collect();
+
+        // Build the same graph twice in different orders, so the maps are laid out differently.
+        let build = |reverse: bool| {
+            let mut pairs: Vec<_> = users
+                .iter()
+                .flat_map(|&a| users.iter().map(move |&b| (a, b)))
+                .filter(|(a, b)| a != b && (a.get() + b.get()) % 3 == 0)
+                .collect();
+            if reverse {
+                pairs.reverse();
+            }
+
+            let mut graph = UserRelationshipGraphMap::new();
+            for (a, b) in pairs {
+                graph.add_weight(a, b, (a.get() * b.get()) as f32);
+            }
+
+            UserRelationshipGraphMap::format_dot(
+                graph.get_display_edges(false),
+                &names_and_colors,
+                &HashSet::new(),
+                Some(users[0]),
+                None,
+                &DotOptions::default(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(build(false), build(true));
     }
 
-    fn graph_data_file_name(
-        data_dir: PathBuf,
-        guild_id: Id<GuildMarker>,
-        channel_id: Id<ChannelMarker>,
-    ) -> PathBuf {
-        let mut file_name = data_dir;
-        file_name.push(format!("{}_{}.json", guild_id, channel_id));
-        file_name
+    #[test]
+    #[ignore = "requires graphviz to be installed"]
+    fn test_dot_is_valid() {
+        let (alice, bob, carol) = (Id::new(1), Id::new(2), Id::new(3));
+
+        let mut graph = UserRelationshipGraphMap::new();
+        graph.add_weight(alice, bob, 2.0);
+        graph.add_weight(bob, carol, 3.0);
+        graph.add_weight(carol, alice, 1.5);
+
+        let names_and_colors = HashMap::from([
+            (
+                alice,
+                (String::from("alice \"the\" <great>"), Some(0xE91E63)),
+            ),
+            (bob, (String::from("{bob} | \\ 'b'"), None)),
+            (carol, (String::from("carol\nnewline"), None)),
+        ]);
+
+        let dot = UserRelationshipGraphMap::format_dot(
+            graph.get_display_edges(false),
+            &names_and_colors,
+            &HashSet::new(),
+            Some(alice),
+            Some(&escape_dot_label("Generated for \"test\" | <{guild}>")),
+            &DotOptions {
+                node_attributes: Some("shape=hexagon fontname=\"Arial\"".parse().unwrap()),
+                edge_attributes: Some("style=dashed".parse().unwrap()),
+                ..DotOptions::default()
+            },
+        )
+        .unwrap();
+
+        let mut graphviz = Command::new("dot")
+            .arg("-Tsvg")
+            .arg("-o")
+            .arg("/dev/null")
+            .stdin(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        graphviz
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(dot.as_bytes())
+            .unwrap();
+
+        assert!(graphviz.wait().unwrap().success(), "{}", dot);
     }
 }
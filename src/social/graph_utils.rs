@@ -0,0 +1,117 @@
+use twilight_model::id::marker::UserMarker;
+use twilight_model::id::Id;
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Each user's neighbours, with every edge present in both directions.
+pub type Neighbours = HashMap<Id<UserMarker>, HashSet<Id<UserMarker>>>;
+
+/// How many hops it takes to reach each user from `start`, including `start` itself at 0.
+/// Users further than `max_depth` hops away are left out.
+pub fn bfs_distances(
+    neighbours: &Neighbours,
+    start: Id<UserMarker>,
+    max_depth: Option<u32>,
+) -> HashMap<Id<UserMarker>, u32> {
+    let mut distances = HashMap::from([(start, 0)]);
+    let mut queue = VecDeque::from([start]);
+
+    while let Some(user_id) = queue.pop_front() {
+        let distance = distances[&user_id];
+        if matches!(max_depth, Some(max_depth) if distance >= max_depth) {
+            continue;
+        }
+
+        for &neighbour in neighbours.get(&user_id).into_iter().flatten() {
+            if let Entry::Vacant(entry) = distances.entry(neighbour) {
+                entry.insert(distance + 1);
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    distances
+}
+
+/// Everyone within `depth` hops of `start`, not including `start`, nearest first and then
+/// by user ID.
+pub fn bfs_neighbours(
+    neighbours: &Neighbours,
+    start: Id<UserMarker>,
+    depth: u32,
+) -> Vec<Id<UserMarker>> {
+    let mut users: Vec<_> = bfs_distances(neighbours, start, Some(depth))
+        .into_iter()
+        .filter(|&(user_id, _)| user_id != start)
+        .collect();
+
+    users.sort_by_key(|&(user_id, distance)| (distance, user_id));
+
+    users.into_iter().map(|(user_id, _)| user_id).collect()
+}
+
+/// How many triangles `node` is a corner of, i.e. pairs of its neighbours that are
+/// neighbours of each other.
+pub fn all_triangles(neighbours: &Neighbours, node: Id<UserMarker>) -> u32 {
+    let node_neighbours = match neighbours.get(&node) {
+        Some(node_neighbours) => node_neighbours,
+        None => return 0,
+    };
+
+    // Each triangle is counted once from each of its other two corners.
+    let count: usize = node_neighbours
+        .iter()
+        .filter_map(|neighbour| neighbours.get(neighbour))
+        .map(|their_neighbours| their_neighbours.intersection(node_neighbours).count())
+        .sum();
+
+    (count / 2) as u32
+}
+
+/// Everyone connected to `start` by any number of hops, including `start` itself.
+pub fn reachable_from(neighbours: &Neighbours, start: Id<UserMarker>) -> HashSet<Id<UserMarker>> {
+    bfs_distances(neighbours, start, None).into_keys().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{all_triangles, bfs_distances, bfs_neighbours, reachable_from, Neighbours};
+    use twilight_model::id::Id;
+
+    use std::collections::HashSet;
+
+    fn build_neighbours(edges: &[(u64, u64)]) -> Neighbours {
+        let mut neighbours = Neighbours::new();
+        for &(a, b) in edges {
+            neighbours.entry(Id::new(a)).or_default().insert(Id::new(b));
+            neighbours.entry(Id::new(b)).or_default().insert(Id::new(a));
+        }
+
+        neighbours
+    }
+
+    #[test]
+    fn test_traversals() {
+        // A triangle of 1, 2 and 3, with a tail of 3 - 4 - 5, and 6 - 7 off on their own.
+        let neighbours = build_neighbours(&[(1, 2), (2, 3), (3, 1), (3, 4), (4, 5), (6, 7)]);
+
+        assert_eq!(bfs_distances(&neighbours, Id::new(1), None)[&Id::new(5)], 3);
+
+        assert_eq!(
+            bfs_neighbours(&neighbours, Id::new(1), 2),
+            vec![Id::new(2), Id::new(3), Id::new(4)],
+        );
+        assert!(bfs_neighbours(&neighbours, Id::new(1), 0).is_empty());
+
+        assert_eq!(all_triangles(&neighbours, Id::new(3)), 1);
+        assert_eq!(all_triangles(&neighbours, Id::new(4)), 0);
+        assert_eq!(all_triangles(&neighbours, Id::new(9)), 0);
+
+        assert_eq!(
+            reachable_from(&neighbours, Id::new(6)),
+            HashSet::from([Id::new(6), Id::new(7)]),
+        );
+        assert_eq!(reachable_from(&neighbours, Id::new(2)).len(), 5);
+    }
+}
@@ -0,0 +1,303 @@
+use anyhow::Result;
+use sqlx::MySqlPool;
+use tracing::warn;
+use twilight_model::id::marker::{ChannelMarker, GuildMarker, UserMarker};
+use twilight_model::id::Id;
+
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+
+use crate::social::inference::RelationshipChangeReason;
+
+/// Every recorded `(timestamp, reason)` between two users in either direction, oldest first.
+pub async fn get_interaction_history(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    user_a: Id<UserMarker>,
+    user_b: Id<UserMarker>,
+) -> Result<Vec<(u64, u8)>> {
+    let history = sqlx::query_as(
+        "SELECT timestamp, reason FROM events WHERE guild = ? AND ((source = ? AND target = ?) OR (source = ? AND target = ?)) ORDER BY timestamp ASC",
+    )
+    .bind(guild_id.get())
+    .bind(user_a.get())
+    .bind(user_b.get())
+    .bind(user_b.get())
+    .bind(user_a.get())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(history)
+}
+
+/// Pairs of users whose first recorded interaction, in either direction, was after `since_ms`.
+pub async fn get_new_connections_since(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+) -> Result<Vec<(Id<UserMarker>, Id<UserMarker>)>> {
+    let pairs: Vec<(u64, u64)> = sqlx::query_as(
+        "SELECT LEAST(source, target) AS a, GREATEST(source, target) AS b FROM events WHERE guild = ? AND source != target GROUP BY a, b HAVING MIN(timestamp) > ?",
+    )
+    .bind(guild_id.get())
+    .bind(since_ms)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(pairs
+        .into_iter()
+        .map(|(a, b)| (Id::new(a), Id::new(b)))
+        .collect())
+}
+
+/// The channel with the most recorded interactions after `since_ms`, and how many it had.
+pub async fn get_most_active_channel(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+) -> Result<Option<(Id<ChannelMarker>, u64)>> {
+    let channel: Option<(u64, i64)> = sqlx::query_as(
+        "SELECT channel, COUNT(*) AS cnt FROM events WHERE guild = ? AND timestamp > ? GROUP BY channel ORDER BY cnt DESC LIMIT 1",
+    )
+    .bind(guild_id.get())
+    .bind(since_ms)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(channel.map(|(channel, count)| (Id::new(channel), count as u64)))
+}
+
+/// The channels with the most recorded interactions after `since_ms` and up to `until_ms`,
+/// with how many each had, busiest first.
+pub async fn get_top_channels(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+    until_ms: u64,
+    limit: u32,
+) -> Result<Vec<(Id<ChannelMarker>, u64)>> {
+    let channels: Vec<(u64, i64)> = sqlx::query_as(
+        "SELECT channel, COUNT(*) AS cnt FROM events WHERE guild = ? AND timestamp > ? AND timestamp <= ? GROUP BY channel ORDER BY cnt DESC, channel ASC LIMIT ?",
+    )
+    .bind(guild_id.get())
+    .bind(since_ms)
+    .bind(until_ms)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(channels
+        .into_iter()
+        .map(|(channel, count)| (Id::new(channel), count as u64))
+        .collect())
+}
+
+/// How many interactions were recorded in each of `channel_ids` after `since_ms` and up to
+/// `until_ms`. Channels without any are left out.
+pub async fn get_channel_interaction_counts(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    channel_ids: &[Id<ChannelMarker>],
+    since_ms: u64,
+    until_ms: u64,
+) -> Result<HashMap<Id<ChannelMarker>, u64>> {
+    if channel_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    // Only placeholders are added to the query, the IDs themselves are bound.
+    let query = format!(
+        "SELECT channel, COUNT(*) AS cnt FROM events WHERE guild = ? AND timestamp > ? AND timestamp <= ? AND channel IN ({}) GROUP BY channel",
+        vec!["?"; channel_ids.len()].join(", "),
+    );
+
+    let mut query = sqlx::query_as(&query)
+        .bind(guild_id.get())
+        .bind(since_ms)
+        .bind(until_ms);
+    for channel_id in channel_ids {
+        query = query.bind(channel_id.get());
+    }
+
+    let channels: Vec<(u64, i64)> = query.fetch_all(pool).await?;
+
+    Ok(channels
+        .into_iter()
+        .map(|(channel, count)| (Id::new(channel), count as u64))
+        .collect())
+}
+
+/// How many interactions each user sent or received, as `(total, since)` pairs where `since` is
+/// how many of them were after `since_ms`.
+pub async fn get_interaction_counts(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+) -> Result<HashMap<Id<UserMarker>, (u64, u64)>> {
+    let users: Vec<(u64, i64, i64)> = sqlx::query_as(
+        "SELECT user, COUNT(*), CAST(COALESCE(SUM(timestamp > ?), 0) AS SIGNED) FROM (SELECT source AS user, timestamp FROM events WHERE guild = ? AND source != target UNION ALL SELECT target AS user, timestamp FROM events WHERE guild = ? AND source != target) AS involved GROUP BY user",
+    )
+    .bind(since_ms)
+    .bind(guild_id.get())
+    .bind(guild_id.get())
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users
+        .into_iter()
+        .map(|(user, total, since)| (Id::new(user), (total as u64, since as u64)))
+        .collect())
+}
+
+/// The users with the most recorded interactions, with how many each had. Counts interactions
+/// the user was the target of, or the source of when `sent` is set.
+pub async fn get_interaction_leaderboard(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    sent: bool,
+    limit: u32,
+) -> Result<Vec<(Id<UserMarker>, u64)>> {
+    // The column name comes from a fixed choice, so this can't be used for injection.
+    let column = if sent { "source" } else { "target" };
+
+    let query = format!(
+        "SELECT {0} AS user, COUNT(*) AS cnt FROM events WHERE guild = ? AND source != target GROUP BY {0} ORDER BY cnt DESC LIMIT ?",
+        column,
+    );
+
+    let users: Vec<(u64, i64)> = sqlx::query_as(&query)
+        .bind(guild_id.get())
+        .bind(limit)
+        .fetch_all(pool)
+        .await?;
+
+    Ok(users
+        .into_iter()
+        .map(|(user, count)| (Id::new(user), count as u64))
+        .collect())
+}
+
+/// How many interactions the user sent or received in each week after `since_ms`, as
+/// `(week, count)` pairs oldest first. Weeks are numbered from the Unix epoch, and weeks
+/// without any interactions are left out.
+pub async fn get_weekly_activity(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    user_id: Id<UserMarker>,
+    since_ms: u64,
+) -> Result<Vec<(u64, u64)>> {
+    const WEEK_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+    let weeks: Vec<(u64, i64)> = sqlx::query_as(
+        "SELECT timestamp DIV ? AS week, COUNT(*) AS cnt FROM events WHERE guild = ? AND (source = ? OR target = ?) AND source != target AND timestamp > ? GROUP BY week ORDER BY week ASC",
+    )
+    .bind(WEEK_MS)
+    .bind(guild_id.get())
+    .bind(user_id.get())
+    .bind(user_id.get())
+    .bind(since_ms)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(weeks
+        .into_iter()
+        .map(|(week, count)| (week, count as u64))
+        .collect())
+}
+
+/// How many interactions have been recorded in the guild for each reason.
+pub async fn get_reason_distribution(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+) -> Result<HashMap<RelationshipChangeReason, u64>> {
+    let reasons: Vec<(u8, i64)> =
+        sqlx::query_as("SELECT reason, COUNT(*) FROM events WHERE guild = ? GROUP BY reason")
+            .bind(guild_id.get())
+            .fetch_all(pool)
+            .await?;
+
+    Ok(reasons
+        .into_iter()
+        .filter_map(
+            |(reason, count)| match RelationshipChangeReason::try_from(reason) {
+                Ok(reason) => Some((reason, count as u64)),
+                Err(error) => {
+                    warn!("skipping events in guild {}: {}", guild_id, error);
+                    None
+                }
+            },
+        )
+        .collect())
+}
+
+/// The reason recorded most often for each edge in the guild, ties going to the lowest reason.
+/// Removed reactions only ever weaken an edge, so they're never an edge's reason.
+pub async fn get_edge_reasons(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+) -> Result<HashMap<(Id<UserMarker>, Id<UserMarker>), RelationshipChangeReason>> {
+    let counts: Vec<(u64, u64, u8)> = sqlx::query_as(
+        "SELECT source, target, reason FROM events WHERE guild = ? AND reason != ? \
+         GROUP BY source, target, reason ORDER BY source, target, COUNT(*) DESC, reason",
+    )
+    .bind(guild_id.get())
+    .bind(RelationshipChangeReason::ReactionRemoved as u8)
+    .fetch_all(pool)
+    .await?;
+
+    let mut reasons = HashMap::new();
+    for (source, target, reason) in counts {
+        let reason = match RelationshipChangeReason::try_from(reason) {
+            Ok(reason) => reason,
+            Err(error) => {
+                warn!("skipping events in guild {}: {}", guild_id, error);
+                continue;
+            }
+        };
+
+        // Rows for each edge come most common first, so keep the first one.
+        reasons
+            .entry((Id::new(source), Id::new(target)))
+            .or_insert(reason);
+    }
+
+    Ok(reasons)
+}
+
+/// How many interactions were recorded in the guild after `since_ms` and up to `until_ms`.
+pub async fn get_guild_interaction_count(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+    until_ms: u64,
+) -> Result<u64> {
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM events WHERE guild = ? AND timestamp > ? AND timestamp <= ?",
+    )
+    .bind(guild_id.get())
+    .bind(since_ms)
+    .bind(until_ms)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(count as u64)
+}
+
+/// Every user who sent or received an interaction in the guild after `since_ms`.
+pub async fn get_active_users_since(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    since_ms: u64,
+) -> Result<HashSet<Id<UserMarker>>> {
+    let users: Vec<(u64,)> = sqlx::query_as(
+        "SELECT DISTINCT source FROM events WHERE guild = ? AND timestamp > ? UNION SELECT DISTINCT target FROM events WHERE guild = ? AND timestamp > ?",
+    )
+    .bind(guild_id.get())
+    .bind(since_ms)
+    .bind(guild_id.get())
+    .bind(since_ms)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users.into_iter().map(|(user,)| Id::new(user)).collect())
+}
@@ -1,34 +1,117 @@
 use anyhow::{Context as AnyhowContext, Result};
 use futures::future::join_all;
-use twilight_model::channel::Message;
+use serde::{Deserialize, Serialize};
+use twilight_model::channel::{Channel, Message};
 use twilight_model::gateway::payload::incoming::ReactionAdd;
+use twilight_model::guild::scheduled_event::{GuildScheduledEvent, Status as ScheduledEventStatus};
 use twilight_model::id::marker::{ChannelMarker, GuildMarker, UserMarker};
 use twilight_model::id::Id;
 
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
 use std::time::Instant;
 
-use crate::cache::{Cache, CachedMessage};
+use crate::cache::{hash_message_content, Cache, CachedChannel, CachedMessage, CachedUser};
+use crate::util::get_current_timestamp_ms;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
 pub enum InteractionType {
     Message,
     Reaction,
+    ReactionRemoval,
+    EventRSVP,
+    EventAttendance,
+    ForumPost,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct Interaction {
     pub what: InteractionType,
+    #[serde(skip)]
     pub when: Instant,
+    /// When the interaction happened on Discord, as a millisecond Unix timestamp.
+    pub timestamp: u64,
     pub guild: Id<GuildMarker>,
     pub channel: Id<ChannelMarker>,
     pub source: Id<UserMarker>,
     pub source_is_bot: bool,
     pub target: Option<Id<UserMarker>>,
     pub other_targets: Vec<Id<UserMarker>>,
+    /// For messages, a hash of what they said, see `hash_message_content`.
+    pub content_hash: Option<u64>,
 }
 
+/// Why `Interaction::validate` rejected an interaction.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum InteractionValidationError {
+    /// The interaction has no timestamp, so it would be recorded at the start of the epoch.
+    MissingTimestamp,
+    /// A kind of interaction that's always aimed at someone has no target.
+    MissingTarget(InteractionType),
+    /// Event attendance has nobody else to have attended with.
+    MissingOtherTargets(InteractionType),
+    /// A kind of interaction with a single target has other targets too.
+    UnexpectedOtherTargets(InteractionType),
+    /// The same user is targeted more than once, which would count them twice.
+    DuplicateTarget(Id<UserMarker>),
+}
+
+impl fmt::Display for InteractionValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingTimestamp => f.write_str("interaction has no timestamp"),
+            Self::MissingTarget(what) => write!(f, "{:?} interaction has no target", what),
+            Self::MissingOtherTargets(what) => {
+                write!(f, "{:?} interaction has no other targets", what)
+            }
+            Self::UnexpectedOtherTargets(what) => {
+                write!(f, "{:?} interaction shouldn't have other targets", what)
+            }
+            Self::DuplicateTarget(user_id) => write!(f, "{} is targeted more than once", user_id),
+        }
+    }
+}
+
+impl std::error::Error for InteractionValidationError {}
+
 impl Interaction {
+    /// Check that the interaction makes sense for its type, so bad ones don't reach the graph.
+    pub fn validate(&self) -> Result<(), InteractionValidationError> {
+        if self.timestamp == 0 {
+            return Err(InteractionValidationError::MissingTimestamp);
+        }
+
+        match self.what {
+            InteractionType::Reaction
+            | InteractionType::ReactionRemoval
+            | InteractionType::EventRSVP => {
+                if self.target.is_none() {
+                    return Err(InteractionValidationError::MissingTarget(self.what));
+                }
+
+                if !self.other_targets.is_empty() {
+                    return Err(InteractionValidationError::UnexpectedOtherTargets(
+                        self.what,
+                    ));
+                }
+            }
+            InteractionType::EventAttendance if self.other_targets.is_empty() => {
+                return Err(InteractionValidationError::MissingOtherTargets(self.what));
+            }
+            _ => (),
+        }
+
+        let mut targets = HashSet::new();
+        for &target in self.target.iter().chain(&self.other_targets) {
+            if !targets.insert(target) {
+                return Err(InteractionValidationError::DuplicateTarget(target));
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn new_from_message(
         message: &Message,
         referenced_message: Option<&CachedMessage>,
@@ -54,12 +137,14 @@ impl Interaction {
         Ok(Interaction {
             what: InteractionType::Message,
             when: Instant::now(),
+            timestamp: (message.timestamp.as_micros() / 1000) as u64,
             guild: guild_id,
             channel: message.channel_id,
             source: message.author.id,
             source_is_bot: message.author.bot,
             target: reply_to,
             other_targets: user_mentions,
+            content_hash: hash_message_content(&message.content),
         })
     }
 
@@ -80,12 +165,116 @@ impl Interaction {
         Ok(Interaction {
             what: InteractionType::Reaction,
             when: Instant::now(),
+            timestamp: get_current_timestamp_ms(),
             guild: guild_id,
             channel: reaction.channel_id,
             source: reaction.user_id,
             source_is_bot: user.bot,
             target: Some(target_message.author_id),
             other_targets: Vec::new(),
+            content_hash: None,
+        })
+    }
+
+    /// A user taking back their reaction to a message, undoing what it added. Removals don't
+    /// include member info, so we can't tell if the user is a bot.
+    pub fn new_from_reaction_removal(
+        guild_id: Option<Id<GuildMarker>>,
+        channel_id: Id<ChannelMarker>,
+        user_id: Id<UserMarker>,
+        target_message: &CachedMessage,
+    ) -> Result<Self> {
+        let guild_id = guild_id
+            .context("tried to create an interaction from a reaction not sent to a guild")?;
+
+        Ok(Interaction {
+            what: InteractionType::ReactionRemoval,
+            when: Instant::now(),
+            timestamp: get_current_timestamp_ms(),
+            guild: guild_id,
+            channel: channel_id,
+            source: user_id,
+            source_is_bot: false,
+            target: Some(target_message.author_id),
+            other_targets: Vec::new(),
+            content_hash: None,
+        })
+    }
+
+    /// A user subscribing to a scheduled event, targeting the event's creator.
+    pub fn new_from_scheduled_event_rsvp(
+        event: &ScheduledEventState,
+        user_id: Id<UserMarker>,
+    ) -> Option<Self> {
+        let creator = event.creator?;
+
+        if creator == user_id {
+            return None;
+        }
+
+        Some(Interaction {
+            what: InteractionType::EventRSVP,
+            when: Instant::now(),
+            timestamp: get_current_timestamp_ms(),
+            guild: event.guild,
+            channel: event.channel?,
+            source: user_id,
+            source_is_bot: false,
+            target: Some(creator),
+            other_targets: Vec::new(),
+            content_hash: None,
+        })
+    }
+
+    /// One interaction for everyone attending, from the first attendee to the rest. The
+    /// inference rule connects every pair of them, see `ScheduledEventRule`.
+    pub fn new_from_scheduled_event_start(event: &ScheduledEventState) -> Option<Self> {
+        let channel = event.channel?;
+
+        let mut attendees: Vec<_> = event.attendees.iter().copied().collect();
+        attendees.sort();
+
+        if attendees.len() < 2 {
+            return None;
+        }
+
+        Some(Interaction {
+            what: InteractionType::EventAttendance,
+            when: Instant::now(),
+            timestamp: get_current_timestamp_ms(),
+            guild: event.guild,
+            channel,
+            source: attendees[0],
+            source_is_bot: false,
+            target: None,
+            other_targets: attendees[1..].to_vec(),
+            content_hash: None,
+        })
+    }
+
+    /// A new post in a forum by the thread's owner, targeting the forum's moderators. Recorded
+    /// against the forum itself rather than the post's thread.
+    pub fn new_from_forum_post(
+        thread: &Channel,
+        owner: &CachedUser,
+        forum: &CachedChannel,
+        moderators: Vec<Id<UserMarker>>,
+    ) -> Result<Self> {
+        let guild_id = thread
+            .guild_id
+            .context("tried to create an interaction from a thread not in a guild")?;
+
+        Ok(Interaction {
+            what: InteractionType::ForumPost,
+            when: Instant::now(),
+            timestamp: get_current_timestamp_ms(),
+            guild: guild_id,
+            channel: forum.id,
+            source: owner.id,
+            source_is_bot: owner.bot,
+            target: None,
+            other_targets: moderators,
+            content_hash: None,
         })
     }
 
@@ -136,6 +325,22 @@ impl Interaction {
                 "{} reacted to a message by {} in {} @ \"{}\"",
                 source_name, target_names, channel_name, guild_name
             ),
+            InteractionType::ReactionRemoval => format!(
+                "{} removed a reaction from a message by {} in {} @ \"{}\"",
+                source_name, target_names, channel_name, guild_name
+            ),
+            InteractionType::EventRSVP => format!(
+                "{} subscribed to an event by {} in {} @ \"{}\"",
+                source_name, target_names, channel_name, guild_name
+            ),
+            InteractionType::EventAttendance => format!(
+                "{} attended an event with [{}] in {} @ \"{}\"",
+                source_name, target_names, channel_name, guild_name
+            ),
+            InteractionType::ForumPost => format!(
+                "{} posted in forum {} moderated by [{}] @ \"{}\"",
+                source_name, channel_name, target_names, guild_name
+            ),
         }
     }
 }
@@ -143,13 +348,17 @@ impl Interaction {
 pub type RelationshipStrength = f32;
 
 // These values are serialized and can't be modified.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum RelationshipChangeReason {
     Reaction = 1,
     MessageDirectMention = 2,
     MessageIndirectMention = 3,
     MessageAdjacency = 4,
     MessageBinarySequence = 5,
+    EventRsvp = 6,
+    EventAttendance = 7,
+    ForumPost = 8,
+    ReactionRemoved = 9,
 }
 
 // TODO: I think this needs to be based on the total number of nodes in the graph.
@@ -157,7 +366,41 @@ pub enum RelationshipChangeReason {
 pub const RELATIONSHIP_DECAY: RelationshipStrength = -0.02;
 pub const RELATIONSHIP_DECAY_GLOBAL: RelationshipStrength = -0.0002;
 
+impl TryFrom<u8> for RelationshipChangeReason {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Ok(match value {
+            1 => Self::Reaction,
+            2 => Self::MessageDirectMention,
+            3 => Self::MessageIndirectMention,
+            4 => Self::MessageAdjacency,
+            5 => Self::MessageBinarySequence,
+            6 => Self::EventRsvp,
+            7 => Self::EventAttendance,
+            8 => Self::ForumPost,
+            9 => Self::ReactionRemoved,
+            _ => anyhow::bail!("{} is not a known relationship change reason", value),
+        })
+    }
+}
+
 impl RelationshipChangeReason {
+    /// The kind of interaction that changes for this reason come from.
+    pub fn get_interaction_type(&self) -> InteractionType {
+        match self {
+            Self::Reaction => InteractionType::Reaction,
+            Self::MessageDirectMention
+            | Self::MessageIndirectMention
+            | Self::MessageAdjacency
+            | Self::MessageBinarySequence => InteractionType::Message,
+            Self::EventRsvp => InteractionType::EventRSVP,
+            Self::EventAttendance => InteractionType::EventAttendance,
+            Self::ForumPost => InteractionType::ForumPost,
+            Self::ReactionRemoved => InteractionType::ReactionRemoval,
+        }
+    }
+
     pub fn get_change_strength(&self) -> RelationshipStrength {
         match self {
             Self::Reaction => 0.1,
@@ -166,117 +409,450 @@ impl RelationshipChangeReason {
             Self::MessageAdjacency => 0.5,
             // TODO: Increase weight back to 1.0 once implementation is fixed.
             Self::MessageBinarySequence => 0.5,
+            Self::EventRsvp => 0.2,
+            Self::EventAttendance => 1.0,
+            Self::ForumPost => 0.2,
+            // Replaced with what the reaction actually added, see `match_reaction_weights`.
+            Self::ReactionRemoved => -0.1,
         }
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for RelationshipChangeReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Reaction => "reaction",
+            Self::MessageDirectMention => "reply",
+            Self::MessageIndirectMention => "mention",
+            Self::MessageAdjacency => "adjacent message",
+            Self::MessageBinarySequence => "conversation",
+            Self::EventRsvp => "event rsvp",
+            Self::EventAttendance => "event attendance",
+            Self::ForumPost => "forum post",
+            Self::ReactionRemoved => "reaction removed",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct RelationshipChange {
     pub source: Id<UserMarker>,
     pub target: Id<UserMarker>,
     pub reason: RelationshipChangeReason,
+    /// How much to strengthen the edge by, or weaken it when negative.
+    pub weight: RelationshipStrength,
+}
+
+impl RelationshipChange {
+    /// A change with the reason's usual strength.
+    pub fn new(
+        source: Id<UserMarker>,
+        target: Id<UserMarker>,
+        reason: RelationshipChangeReason,
+    ) -> Self {
+        RelationshipChange {
+            source,
+            target,
+            reason,
+            weight: reason.get_change_strength(),
+        }
+    }
+}
+
+impl fmt::Display for RelationshipChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} ({}, {:+})",
+            self.source, self.target, self.reason, self.weight
+        )
+    }
 }
 
 const MESSAGE_HISTORY_COUNT: usize = 5;
 
+/// How many of each user's recent message hashes to remember per channel.
+const CONTENT_HASH_HISTORY_COUNT: usize = 3;
+
+/// Multiplier for the weight of changes from a message repeating one of its author's recent ones.
+const SPAM_PENALTY: RelationshipStrength = 0.1;
+
 #[derive(Debug)]
 pub struct InferenceState {
     /// Recent messages to channel, used to infer temporal proximity.
     /// Limited to `MESSAGE_HISTORY_COUNT`, latest entries at the front.
     history: VecDeque<Interaction>,
+    /// Content hashes of each user's recent messages to the channel, to spot spam.
+    /// Limited to `CONTENT_HASH_HISTORY_COUNT`, latest entries at the front.
+    content_hashes: HashMap<Id<UserMarker>, VecDeque<u64>>,
 }
 
 impl InferenceState {
     pub fn new() -> Self {
         InferenceState {
             history: VecDeque::new(),
+            content_hashes: HashMap::new(),
         }
     }
 
-    // TODO: Re-write this as a set of inference engines.
-    pub fn infer(&mut self, changes: &mut Vec<RelationshipChange>, interaction: &Interaction) {
-        let source = interaction.source;
+    /// Remember a message's content hash, returning whether its author recently said the same.
+    fn is_repeated_message(&mut self, interaction: &Interaction) -> bool {
+        let content_hash = match interaction.content_hash {
+            Some(content_hash) => content_hash,
+            None => return false,
+        };
+
+        let recent = self.content_hashes.entry(interaction.source).or_default();
+        let repeated = recent.contains(&content_hash);
+
+        recent.push_front(content_hash);
+        recent.truncate(CONTENT_HASH_HISTORY_COUNT);
+
+        repeated
+    }
 
-        if let Some(target) = interaction.target {
-            changes.push(RelationshipChange {
-                source,
-                target,
-                reason: match interaction.what {
-                    InteractionType::Reaction => RelationshipChangeReason::Reaction,
-                    InteractionType::Message => RelationshipChangeReason::MessageDirectMention,
-                },
-            });
+    /// Run each rule over the interaction, then remember it for the rules that look at
+    /// recent messages.
+    pub fn infer<'a>(
+        &mut self,
+        changes: &mut Vec<RelationshipChange>,
+        interaction: &Interaction,
+        rules: impl IntoIterator<Item = &'a dyn InferenceRule>,
+    ) {
+        let first_change = changes.len();
+        for rule in rules {
+            changes.extend(rule.apply(interaction, &self.history));
         }
 
         if interaction.what != InteractionType::Message {
             return;
         }
 
-        for target in &interaction.other_targets {
-            changes.push(RelationshipChange {
-                source,
-                target: *target,
-                reason: RelationshipChangeReason::MessageIndirectMention,
-            });
-        }
-
-        if let Some(last) = self.history.front() {
-            // If the last message isn't from the same author, and was less than 2 minutes ago.
-            // TODO: There might be a reasonable dynamic option here where we set the later
-            //       threshold to 5x (or something) the time difference here. Discord seems
-            //       to move a bit quick for these limits. Often in #sourcemod there will be
-            //       a reply within 30 seconds or so to a question answered only a couple of
-            //       minutes after the previous message.
-            if last.source != source
-                && interaction.when.duration_since(last.when).as_secs() < (60 * 2)
-            {
-                // Find the message before that from a different author.
-                let previous = self
-                    .history
-                    .iter()
-                    .skip(1)
-                    .find(|i| i.source != last.source);
-
-                if let Some(previous) = previous {
-                    // If there was at least 10 minutes between the last message and the message
-                    // before that, and we're messaging within 2 minutes, we're probably replying.
-                    if last.when.duration_since(previous.when).as_secs() > (60 * 10) {
-                        changes.push(RelationshipChange {
-                            source,
-                            target: last.source,
-                            reason: RelationshipChangeReason::MessageAdjacency,
-                        });
-                    }
-                }
+        if self.is_repeated_message(interaction) {
+            for change in &mut changes[first_change..] {
+                change.weight *= SPAM_PENALTY;
             }
         }
 
         self.history.push_front(interaction.clone());
         self.history.truncate(MESSAGE_HISTORY_COUNT);
+    }
+}
 
-        // This is... gross.
-        let unique_sources = self
-            .history
+/// One way of inferring relationship changes from an interaction. New kinds of interaction can
+/// be supported by adding a rule to `default_rules`, rather than changing `InferenceState`.
+pub trait InferenceRule: fmt::Debug + Send + Sync {
+    /// What the rule is called when enabling or disabling it, in kebab-case.
+    fn name(&self) -> &'static str;
+
+    /// The changes implied by an interaction. `history` is the channel's recent messages before
+    /// this one, latest first, limited to `MESSAGE_HISTORY_COUNT`.
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        history: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange>;
+}
+
+/// Changes from the source to every target of the interaction, if it's the right type.
+fn changes_to_all_targets(
+    interaction: &Interaction,
+    what: InteractionType,
+    reason: RelationshipChangeReason,
+) -> Vec<RelationshipChange> {
+    if interaction.what != what {
+        return Vec::new();
+    }
+
+    (interaction.target.iter())
+        .chain(&interaction.other_targets)
+        .map(|&target| RelationshipChange::new(interaction.source, target, reason))
+        .collect()
+}
+
+/// Replying to a message, or mentioning someone at the very start of one.
+#[derive(Debug)]
+pub struct ReplyRule;
+
+impl InferenceRule for ReplyRule {
+    fn name(&self) -> &'static str {
+        "reply"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        _: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        match interaction.target {
+            Some(target) if interaction.what == InteractionType::Message => {
+                vec![RelationshipChange::new(
+                    interaction.source,
+                    target,
+                    RelationshipChangeReason::MessageDirectMention,
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Mentioning someone anywhere else in a message.
+#[derive(Debug)]
+pub struct MentionRule;
+
+impl InferenceRule for MentionRule {
+    fn name(&self) -> &'static str {
+        "mention"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        _: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        if interaction.what != InteractionType::Message {
+            return Vec::new();
+        }
+
+        interaction
+            .other_targets
             .iter()
-            .map(|i| i.source)
+            .map(|&target| {
+                RelationshipChange::new(
+                    interaction.source,
+                    target,
+                    RelationshipChangeReason::MessageIndirectMention,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Reacting to someone's message, and taking the reaction back again.
+#[derive(Debug)]
+pub struct ReactionRule;
+
+impl InferenceRule for ReactionRule {
+    fn name(&self) -> &'static str {
+        "reaction"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        _: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        let mut changes = changes_to_all_targets(
+            interaction,
+            InteractionType::Reaction,
+            RelationshipChangeReason::Reaction,
+        );
+        changes.extend(changes_to_all_targets(
+            interaction,
+            InteractionType::ReactionRemoval,
+            RelationshipChangeReason::ReactionRemoved,
+        ));
+
+        changes
+    }
+}
+
+/// Subscribing to someone's scheduled event, and attending one with other people.
+#[derive(Debug)]
+pub struct ScheduledEventRule;
+
+impl InferenceRule for ScheduledEventRule {
+    fn name(&self) -> &'static str {
+        "scheduled-event"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        _: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        if interaction.what != InteractionType::EventAttendance {
+            return changes_to_all_targets(
+                interaction,
+                InteractionType::EventRSVP,
+                RelationshipChangeReason::EventRsvp,
+            );
+        }
+
+        // Every attendee to each of the attendees after them, so each pair is only counted once.
+        let attendees: Vec<_> = std::iter::once(interaction.source)
+            .chain(interaction.other_targets.iter().copied())
+            .collect();
+
+        attendees
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &source)| {
+                attendees[(i + 1)..].iter().map(move |&target| {
+                    RelationshipChange::new(
+                        source,
+                        target,
+                        RelationshipChangeReason::EventAttendance,
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct ForumPostRule;
+
+impl InferenceRule for ForumPostRule {
+    fn name(&self) -> &'static str {
+        "forum-post"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        _: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        changes_to_all_targets(
+            interaction,
+            InteractionType::ForumPost,
+            RelationshipChangeReason::ForumPost,
+        )
+    }
+}
+
+/// Answering the last message soon after it broke a long silence in the channel.
+#[derive(Debug)]
+pub struct AdjacencyRule;
+
+impl InferenceRule for AdjacencyRule {
+    fn name(&self) -> &'static str {
+        "adjacency"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        history: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        let source = interaction.source;
+        let last = match history.front() {
+            Some(last) if interaction.what == InteractionType::Message => last,
+            _ => return Vec::new(),
+        };
+
+        // If the last message isn't from the same author, and was less than 2 minutes ago.
+        // TODO: There might be a reasonable dynamic option here where we set the later
+        //       threshold to 5x (or something) the time difference here. Discord seems
+        //       to move a bit quick for these limits. Often in #sourcemod there will be
+        //       a reply within 30 seconds or so to a question answered only a couple of
+        //       minutes after the previous message.
+        if last.source == source || interaction.when.duration_since(last.when).as_secs() >= (60 * 2)
+        {
+            return Vec::new();
+        }
+
+        // Find the message before that from a different author.
+        let previous = history.iter().skip(1).find(|i| i.source != last.source);
+
+        match previous {
+            // If there was at least 10 minutes between the last message and the message
+            // before that, and we're messaging within 2 minutes, we're probably replying.
+            Some(previous) if last.when.duration_since(previous.when).as_secs() > (60 * 10) => {
+                vec![RelationshipChange::new(
+                    source,
+                    last.source,
+                    RelationshipChangeReason::MessageAdjacency,
+                )]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Two people taking turns to be the only ones talking in a channel.
+#[derive(Debug)]
+pub struct ConversationRule;
+
+impl InferenceRule for ConversationRule {
+    fn name(&self) -> &'static str {
+        "conversation"
+    }
+
+    fn apply(
+        &self,
+        interaction: &Interaction,
+        history: &VecDeque<Interaction>,
+    ) -> Vec<RelationshipChange> {
+        if interaction.what != InteractionType::Message {
+            return Vec::new();
+        }
+
+        let source = interaction.source;
+
+        // The authors of this message and the ones before it that will still be remembered.
+        let unique_sources = std::iter::once(source)
+            .chain(
+                history
+                    .iter()
+                    .take(MESSAGE_HISTORY_COUNT - 1)
+                    .map(|i| i.source),
+            )
             .collect::<HashSet<Id<UserMarker>>>();
 
         // TODO: This is triggering far too often, especially after the bot first starts.
         //       The original waits for the history list to be full *and* clears it each
         //       time it triggers. It'd be good to do the same here, but I'm not sure if
-        //       we can without losing the single state storage. That said, if we split
-        //       these up like the original, MessageAdjacency will be a lot simpler.
-        if unique_sources.len() == 2 {
-            let mut unique_sources = unique_sources.iter();
-            let first = unique_sources.next().unwrap();
-            let second = unique_sources.next().unwrap();
-            let target = if source == *first { second } else { first };
-
-            changes.push(RelationshipChange {
-                source,
-                target: *target,
-                reason: RelationshipChangeReason::MessageBinarySequence,
-            });
+        //       we can without losing the single state storage.
+        if unique_sources.len() != 2 {
+            return Vec::new();
+        }
+
+        let target = unique_sources
+            .into_iter()
+            .find(|&user_id| user_id != source)
+            .unwrap();
+
+        vec![RelationshipChange::new(
+            source,
+            target,
+            RelationshipChangeReason::MessageBinarySequence,
+        )]
+    }
+}
+
+/// Every rule, in the order their changes are inferred.
+pub fn default_rules() -> Vec<Box<dyn InferenceRule>> {
+    vec![
+        Box::new(ReplyRule),
+        Box::new(MentionRule),
+        Box::new(ReactionRule),
+        Box::new(ScheduledEventRule),
+        Box::new(ForumPostRule),
+        Box::new(AdjacencyRule),
+        Box::new(ConversationRule),
+    ]
+}
+
+/// What we remember about a guild scheduled event between gateway events.
+#[derive(Debug)]
+pub struct ScheduledEventState {
+    pub guild: Id<GuildMarker>,
+    pub channel: Option<Id<ChannelMarker>>,
+    pub creator: Option<Id<UserMarker>>,
+    pub status: ScheduledEventStatus,
+    pub attendees: HashSet<Id<UserMarker>>,
+}
+
+impl From<&GuildScheduledEvent> for ScheduledEventState {
+    fn from(event: &GuildScheduledEvent) -> Self {
+        ScheduledEventState {
+            guild: event.guild_id,
+            channel: event.channel_id,
+            creator: event.creator_id,
+            status: event.status,
+            attendees: HashSet::new(),
         }
     }
 }
@@ -334,3 +910,227 @@ mod parse_direct_mention_tests {
         assert_eq!(mention, Some(Id::new(766407857851072512)));
     }
 }
+
+/// Shared by the tests of everything that handles interactions.
+#[cfg(test)]
+pub mod test_interactions {
+    use super::{Interaction, InteractionType};
+    use twilight_model::id::Id;
+
+    use std::time::Instant;
+
+    /// An interaction from user 3 in channel 2 of guild 1, happening now. Tests that need
+    /// anything else can override fields with struct update syntax.
+    pub fn interaction(
+        what: InteractionType,
+        target: Option<u64>,
+        other_targets: &[u64],
+    ) -> Interaction {
+        Interaction {
+            what,
+            when: Instant::now(),
+            timestamp: 1,
+            guild: Id::new(1),
+            channel: Id::new(2),
+            source: Id::new(3),
+            source_is_bot: false,
+            target: target.map(Id::new),
+            other_targets: other_targets.iter().copied().map(Id::new).collect(),
+            content_hash: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod inference_tests {
+    use super::test_interactions::interaction;
+    use super::{
+        default_rules, InferenceState, Interaction, InteractionType, InteractionValidationError,
+        RelationshipStrength, SPAM_PENALTY,
+    };
+    use crate::social::graph::SocialGraph;
+    use twilight_model::id::Id;
+
+    use std::time::Duration;
+
+    #[test]
+    fn test_reply_outweighs_mention() {
+        let guild = Id::new(1);
+        let (alice, bob) = (Id::new(3), Id::new(4));
+
+        let reply = interaction(InteractionType::Message, Some(4), &[]);
+        let mention = interaction(InteractionType::Message, None, &[4]);
+
+        // Each on its own graph, so neither is decayed or cooled down by the other.
+        let weight_after = |interaction: &Interaction| {
+            let mut social = SocialGraph::new(None);
+            let changes = social.infer(interaction);
+            social.apply(interaction, &changes);
+
+            social
+                .build_guild_graph(guild)
+                .get_pair_weight(alice, bob)
+                .unwrap_or_default()
+        };
+
+        let (reply_weight, mention_weight) = (weight_after(&reply), weight_after(&mention));
+
+        assert!(mention_weight > 0.0);
+        assert!(
+            reply_weight > mention_weight,
+            "{} <= {}",
+            reply_weight,
+            mention_weight
+        );
+    }
+
+    #[test]
+    fn test_reaction_removal_undoes_reaction() {
+        let guild = Id::new(1);
+        let (alice, bob) = (Id::new(3), Id::new(4));
+
+        let reaction = |what| interaction(what, Some(4), &[]);
+        let key = |message_id| (Id::new(message_id), alice, "👍".to_string());
+
+        let mut social = SocialGraph::new(None);
+        let mut process = |what, message_id| {
+            let interaction = reaction(what);
+            let mut changes = social.infer(&interaction);
+            social.apply_cooldown(&interaction, &mut changes, Duration::from_secs(60));
+            social.match_reaction_weights(&interaction, key(message_id), &mut changes);
+            social.apply(&interaction, &changes);
+
+            changes
+                .iter()
+                .map(|change| change.weight)
+                .sum::<RelationshipStrength>()
+        };
+
+        // The second reaction is cooling down, so its removal only takes back the little it added.
+        let first = process(InteractionType::Reaction, 10);
+        let second = process(InteractionType::Reaction, 11);
+        assert!(second < first);
+        assert_eq!(process(InteractionType::ReactionRemoval, 11), -second);
+
+        // Reactions we never saw added aren't taken back at all.
+        assert_eq!(process(InteractionType::ReactionRemoval, 12), 0.0);
+
+        assert_eq!(process(InteractionType::ReactionRemoval, 10), -first);
+        let graph = social.build_guild_graph(guild);
+        assert_eq!(graph.get_pair_weight(alice, bob), None);
+    }
+
+    #[test]
+    fn test_repeated_message_penalty() {
+        let message = |content_hash| Interaction {
+            content_hash,
+            ..interaction(InteractionType::Message, Some(4), &[])
+        };
+
+        let rules = default_rules();
+        let mut state = InferenceState::new();
+        let mut infer = |interaction: &Interaction| {
+            let mut changes = Vec::new();
+            state.infer(
+                &mut changes,
+                interaction,
+                rules.iter().map(|rule| rule.as_ref()),
+            );
+            changes[0].weight
+        };
+
+        let weight = infer(&message(Some(1)));
+        assert_eq!(infer(&message(Some(2))), weight);
+        assert_eq!(infer(&message(Some(1))), weight * SPAM_PENALTY);
+
+        // Messages without content are never treated as repeats.
+        assert_eq!(infer(&message(None)), weight);
+        assert_eq!(infer(&message(None)), weight);
+    }
+
+    #[test]
+    fn test_validate() {
+        for valid in &[
+            interaction(InteractionType::Message, None, &[]),
+            interaction(InteractionType::Message, Some(4), &[5, 6]),
+            interaction(InteractionType::Reaction, Some(4), &[]),
+            interaction(InteractionType::ReactionRemoval, Some(4), &[]),
+            interaction(InteractionType::EventRSVP, Some(4), &[]),
+            interaction(InteractionType::EventAttendance, None, &[4, 5]),
+            interaction(InteractionType::ForumPost, None, &[]),
+        ] {
+            assert_eq!(valid.validate(), Ok(()), "{:?}", valid);
+        }
+    }
+
+    #[test]
+    fn test_event_attendance_connects_every_pair() {
+        let attendance = interaction(InteractionType::EventAttendance, None, &[4, 5]);
+
+        let mut pairs: Vec<_> = SocialGraph::new(None)
+            .infer(&attendance)
+            .into_iter()
+            .map(|change| (change.source.get(), change.target.get()))
+            .collect();
+        pairs.sort();
+
+        assert_eq!(pairs, vec![(3, 4), (3, 5), (4, 5)]);
+    }
+
+    #[test]
+    fn test_validate_missing_timestamp() {
+        let mut invalid = interaction(InteractionType::Message, None, &[]);
+        invalid.timestamp = 0;
+
+        assert_eq!(
+            invalid.validate(),
+            Err(InteractionValidationError::MissingTimestamp),
+        );
+    }
+
+    #[test]
+    fn test_validate_missing_target() {
+        for &what in &[
+            InteractionType::Reaction,
+            InteractionType::ReactionRemoval,
+            InteractionType::EventRSVP,
+        ] {
+            assert_eq!(
+                interaction(what, None, &[]).validate(),
+                Err(InteractionValidationError::MissingTarget(what)),
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_missing_other_targets() {
+        assert_eq!(
+            interaction(InteractionType::EventAttendance, None, &[]).validate(),
+            Err(InteractionValidationError::MissingOtherTargets(
+                InteractionType::EventAttendance
+            )),
+        );
+    }
+
+    #[test]
+    fn test_validate_unexpected_other_targets() {
+        assert_eq!(
+            interaction(InteractionType::Reaction, Some(4), &[5]).validate(),
+            Err(InteractionValidationError::UnexpectedOtherTargets(
+                InteractionType::Reaction
+            )),
+        );
+    }
+
+    #[test]
+    fn test_validate_duplicate_target() {
+        assert_eq!(
+            interaction(InteractionType::Message, Some(4), &[5, 4]).validate(),
+            Err(InteractionValidationError::DuplicateTarget(Id::new(4))),
+        );
+        assert_eq!(
+            interaction(InteractionType::EventAttendance, None, &[5, 5]).validate(),
+            Err(InteractionValidationError::DuplicateTarget(Id::new(5))),
+        );
+    }
+}
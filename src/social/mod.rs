@@ -1,36 +1,400 @@
+pub mod analysis;
+pub mod dot;
 pub mod graph;
+pub mod graph_utils;
+pub mod history;
 pub mod inference;
+pub mod persistence;
+pub mod presence;
 
 use anyhow::Result;
-use tracing::{error, info};
+use futures::TryStreamExt;
+use sqlx::mysql::MySqlRow;
+use sqlx::{MySqlPool, Row};
+use tracing::{debug, error, info, warn, Span};
 use twilight_model::channel::message::{MessageReference, MessageType};
-use twilight_model::channel::ChannelType;
+use twilight_model::channel::{Channel, ChannelType};
 use twilight_model::gateway::event::Event;
 use twilight_model::gateway::event::Event::{
-    ChannelCreate, ChannelDelete, GuildCreate, GuildDelete, MessageCreate, ReactionAdd,
+    ChannelCreate, ChannelDelete, GuildCreate, GuildDelete, GuildScheduledEventCreate,
+    GuildScheduledEventDelete, GuildScheduledEventUpdate, GuildScheduledEventUserAdd,
+    GuildScheduledEventUserRemove, MessageCreate, PresenceUpdate, ReactionAdd, ReactionRemove,
+    ReactionRemoveEmoji, ThreadCreate,
 };
+use twilight_model::id::marker::{ChannelMarker, GuildMarker, UserMarker};
+use twilight_model::id::Id;
 
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+use crate::cache::reaction_key;
 use crate::context::Context;
-use crate::social::inference::Interaction;
+use crate::session::ProcessedInteraction;
+use crate::social::graph::{ChannelRelation, DecayConfig, ReactionKey, SocialGraph};
+use crate::social::inference::{Interaction, RelationshipChange, RelationshipChangeReason};
+use crate::social::persistence::{
+    delete_edges, load_guild_edges_from_database, save_guild_edges_to_database, upsert_edges,
+    GuildWriteLocks,
+};
+use crate::sync::CheckedMutex;
+use crate::util::format_duration;
+
+/// How many events to read before taking the graph lock to apply them.
+const REBUILD_BATCH_SIZE: usize = 10_000;
+
+struct RebuildRow {
+    timestamp: u64,
+    guild: Id<GuildMarker>,
+    channel: Id<ChannelMarker>,
+    source: Id<UserMarker>,
+    target: Id<UserMarker>,
+    reason: RelationshipChangeReason,
+    weight: f32,
+}
+
+impl RebuildRow {
+    /// Every change from an interaction is recorded with its timestamp, guild and channel.
+    fn get_interaction_key(&self) -> (u64, Id<GuildMarker>, Id<ChannelMarker>) {
+        (self.timestamp, self.guild, self.channel)
+    }
+}
+
+/// Group event rows back into the interactions that recorded them, so replaying them decays
+/// the graphs like applying them did. Interactions that didn't change anything weren't
+/// recorded, and bots' messages can't be told apart, so their decay can still differ.
+fn group_rebuild_rows(
+    rows: impl Iterator<Item = RebuildRow>,
+) -> Vec<(Interaction, Vec<RelationshipChange>)> {
+    let mut interactions: Vec<(Interaction, Vec<RelationshipChange>)> = Vec::new();
+
+    for row in rows {
+        let change = RelationshipChange {
+            source: row.source,
+            target: row.target,
+            reason: row.reason,
+            weight: row.weight,
+        };
+
+        match interactions.last_mut() {
+            Some((interaction, changes))
+                if (
+                    interaction.timestamp,
+                    interaction.guild,
+                    interaction.channel,
+                ) == row.get_interaction_key() =>
+            {
+                changes.push(change)
+            }
+            _ => interactions.push((
+                Interaction {
+                    what: row.reason.get_interaction_type(),
+                    when: Instant::now(),
+                    timestamp: row.timestamp,
+                    guild: row.guild,
+                    channel: row.channel,
+                    source: row.source,
+                    source_is_bot: false,
+                    target: None,
+                    other_targets: Vec::new(),
+                    content_hash: None,
+                },
+                vec![change],
+            )),
+        }
+    }
+
+    interactions
+}
+
+/// Apply and empty a batch of event rows while holding the lock once. The batch must end with
+/// the last row of an interaction.
+fn apply_rebuild_batch(social: &CheckedMutex<SocialGraph>, batch: &mut Vec<RebuildRow>) -> usize {
+    let count = batch.len();
+
+    let interactions = group_rebuild_rows(batch.drain(..));
+    social.lock().rebuild_from_changes(interactions.into_iter());
+
+    count
+}
+
+/// Read an event row, returning `None` if it should be skipped.
+fn parse_rebuild_row(row: &MySqlRow) -> Result<Option<RebuildRow>> {
+    let reason: u8 = row.try_get("reason")?;
+    let reason = match RelationshipChangeReason::try_from(reason) {
+        Ok(reason) => reason,
+        Err(error) => {
+            warn!("skipping event: {}", error);
+            return Ok(None);
+        }
+    };
+
+    let (source, target) = (
+        Id::new(row.try_get("source")?),
+        Id::new(row.try_get("target")?),
+    );
+
+    // Self-loops were recorded before inference filtered them out.
+    if source == target {
+        return Ok(None);
+    }
+
+    // Events recorded before weights were stored use the reason's usual strength.
+    let weight: Option<f32> = row.try_get("weight")?;
+    let weight = weight.unwrap_or_else(|| reason.get_change_strength());
+
+    Ok(Some(RebuildRow {
+        timestamp: row.try_get("timestamp")?,
+        guild: Id::new(row.try_get("guild")?),
+        channel: Id::new(row.try_get("channel")?),
+        source,
+        target,
+        reason,
+        weight,
+    }))
+}
+
+/// How many events to read between progress updates while rebuilding.
+const REBUILD_PROGRESS_INTERVAL: u64 = 10_000;
+
+fn log_rebuild_progress(processed: u64, total: u64, started: Instant) {
+    let fraction = (processed as f64 / total.max(1) as f64).min(1.0);
+
+    // Assume the remaining events take as long each as the ones so far.
+    let elapsed = started.elapsed();
+    let remaining = elapsed.mul_f64((1.0 - fraction) / fraction);
+
+    info!(
+        "rebuilding graphs: {}/{} events processed ({:.1}%), about {} left",
+        processed,
+        total,
+        fraction * 100.0,
+        format_duration(remaining),
+    );
+}
+
+/// Replay every recorded event into the in-memory graphs, through `SocialGraph::apply` like
+/// they were applied live.
+#[tracing::instrument(skip_all, fields(total_events))]
+pub async fn rebuild_graphs_from_database(
+    pool: &MySqlPool,
+    social: &CheckedMutex<SocialGraph>,
+) -> Result<()> {
+    let (total,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM events")
+        .fetch_one(pool)
+        .await?;
+    let total = total as u64;
+
+    Span::current().record("total_events", total);
+    info!("rebuilding graphs from {} events", total);
+
+    let started = Instant::now();
+    let mut processed = 0;
+
+    let mut rows = sqlx::query(
+        "SELECT timestamp, guild, channel, source, target, reason, weight FROM events ORDER BY timestamp ASC, guild ASC, channel ASC",
+    )
+    .fetch(pool);
+
+    let mut count = 0;
+    let mut batch = Vec::with_capacity(REBUILD_BATCH_SIZE);
+
+    while let Some(row) = rows.try_next().await? {
+        processed += 1;
+        if processed % REBUILD_PROGRESS_INTERVAL == 0 {
+            log_rebuild_progress(processed, total, started);
+        }
+
+        let row = match parse_rebuild_row(&row)? {
+            Some(row) => row,
+            None => continue,
+        };
+
+        // Only between interactions, so none of them are split across batches.
+        let ends_interaction = batch.last().is_none_or(|last: &RebuildRow| {
+            last.get_interaction_key() != row.get_interaction_key()
+        });
+        if batch.len() >= REBUILD_BATCH_SIZE && ends_interaction {
+            count += apply_rebuild_batch(social, &mut batch);
+        }
+
+        batch.push(row);
+    }
+
+    count += apply_rebuild_batch(social, &mut batch);
+
+    info!(
+        "rebuilt graphs from {} events in {}",
+        count,
+        format_duration(started.elapsed()),
+    );
+
+    Ok(())
+}
+
+/// Decay every guild's quiet edges, see `SocialGraph::decay`, and save the decayed graphs to
+/// the database. Returns how many edges were removed.
+pub async fn decay_graphs(
+    social: &CheckedMutex<SocialGraph>,
+    pool: Option<&MySqlPool>,
+    edge_writes: &GuildWriteLocks,
+    config: &DecayConfig,
+) -> Result<usize> {
+    let started = Instant::now();
+
+    let removed = {
+        let mut social = social.lock();
+        social.decay(config.factor, config.cutoff, config.min_weight)
+    };
+
+    if let Some(pool) = pool {
+        save_graphs_to_database(pool, social, edge_writes).await?;
+    }
+
+    info!(
+        "decayed graphs in {}, removing {} edges",
+        format_duration(started.elapsed()),
+        removed,
+    );
+
+    Ok(removed)
+}
+
+/// Delete every event recorded in a channel and its saved graph, returning how many events
+/// there were.
+pub async fn delete_channel_events(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    channel_id: Id<ChannelMarker>,
+) -> Result<u64> {
+    let mut transaction = pool.begin().await?;
+
+    let result = sqlx::query("DELETE FROM events WHERE guild = ? AND channel = ?")
+        .bind(guild_id.get())
+        .bind(channel_id.get())
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query("DELETE FROM graph_edges WHERE guild = ? AND channel = ?")
+        .bind(guild_id.get())
+        .bind(channel_id.get())
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+fn is_thread(kind: ChannelType) -> bool {
+    matches!(
+        kind,
+        ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::AnnouncementThread
+    )
+}
+
+/// Load a channel's graph into memory, and if it's a thread, note which channel it's in.
+fn track_thread(social: &mut SocialGraph, guild_id: Id<GuildMarker>, channel: &Channel) {
+    social.get_graph(guild_id, channel.id);
+
+    if let (true, Some(parent)) = (is_thread(channel.kind), channel.parent_id) {
+        social.set_channel_relation(guild_id, channel.id, ChannelRelation { parent });
+    }
+}
+
+/// Threads that we didn't see created, like ones unarchived since we joined the guild, are only
+/// found when someone talks in them.
+async fn find_thread_parent(
+    context: &Context,
+    guild_id: Id<GuildMarker>,
+    channel_id: Id<ChannelMarker>,
+) -> Result<()> {
+    let known = {
+        let social = context.social.lock();
+        social.get_channel_relation(guild_id, channel_id).is_some()
+    };
+
+    if known {
+        return Ok(());
+    }
+
+    let channel = context.cache.get_channel(channel_id).await?;
+    if let (true, Some(parent)) = (is_thread(channel.kind), channel.parent_id) {
+        let mut social = context.social.lock();
+        social.set_channel_relation(guild_id, channel_id, ChannelRelation { parent });
+    }
+
+    Ok(())
+}
 
 pub async fn handle_event(context: &Context, event: &Event) -> Result<()> {
     match event {
         GuildCreate(guild) => {
-            // Load any existing graphs into memory for the guild's channels.
-            let mut social = context.social.lock();
-            for channel in &guild.channels {
-                social.get_graph(guild.id, channel.id);
+            {
+                // Load any existing graphs into memory for the guild's channels.
+                let mut social = context.social.lock();
+                for channel in &guild.channels {
+                    social.get_graph(guild.id, channel.id);
+                }
+
+                for thread in &guild.threads {
+                    track_thread(&mut social, guild.id, thread);
+                }
+
+                if context.config.track_presence {
+                    for presence in &guild.presences {
+                        social.presence.update(presence);
+                    }
+                }
+            }
+
+            // Channels without a graph on disk or in memory might have one in the database.
+            if let Some(pool) = &context.pool {
+                let edges = load_guild_edges_from_database(pool, guild.id).await?;
+
+                // Channels that have since been deleted would only be dropped again.
+                let channel_ids: HashSet<_> = (guild.channels.iter())
+                    .chain(&guild.threads)
+                    .map(|channel| channel.id)
+                    .collect();
+                let edges = edges
+                    .into_iter()
+                    .filter(|edge| channel_ids.contains(&edge.channel));
+
+                let restored = {
+                    let mut social = context.social.lock();
+                    social.restore_empty_channel_graphs(guild.id, edges)
+                };
+
+                if restored > 0 {
+                    info!(
+                        "restored {} channel graphs for guild {} from the database",
+                        restored, guild.id
+                    );
+                }
             }
         }
-        GuildDelete(guild) => {
+        PresenceUpdate(presence) if context.config.track_presence => {
             let mut social = context.social.lock();
-            social.remove_guild(guild.id);
+            social.presence.update(&presence.0);
+        }
+        GuildDelete(guild) => {
+            {
+                let mut social = context.social.lock();
+                social.remove_guild(guild.id);
+            }
+
+            context.cache.remove_guild(guild.id);
         }
-        ChannelCreate(channel) if channel.kind == ChannelType::GuildText => {
+        ChannelCreate(channel)
+            if channel.kind == ChannelType::GuildText
+                || is_thread(channel.kind)
+                || (channel.kind == ChannelType::GuildForum && context.config.track_forums) =>
+        {
             if let Some(guild_id) = channel.guild_id {
                 // Load any existing graph into memory for the channel.
                 let mut social = context.social.lock();
-                social.get_graph(guild_id, channel.id);
+                track_thread(&mut social, guild_id, channel);
             }
         }
         ChannelDelete(channel) => {
@@ -53,6 +417,10 @@ pub async fn handle_event(context: &Context, event: &Event) -> Result<()> {
                 _ => None,
             };
 
+            if let Some(guild_id) = message.guild_id {
+                find_thread_parent(context, guild_id, message.channel_id).await?;
+            }
+
             let interaction = Interaction::new_from_message(message, referenced_message.as_ref())?;
             process_interaction(context, interaction).await;
         }
@@ -63,7 +431,154 @@ pub async fn handle_event(context: &Context, event: &Event) -> Result<()> {
                 .await?;
 
             let interaction = Interaction::new_from_reaction(reaction, &message)?;
-            process_interaction(context, interaction).await;
+            let key = (
+                reaction.message_id,
+                reaction.user_id,
+                reaction_key(&reaction.emoji),
+            );
+            process_reaction(context, interaction, Some(key)).await;
+        }
+        ReactionRemove(reaction) if reaction.user_id != context.user.id => {
+            let message = context
+                .cache
+                .get_message(reaction.channel_id, reaction.message_id)
+                .await?;
+
+            let interaction = Interaction::new_from_reaction_removal(
+                reaction.guild_id,
+                reaction.channel_id,
+                reaction.user_id,
+                &message,
+            )?;
+            let key = (
+                reaction.message_id,
+                reaction.user_id,
+                reaction_key(&reaction.emoji),
+            );
+            process_reaction(context, interaction, Some(key)).await;
+        }
+        // Discord doesn't say who had reacted, so this relies on the cache having seen them.
+        ReactionRemoveEmoji(removal) => {
+            let reactors = context
+                .cache
+                .take_reactors(removal.message_id, &removal.emoji)
+                .await;
+
+            if reactors.is_empty() {
+                return Ok(());
+            }
+
+            let message = context
+                .cache
+                .get_message(removal.channel_id, removal.message_id)
+                .await?;
+
+            for user_id in reactors {
+                if user_id == context.user.id {
+                    continue;
+                }
+
+                let interaction = Interaction::new_from_reaction_removal(
+                    Some(removal.guild_id),
+                    removal.channel_id,
+                    user_id,
+                    &message,
+                )?;
+                let key = (removal.message_id, user_id, reaction_key(&removal.emoji));
+                process_reaction(context, interaction, Some(key)).await;
+            }
+        }
+        ThreadCreate(thread) => {
+            if let Some(guild_id) = thread.guild_id {
+                let mut social = context.social.lock();
+                track_thread(&mut social, guild_id, thread);
+            }
+
+            if !context.config.track_forums || thread.newly_created != Some(true) {
+                return Ok(());
+            }
+
+            let parent = match thread.parent_id {
+                Some(parent_id) => context.cache.get_channel(parent_id).await?,
+                None => return Ok(()),
+            };
+
+            if parent.kind == ChannelType::GuildForum {
+                let (guild_id, owner_id) = match (thread.guild_id, thread.owner_id) {
+                    (Some(guild_id), Some(owner_id)) => (guild_id, owner_id),
+                    _ => anyhow::bail!("forum post is missing its guild or owner"),
+                };
+
+                let owner = context.cache.get_user(owner_id).await?;
+                let moderators = context.cache.get_channel_moderators(guild_id, &parent);
+
+                let interaction =
+                    Interaction::new_from_forum_post(thread, &owner, &parent, moderators)?;
+                process_interaction(context, interaction).await;
+            }
+        }
+        GuildScheduledEventCreate(event) if context.config.track_scheduled_events => {
+            let mut social = context.social.lock();
+            social.update_scheduled_event(event);
+        }
+        GuildScheduledEventUpdate(event) if context.config.track_scheduled_events => {
+            let interaction = {
+                let mut social = context.social.lock();
+                social.update_scheduled_event(event)
+            };
+
+            if let Some(interaction) = interaction {
+                process_interaction(context, interaction).await;
+            }
+        }
+        GuildScheduledEventDelete(event) if context.config.track_scheduled_events => {
+            let mut social = context.social.lock();
+            social.remove_scheduled_event(event.id);
+        }
+        GuildScheduledEventUserAdd(rsvp) if context.config.track_scheduled_events => {
+            let known = {
+                let mut social = context.social.lock();
+                social
+                    .get_scheduled_event_mut(rsvp.guild_scheduled_event_id)
+                    .is_some()
+            };
+
+            // The event may have been created before we started, so fetch it.
+            if !known {
+                let event = context
+                    .http
+                    .guild_scheduled_event(rsvp.guild_id, rsvp.guild_scheduled_event_id)
+                    .await?
+                    .model()
+                    .await?;
+
+                let mut social = context.social.lock();
+                social.update_scheduled_event(&event);
+            }
+
+            let interaction = {
+                let mut social = context.social.lock();
+                social
+                    .get_scheduled_event_mut(rsvp.guild_scheduled_event_id)
+                    .and_then(|event| {
+                        event.attendees.insert(rsvp.user_id);
+                        Interaction::new_from_scheduled_event_rsvp(event, rsvp.user_id)
+                    })
+            };
+
+            match interaction {
+                Some(interaction) => process_interaction(context, interaction).await,
+                None => debug!(
+                    "no interaction for rsvp to scheduled event {}",
+                    rsvp.guild_scheduled_event_id,
+                ),
+            }
+        }
+        GuildScheduledEventUserRemove(rsvp) if context.config.track_scheduled_events => {
+            let mut social = context.social.lock();
+            if let Some(event) = social.get_scheduled_event_mut(rsvp.guild_scheduled_event_id) {
+                event.attendees.remove(&rsvp.user_id);
+            }
         }
         _ => (),
     }
@@ -72,15 +587,38 @@ pub async fn handle_event(context: &Context, event: &Event) -> Result<()> {
 }
 
 async fn process_interaction(context: &Context, interaction: Interaction) {
+    process_reaction(context, interaction, None).await;
+}
+
+/// Like `process_interaction`, identifying the reaction for reaction interactions, so that
+/// removing one takes back what adding it did.
+async fn process_reaction(
+    context: &Context,
+    interaction: Interaction,
+    reaction: Option<ReactionKey>,
+) {
+    if let Err(error) = interaction.validate() {
+        warn!("dropping invalid interaction, {}: {:?}", error, interaction);
+        return;
+    }
+
     let interaction_string = interaction.to_string(&context.cache).await;
     info!("{}", interaction_string);
 
     let changes = {
         let mut social = context.social.lock();
 
-        let changes = social.infer(&interaction);
+        let mut changes = social.infer(&interaction);
+        social.apply_cooldown(
+            &interaction,
+            &mut changes,
+            Duration::from_secs(context.config.cooldown_seconds),
+        );
+        if let Some(reaction) = reaction {
+            social.match_reaction_weights(&interaction, reaction, &mut changes);
+        }
         for change in &changes {
-            info!("-> {:?}", change);
+            info!("-> {}", change);
         }
 
         social.apply(&interaction, &changes);
@@ -88,26 +626,324 @@ async fn process_interaction(context: &Context, interaction: Interaction) {
         changes
     };
 
+    context.session.set_last_interaction(ProcessedInteraction {
+        interaction: interaction.clone(),
+        changes: changes.clone(),
+    });
+
     if let Some(pool) = &context.pool {
-        for change in changes {
-            let timestamp = std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64;
-
-            let result = sqlx::query("INSERT INTO events (timestamp, guild, channel, source, target, reason) VALUES (?, ?, ?, ?, ?, ?)")
-            .bind(timestamp)
+        if let Err(error) = record_changes(context, pool, &interaction, &changes).await {
+            error!("query error: {}", error);
+        }
+    }
+}
+
+/// Insert the events for an interaction's changes, and save the edges they changed, together.
+/// Edges that were only decayed are left for `decay_graphs` and the save on shutdown.
+async fn record_changes(
+    context: &Context,
+    pool: &MySqlPool,
+    interaction: &Interaction,
+    changes: &[RelationshipChange],
+) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let write_lock = context.edge_writes.get(interaction.guild);
+    let _write_guard = write_lock.lock().await;
+
+    // Read under the write lock, so a slower write for an earlier interaction can't land after
+    // this one and put back older weights.
+    let (edges, removed) = {
+        let social = context.social.lock();
+        social.get_changed_edges(interaction, changes)
+    };
+
+    let mut transaction = pool.begin().await?;
+
+    for change in changes {
+        sqlx::query("INSERT INTO events (timestamp, guild, channel, source, target, reason, weight) VALUES (?, ?, ?, ?, ?, ?, ?)")
+            .bind(interaction.timestamp)
             .bind(interaction.guild.get())
             .bind(interaction.channel.get())
             .bind(change.source.get())
             .bind(change.target.get())
             .bind(change.reason as u8)
-            .execute(pool)
+            .bind(change.weight)
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    upsert_edges(&mut transaction, interaction.guild, &edges).await?;
+    delete_edges(
+        &mut transaction,
+        interaction.guild,
+        interaction.channel,
+        &removed,
+    )
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Write each guild's graphs to `graph_edges`, taking its write lock so this can't race the
+/// writes for interactions. This catches the decay of edges that haven't changed since they
+/// were last written.
+pub async fn save_graphs_to_database(
+    pool: &MySqlPool,
+    social: &CheckedMutex<SocialGraph>,
+    edge_writes: &GuildWriteLocks,
+) -> Result<()> {
+    let guild_ids: Vec<_> = social.lock().get_all_graphs().keys().copied().collect();
+
+    for guild_id in guild_ids {
+        let write_lock = edge_writes.get(guild_id);
+        let _write_guard = write_lock.lock().await;
+
+        // Copied out, so the lock isn't held while waiting on the database.
+        let channels = match social.lock().get_all_graphs().get(&guild_id) {
+            Some(channels) => channels.clone(),
+            None => continue,
+        };
+
+        save_guild_edges_to_database(pool, guild_id, &channels).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_rebuild_batch, handle_event, RebuildRow};
+    use crate::context::Context;
+    use crate::social::graph::{ChannelRelation, SocialGraph};
+    use crate::social::inference::test_interactions::interaction;
+    use crate::social::inference::{Interaction, InteractionType};
+    use crate::sync::CheckedMutex;
+    use serde_json::{json, Value};
+    use twilight_model::gateway::event::Event;
+    use twilight_model::gateway::payload::incoming::{
+        ChannelUpdate, GuildCreate, MessageCreate, ThreadCreate,
+    };
+    use twilight_model::id::marker::UserMarker;
+    use twilight_model::id::Id;
+
+    use std::collections::HashMap;
+
+    /// Feed an event through the cache and then the social graph, like the event loop does.
+    async fn dispatch(context: &Context, event: Event) {
+        context.cache.update(&event).await;
+        handle_event(context, &event).await.unwrap();
+    }
+
+    fn channel(id: u64, kind: u8, parent_id: Option<u64>) -> Value {
+        json!({
+            "id": id.to_string(),
+            "guild_id": "100",
+            "type": kind,
+            "name": format!("channel-{}", id),
+            "parent_id": parent_id.map(|id| id.to_string()),
+        })
+    }
+
+    fn user(id: u64) -> Value {
+        json!({
+            "id": id.to_string(),
+            "username": format!("user-{}", id),
+            "discriminator": "0001",
+            "avatar": null,
+            "public_flags": 0,
+        })
+    }
+
+    fn member() -> Value {
+        json!({
+            "deaf": false,
+            "mute": false,
+            "flags": 0,
+            "joined_at": "2026-01-01T00:00:00.000000+00:00",
+            "roles": [],
+        })
+    }
+
+    #[tokio::test]
+    async fn test_thread_messages_are_merged_into_parent() {
+        let context = Context::for_tests();
+        let guild_id = Id::new(100);
+        let (text_channel, active_thread, new_thread, unseen_thread) = (10, 11, 12, 13);
+
+        let guild = json!({
+            "id": "100",
+            "name": "Guild",
+            "owner_id": "2",
+            "afk_timeout": 300,
+            "default_message_notifications": 0,
+            "explicit_content_filter": 0,
+            "features": [],
+            "emojis": [],
+            "icon": null,
+            "large": false,
+            "mfa_level": 0,
+            "nsfw_level": 0,
+            "preferred_locale": "en-US",
+            "premium_progress_bar_enabled": false,
+            "roles": [],
+            "system_channel_flags": 0,
+            "verification_level": 0,
+            "channels": [channel(text_channel, 0, None)],
+            "threads": [channel(active_thread, 11, Some(text_channel))],
+        });
+        dispatch(
+            &context,
+            Event::GuildCreate(Box::new(GuildCreate(
+                serde_json::from_value(guild).unwrap(),
+            ))),
+        )
+        .await;
+
+        let thread = channel(new_thread, 11, Some(text_channel));
+        dispatch(
+            &context,
+            Event::ThreadCreate(Box::new(ThreadCreate(
+                serde_json::from_value(thread).unwrap(),
+            ))),
+        )
+        .await;
+
+        // Only the cache sees this one, like a thread that was archived when we joined.
+        let thread = channel(unseen_thread, 12, Some(text_channel));
+        context
+            .cache
+            .update(&Event::ChannelUpdate(Box::new(ChannelUpdate(
+                serde_json::from_value(thread).unwrap(),
+            ))))
             .await;
 
-            if let Err(error) = result {
-                error!("query error: {}", error);
+        let parent = ChannelRelation {
+            parent: Id::new(text_channel),
+        };
+        {
+            let social = context.social.lock();
+            for &thread in &[active_thread, new_thread] {
+                assert_eq!(
+                    social.get_channel_relation(guild_id, Id::new(thread)),
+                    Some(parent),
+                );
             }
+            assert_eq!(
+                social.get_channel_relation(guild_id, Id::new(unseen_thread)),
+                None,
+            );
+        }
+
+        let mut mention = user(3);
+        mention["member"] = member();
+        let message = json!({
+            "id": "1000",
+            "channel_id": unseen_thread.to_string(),
+            "guild_id": "100",
+            "author": user(2),
+            "member": member(),
+            "content": "hi <@3>",
+            "timestamp": "2026-01-01T00:00:00.000000+00:00",
+            "edited_timestamp": null,
+            "tts": false,
+            "mention_everyone": false,
+            "mentions": [mention],
+            "mention_roles": [],
+            "attachments": [],
+            "embeds": [],
+            "pinned": false,
+            "type": 0,
+        });
+        dispatch(
+            &context,
+            Event::MessageCreate(Box::new(MessageCreate(
+                serde_json::from_value(message).unwrap(),
+            ))),
+        )
+        .await;
+
+        let social = context.social.lock();
+        assert_eq!(
+            social.get_channel_relation(guild_id, Id::new(unseen_thread)),
+            Some(parent),
+        );
+
+        let separate = social.get_channel_graphs(guild_id, false);
+        assert!(separate[&Id::new(unseen_thread)]
+            .get_pair_weight(Id::new(2), Id::new(3))
+            .is_some());
+
+        let merged = social.get_channel_graphs(guild_id, true);
+        assert!(!merged.contains_key(&Id::new(unseen_thread)));
+        assert!(merged[&Id::new(text_channel)]
+            .get_pair_weight(Id::new(2), Id::new(3))
+            .is_some());
+    }
+
+    #[test]
+    fn test_rebuild_matches_live_graph() {
+        let guild = Id::new(1);
+        let (channel, other_channel) = (Id::new(2), Id::new(3));
+        let (alice, bob, carol) = (Id::new(4), Id::new(5), Id::new(6));
+
+        let event = |what, timestamp, channel, source, target: Id<UserMarker>| Interaction {
+            timestamp,
+            channel,
+            source,
+            ..interaction(what, Some(target.get()), &[])
+        };
+
+        let interactions = [
+            event(InteractionType::Message, 1, channel, alice, bob),
+            event(InteractionType::Message, 2, other_channel, bob, carol),
+            event(InteractionType::Reaction, 3, channel, carol, alice),
+            event(InteractionType::Message, 4, channel, bob, alice),
+            event(InteractionType::Message, 5, other_channel, carol, bob),
+        ];
+
+        // Apply them live, recording the rows `record_changes` would.
+        let mut live = SocialGraph::new(None);
+        let mut rows = Vec::new();
+        for interaction in &interactions {
+            let changes = live.infer(interaction);
+            live.apply(interaction, &changes);
+
+            rows.extend(changes.iter().map(|change| RebuildRow {
+                timestamp: interaction.timestamp,
+                guild: interaction.guild,
+                channel: interaction.channel,
+                source: change.source,
+                target: change.target,
+                reason: change.reason,
+                weight: change.weight,
+            }));
         }
+
+        let replayed = CheckedMutex::new(SocialGraph::new(None));
+        let row_count = rows.len();
+        assert_eq!(apply_rebuild_batch(&replayed, &mut rows), row_count);
+
+        let weights = |social: &SocialGraph| -> HashMap<_, HashMap<_, _>> {
+            social
+                .get_channel_graphs(guild, false)
+                .into_iter()
+                .map(|(channel, graph)| {
+                    let edges = graph
+                        .iter()
+                        .map(|(&pair, &weight)| (pair, weight))
+                        .collect();
+                    (channel, edges)
+                })
+                .collect()
+        };
+
+        let live_weights = weights(&live);
+        assert_eq!(live_weights.len(), 2);
+        assert_eq!(weights(&replayed.lock()), live_weights);
     }
 }
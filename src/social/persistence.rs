@@ -0,0 +1,208 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use sqlx::{MySql, MySqlPool, QueryBuilder, Row, Transaction};
+use tokio::sync::Mutex as AsyncMutex;
+use twilight_model::id::marker::{ChannelMarker, GuildMarker};
+use twilight_model::id::Id;
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::social::graph::{GuildGraphs, StoredEdge, UserPair, UserRelationshipGraphMap};
+use crate::util::get_current_timestamp_ms;
+
+/// How many edges to write to `graph_edges` in each statement.
+const EDGE_INSERT_BATCH_SIZE: usize = 1000;
+
+/// Write every guild's graphs to a single JSON file, so a restart can skip replaying the
+/// database. The file is written next to `path` first and then moved into place, so a crash
+/// part way through can't leave a truncated file behind.
+pub fn save_graph_to_file(path: &Path, graphs: &GuildGraphs) -> Result<()> {
+    let temporary_path = path.with_extension("tmp");
+
+    let mut writer = BufWriter::new(File::create(&temporary_path)?);
+    serde_json::to_writer(&mut writer, graphs)?;
+    writer.flush()?;
+    drop(writer);
+
+    fs::rename(&temporary_path, path)?;
+
+    Ok(())
+}
+
+/// Read graphs written by `save_graph_to_file`.
+pub fn load_graph_from_file(path: &Path) -> Result<GuildGraphs> {
+    // The graph deserializer borrows its keys, so this can't stream from a reader.
+    let contents = fs::read_to_string(path)?;
+
+    let graphs = serde_json::from_str(&contents)
+        .with_context(|| format!("{} is not a valid graph file", path.display()))?;
+
+    Ok(graphs)
+}
+
+/// One lock per guild, held while its rows in `graph_edges` are written. Writers read the graphs
+/// they save after taking the lock, so the last write to commit always has the newest weights,
+/// whatever order the tasks that started them ran in.
+#[derive(Debug, Default)]
+pub struct GuildWriteLocks(Mutex<HashMap<Id<GuildMarker>, Arc<AsyncMutex<()>>>>);
+
+impl GuildWriteLocks {
+    pub fn get(&self, guild_id: Id<GuildMarker>) -> Arc<AsyncMutex<()>> {
+        self.0.lock().entry(guild_id).or_default().clone()
+    }
+}
+
+fn to_stored_edges(
+    channels: &HashMap<Id<ChannelMarker>, UserRelationshipGraphMap>,
+) -> Vec<StoredEdge> {
+    // Edges loaded from the data dir don't know when they last changed, so count it as now.
+    let now = get_current_timestamp_ms();
+
+    channels
+        .iter()
+        .flat_map(|(&channel_id, graph)| {
+            graph
+                .iter()
+                .map(move |(&(source, target), &weight)| StoredEdge {
+                    channel: channel_id,
+                    source,
+                    target,
+                    weight,
+                    last_updated: graph.get_last_updated(source, target).unwrap_or(now),
+                })
+        })
+        .collect()
+}
+
+/// Write a guild's edges, replacing any rows they already have, as part of a larger transaction.
+pub async fn upsert_edges(
+    transaction: &mut Transaction<'_, MySql>,
+    guild_id: Id<GuildMarker>,
+    edges: &[StoredEdge],
+) -> Result<()> {
+    for batch in edges.chunks(EDGE_INSERT_BATCH_SIZE) {
+        let mut query = QueryBuilder::<MySql>::new(
+            "INSERT INTO graph_edges (guild, channel, source, target, weight, last_updated) ",
+        );
+
+        query.push_values(batch, |mut row, edge| {
+            row.push_bind(guild_id.get())
+                .push_bind(edge.channel.get())
+                .push_bind(edge.source.get())
+                .push_bind(edge.target.get())
+                .push_bind(edge.weight)
+                .push_bind(edge.last_updated);
+        });
+        query.push(
+            " ON DUPLICATE KEY UPDATE weight = VALUES(weight), last_updated = VALUES(last_updated)",
+        );
+
+        query.build().execute(&mut *transaction).await?;
+    }
+
+    Ok(())
+}
+
+/// Delete the rows for edges that no longer exist in a channel, as part of a larger transaction.
+pub async fn delete_edges(
+    transaction: &mut Transaction<'_, MySql>,
+    guild_id: Id<GuildMarker>,
+    channel_id: Id<ChannelMarker>,
+    pairs: &[UserPair],
+) -> Result<()> {
+    for &(source, target) in pairs {
+        sqlx::query(
+            "DELETE FROM graph_edges WHERE guild = ? AND channel = ? AND source = ? AND target = ?",
+        )
+        .bind(guild_id.get())
+        .bind(channel_id.get())
+        .bind(source.get())
+        .bind(target.get())
+        .execute(&mut *transaction)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Replace a guild's rows in `graph_edges` with its current channel graphs, in one transaction.
+pub async fn save_guild_edges_to_database(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+    channels: &HashMap<Id<ChannelMarker>, UserRelationshipGraphMap>,
+) -> Result<()> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query("DELETE FROM graph_edges WHERE guild = ?")
+        .bind(guild_id.get())
+        .execute(&mut transaction)
+        .await?;
+
+    upsert_edges(&mut transaction, guild_id, &to_stored_edges(channels)).await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Read a guild's saved edges.
+pub async fn load_guild_edges_from_database(
+    pool: &MySqlPool,
+    guild_id: Id<GuildMarker>,
+) -> Result<Vec<StoredEdge>> {
+    let rows = sqlx::query(
+        "SELECT channel, source, target, weight, last_updated FROM graph_edges WHERE guild = ?",
+    )
+    .bind(guild_id.get())
+    .fetch_all(pool)
+    .await?;
+
+    rows.iter()
+        .map(|row| {
+            Ok(StoredEdge {
+                channel: Id::new(row.try_get("channel")?),
+                source: Id::new(row.try_get("source")?),
+                target: Id::new(row.try_get("target")?),
+                weight: row.try_get("weight")?,
+                last_updated: row.try_get("last_updated")?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_graph_from_file, save_graph_to_file};
+    use crate::social::graph::SocialGraph;
+    use twilight_model::id::Id;
+
+    use std::env;
+
+    #[test]
+    fn test_save_and_load_graph_file() {
+        let (guild, channel) = (Id::new(1), Id::new(2));
+        let (alice, bob) = (Id::new(3), Id::new(4));
+
+        let mut social = SocialGraph::new(None);
+        social
+            .apply_from_db_row(guild, channel, alice, bob, 2, 1.5)
+            .unwrap();
+
+        let path = env::temp_dir().join(format!("discograph-test-{}.json", std::process::id()));
+        save_graph_to_file(&path, social.get_all_graphs()).unwrap();
+
+        let graphs = load_graph_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut restored = SocialGraph::new(None);
+        restored.restore_all_graphs(graphs);
+
+        let graph = restored.build_guild_graph(guild);
+        assert_eq!(graph.len(), 1);
+        assert_eq!(graph[&(alice, bob)], 1.5);
+    }
+}
@@ -0,0 +1,107 @@
+use twilight_model::gateway::presence::{Presence, Status as PresenceStatus};
+use twilight_model::id::marker::{GuildMarker, UserMarker};
+use twilight_model::id::Id;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::graph::UserRelationshipGraphMap;
+
+/// Anyone online for less than this is treated as if they'd been online this long,
+/// to stop a handful of interactions from a rarely-seen user dominating the graph.
+const MINIMUM_ONLINE_TIME: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+struct PresenceState {
+    status: PresenceStatus,
+    since: Instant,
+    /// Time spent online before `since`.
+    online: Duration,
+}
+
+impl PresenceState {
+    fn is_online(&self) -> bool {
+        !matches!(
+            self.status,
+            PresenceStatus::Offline | PresenceStatus::Invisible
+        )
+    }
+
+    fn online_time(&self, now: Instant) -> Duration {
+        if self.is_online() {
+            self.online + now.duration_since(self.since)
+        } else {
+            self.online
+        }
+    }
+}
+
+/// Approximate online time for each member, accumulated since we started watching presences.
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+    users: HashMap<(Id<GuildMarker>, Id<UserMarker>), PresenceState>,
+}
+
+impl PresenceTracker {
+    pub fn update(&mut self, presence: &Presence) {
+        let now = Instant::now();
+
+        let state = self
+            .users
+            .entry((presence.guild_id, presence.user.id()))
+            .or_insert_with(|| PresenceState {
+                status: PresenceStatus::Offline,
+                since: now,
+                online: Duration::ZERO,
+            });
+
+        state.online = state.online_time(now);
+        state.status = presence.status;
+        state.since = now;
+    }
+
+    pub fn remove_guild(&mut self, guild_id: Id<GuildMarker>) {
+        self.users.retain(|&(guild, _), _| guild != guild_id);
+    }
+
+    fn get_online_time(
+        &self,
+        guild_id: Id<GuildMarker>,
+        user_id: Id<UserMarker>,
+        now: Instant,
+    ) -> Option<Duration> {
+        self.users
+            .get(&(guild_id, user_id))
+            .map(|state| state.online_time(now).max(MINIMUM_ONLINE_TIME))
+    }
+
+    /// Scale each edge by how much time its users spent online, relative to the average, so
+    /// people who are around more often don't look closer just for having more opportunities.
+    /// Users we have no presence data for are left at the average.
+    pub fn normalize(&self, guild_id: Id<GuildMarker>, graph: &mut UserRelationshipGraphMap) {
+        let now = Instant::now();
+
+        let hours: HashMap<_, _> = graph
+            .keys()
+            .flat_map(|&(source, target)| [source, target])
+            .filter_map(|user_id| {
+                let online = self.get_online_time(guild_id, user_id, now)?;
+
+                Some((user_id, online.as_secs_f32() / 3600.0))
+            })
+            .collect();
+
+        if hours.is_empty() {
+            return;
+        }
+
+        let average_hours = hours.values().sum::<f32>() / hours.len() as f32;
+
+        graph.scale_weights(|source, target| {
+            let source_hours = hours.get(&source).copied().unwrap_or(average_hours);
+            let target_hours = hours.get(&target).copied().unwrap_or(average_hours);
+
+            average_hours / (source_hours * target_hours).sqrt()
+        });
+    }
+}
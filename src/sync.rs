@@ -0,0 +1,121 @@
+#[cfg(debug_assertions)]
+pub use self::debug::DebugMutex as CheckedMutex;
+#[cfg(not(debug_assertions))]
+pub use parking_lot::Mutex as CheckedMutex;
+
+#[cfg(debug_assertions)]
+mod debug {
+    use parking_lot::{Mutex, MutexGuard};
+    use tracing::warn;
+
+    use std::ops::{Deref, DerefMut};
+    use std::panic::Location;
+    use std::thread::{self, ThreadId};
+    use std::time::{Duration, Instant};
+
+    const SLOW_LOCK_THRESHOLD: Duration = Duration::from_millis(100);
+
+    #[derive(Debug, Clone, Copy)]
+    struct Holder {
+        thread: ThreadId,
+        location: &'static Location<'static>,
+    }
+
+    /// A `Mutex` that panics instead of deadlocking when a thread tries to lock it twice,
+    /// and warns when it's held for too long. Only used in debug builds.
+    #[derive(Debug)]
+    pub struct DebugMutex<T> {
+        inner: Mutex<T>,
+        holder: Mutex<Option<Holder>>,
+    }
+
+    impl<T> DebugMutex<T> {
+        pub fn new(value: T) -> Self {
+            DebugMutex {
+                inner: Mutex::new(value),
+                holder: Mutex::new(None),
+            }
+        }
+
+        #[track_caller]
+        pub fn lock(&self) -> DebugMutexGuard<'_, T> {
+            let location = Location::caller();
+            let thread = thread::current().id();
+
+            if let Some(holder) = *self.holder.lock() {
+                if holder.thread == thread {
+                    panic!(
+                        "mutex locked at {} is already held by this thread, locked at {}",
+                        location, holder.location,
+                    );
+                }
+            }
+
+            let guard = self.inner.lock();
+            *self.holder.lock() = Some(Holder { thread, location });
+
+            DebugMutexGuard {
+                guard,
+                holder: &self.holder,
+                location,
+                acquired: Instant::now(),
+            }
+        }
+    }
+
+    pub struct DebugMutexGuard<'a, T> {
+        guard: MutexGuard<'a, T>,
+        holder: &'a Mutex<Option<Holder>>,
+        location: &'static Location<'static>,
+        acquired: Instant,
+    }
+
+    impl<T> Drop for DebugMutexGuard<'_, T> {
+        fn drop(&mut self) {
+            *self.holder.lock() = None;
+
+            let held = self.acquired.elapsed();
+            if held > SLOW_LOCK_THRESHOLD {
+                warn!("mutex locked at {} was held for {:?}", self.location, held);
+            }
+        }
+    }
+
+    impl<T> Deref for DebugMutexGuard<'_, T> {
+        type Target = T;
+
+        fn deref(&self) -> &T {
+            &self.guard
+        }
+    }
+
+    impl<T> DerefMut for DebugMutexGuard<'_, T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.guard
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::DebugMutex;
+
+        #[test]
+        fn test_sequential_locks() {
+            let mutex = DebugMutex::new(0);
+
+            *mutex.lock() += 1;
+            *mutex.lock() += 1;
+
+            assert_eq!(*mutex.lock(), 2);
+        }
+
+        #[test]
+        #[should_panic(expected = "already held by this thread")]
+        fn test_double_lock_panics() {
+            let mutex = DebugMutex::new(0);
+
+            let _first = mutex.lock();
+            let _second = mutex.lock();
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, SystemTime};
+
+/// Render a duration in its largest whole unit, e.g. "45 seconds" or "3 days".
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+
+    let (value, unit) = match seconds {
+        0..=59 => (seconds, "second"),
+        60..=3599 => (seconds / 60, "minute"),
+        3600..=86399 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+
+    if value == 1 {
+        format!("{} {}", value, unit)
+    } else {
+        format!("{} {}s", value, unit)
+    }
+}
+
+/// Like `format_duration`, but counting in months once it's been a couple of them.
+pub fn format_long_duration(duration: Duration) -> String {
+    const DAYS_PER_MONTH: u64 = 30;
+
+    let days = duration.as_secs() / 86400;
+    if days < 2 * DAYS_PER_MONTH {
+        return format_duration(duration);
+    }
+
+    format!("{} months", days / DAYS_PER_MONTH)
+}
+
+/// The current time as a millisecond Unix timestamp, as stored in the events table.
+pub fn get_current_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+/// Render a millisecond Unix timestamp relative to now, e.g. "3 days ago".
+pub fn format_relative_time(timestamp_ms: u64) -> String {
+    let elapsed = Duration::from_millis(get_current_timestamp_ms().saturating_sub(timestamp_ms));
+
+    format!("{} ago", format_duration(elapsed))
+}
+
+/// Mix two `0xRRGGBB` colors, from all `from` at `t = 0` to all `to` at `t = 1`.
+pub fn blend_colors(from: u32, to: u32, t: f32) -> u32 {
+    let t = t.clamp(0.0, 1.0);
+
+    [16, 8, 0].iter().fold(0, |color, &shift| {
+        let from = ((from >> shift) & 0xFF) as f32;
+        let to = ((to >> shift) & 0xFF) as f32;
+        let channel = (from + ((to - from) * t)).round() as u32;
+
+        color | (channel << shift)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, format_long_duration};
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0 seconds");
+        assert_eq!(format_duration(Duration::from_secs(1)), "1 second");
+        assert_eq!(format_duration(Duration::from_secs(45)), "45 seconds");
+        assert_eq!(format_duration(Duration::from_secs(60)), "1 minute");
+        assert_eq!(format_duration(Duration::from_secs(3599)), "59 minutes");
+        assert_eq!(format_duration(Duration::from_secs(7200)), "2 hours");
+        assert_eq!(format_duration(Duration::from_secs(86400 * 7)), "7 days");
+    }
+
+    #[test]
+    fn test_format_long_duration() {
+        assert_eq!(format_long_duration(Duration::from_secs(7200)), "2 hours");
+        assert_eq!(
+            format_long_duration(Duration::from_secs(86400 * 59)),
+            "59 days"
+        );
+        assert_eq!(
+            format_long_duration(Duration::from_secs(86400 * 60)),
+            "2 months"
+        );
+        assert_eq!(
+            format_long_duration(Duration::from_secs(86400 * 425)),
+            "14 months"
+        );
+    }
+}